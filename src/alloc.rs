@@ -1,21 +1,212 @@
 use std::{
-    alloc::{AllocError, Allocator, Layout},
-    collections::VecDeque,
+    alloc::{AllocError, Allocator, Layout, System},
+    collections::{BTreeMap, VecDeque},
     fmt::{self, Debug, Display},
+    io::{self, IsTerminal, Write},
     ptr::NonNull,
-    sync::{Arc, RwLock, RwLockReadGuard},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock, RwLock, RwLockReadGuard,
+    },
+    time::{Duration, Instant},
 };
 
+mod anomaly;
+mod backend;
+#[cfg(feature = "backtrace")]
+mod backtrace;
+mod budget;
+mod chains;
+mod channel;
+mod checkpoint;
+mod chrome_trace;
+#[cfg(feature = "color")]
+mod color;
+mod contract;
+mod csv;
+#[cfg(all(feature = "backtrace", target_os = "linux", not(target_arch = "arm")))]
+mod demangle;
+#[cfg(feature = "dhat")]
+mod dhat;
+mod dot;
+mod epoch;
+mod fault;
+mod filter;
+mod format;
+mod heaptrack;
+mod histogram;
+mod hooks;
+mod json;
+mod leak;
+#[cfg(all(feature = "linux", target_os = "linux"))]
+mod linux;
+mod live;
+mod massif;
+mod measure;
+mod patterns;
+mod pause;
+mod poison;
+mod profile;
+#[cfg(feature = "prometheus")]
+mod prometheus;
+mod quarantine;
+mod quota;
+mod record;
+mod redzone;
+mod report;
+mod rle;
+mod sampling;
+#[cfg(feature = "backtrace")]
+mod speedscope;
+mod stats;
+mod stream;
+mod strict;
+mod svg;
+#[cfg(all(feature = "syslog", unix))]
+mod syslog;
+mod thread;
+mod timeline;
+mod top_n;
+mod trace;
+#[cfg(all(feature = "backtrace", target_os = "linux", not(target_arch = "arm")))]
+mod unwind;
+
+pub use anomaly::*;
+#[cfg(feature = "backtrace")]
+pub use backtrace::AllocBacktrace;
+pub use channel::*;
+pub use checkpoint::*;
+#[cfg(feature = "color")]
+pub use color::ColorMode;
+pub use contract::*;
+pub use fault::FaultConfig;
+pub use filter::HistoryFilter;
+pub use format::*;
+pub use histogram::*;
+pub use hooks::{AllocRequest, WatchHandle};
+pub use leak::*;
+pub use live::*;
+pub use measure::*;
+pub use patterns::*;
+pub use pause::RecordingPaused;
+pub use poison::*;
+pub use profile::*;
+pub use redzone::*;
+pub use report::*;
+pub use rle::*;
+pub use stats::*;
+pub use stream::read_stream;
+#[cfg(all(feature = "syslog", unix))]
+pub use syslog::Severity;
+pub use thread::ThreadName;
+pub use timeline::UsageSample;
+pub use trace::Trace;
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Action {
+    /// 記録した順に振られる通し番号
+    pub seq: u64,
     pub addr: Option<NonNull<()>>,
+    /// grow/shrinkで元になったブロックのアドレス
+    pub old_addr: Option<NonNull<()>>,
+    /// 内部の割り当て器が返したスライスの長さ(解放と失敗では0)
+    pub len: usize,
+    /// 内部の割り当て器を呼ばずに失敗させた場合はその理由
+    pub denied: Option<Denial>,
+    /// 最初に記録した操作からの経過時間(プロセス全体で共通)
+    pub timestamp: Duration,
+    /// 操作したときのエポック([`DebugAlloc::tick_epoch`]で進む)
+    pub epoch: u64,
+    /// 操作したスレッドの[`ThreadId::as_u64`](std::thread::ThreadId::as_u64)
+    pub thread_id: u64,
+    /// 操作したスレッドの名前(名前が付いていれば)
+    pub thread_name: Option<ThreadName>,
+    /// 確保した場所の呼び出し履歴(解放と失敗では`None`)
+    #[cfg(feature = "backtrace")]
+    pub backtrace: Option<AllocBacktrace>,
     pub layout: Layout,
     pub kind: Kind,
 }
 
+impl Action {
+    fn new(
+        kind: Kind,
+        layout: Layout,
+        addr: Option<NonNull<()>>,
+        len: usize,
+        old_addr: Option<NonNull<()>>,
+    ) -> Self {
+        let (thread_id, thread_name) = thread::current_thread();
+        Self {
+            seq: 0,
+            addr,
+            old_addr,
+            len,
+            denied: None,
+            timestamp: elapsed(),
+            epoch: 0,
+            thread_id,
+            thread_name,
+            #[cfg(feature = "backtrace")]
+            backtrace: None,
+            layout,
+            kind,
+        }
+    }
+
+    /// 操作した時刻
+    ///
+    /// アプリケーション側で取った`Instant`と比べられる。
+    pub fn instant(&self) -> Instant {
+        epoch_instant() + self.timestamp
+    }
+
+    /// この操作による生存バイト数の増減(失敗した操作は0)
+    pub fn net_bytes(&self) -> i64 {
+        if self.addr.is_none() {
+            return 0;
+        }
+        let size = self.layout.size() as i64;
+        match self.kind {
+            Kind::Allocate | Kind::AllocateZeroed => size,
+            Kind::Deallocate => -size,
+            Kind::Grow(old_layout) | Kind::GrowZeroed(old_layout) | Kind::Shrink(old_layout) => {
+                size - old_layout.size() as i64
+            }
+        }
+    }
+}
+
+/// 経過時間の基準になる時刻(プロセス内で最初に呼ばれた時刻)
+fn epoch_instant() -> Instant {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    *EPOCH.get_or_init(Instant::now)
+}
+
+/// プロセス内で最初に記録してからの経過時間
+fn elapsed() -> Duration {
+    epoch_instant().elapsed()
+}
+
 unsafe impl Send for Action {}
 unsafe impl Sync for Action {}
 
+/// `action`を`formatter`で書き出す(`color`なら種類ごとに色を付ける)
+fn write_action<W: Write>(
+    w: &mut W,
+    action: &Action,
+    formatter: &ActionFormatter,
+    color: bool,
+) -> io::Result<()> {
+    #[cfg(feature = "color")]
+    if color {
+        return color::write_colored(w, action, formatter);
+    }
+    #[cfg(not(feature = "color"))]
+    let _ = color;
+    writeln!(w, "{}", formatter.display(action))
+}
+
 fn fmt_layout(f: &mut fmt::Formatter<'_>, layout: Layout) -> fmt::Result {
     write!(
         f,
@@ -27,36 +218,37 @@ fn fmt_layout(f: &mut fmt::Formatter<'_>, layout: Layout) -> fmt::Result {
 
 impl Display for Action {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self.kind {
-            Kind::Allocate => write!(f, "allocate"),
-            Kind::Deallocate => write!(f, "deallocate"),
-            Kind::AllocateZeroed => write!(f, "allocate_zeroed"),
-            Kind::Grow(layout) => {
-                write!(f, "grow\n\told_layout: ")?;
-                fmt_layout(f, layout)
-            }
-            Kind::GrowZeroed(layout) => {
-                write!(f, "grow_zeroed\n\told_layout: ")?;
-                fmt_layout(f, layout)
-            }
-            Kind::Shrink(layout) => {
-                write!(f, "shrink\n\told_layout: ")?;
-                fmt_layout(f, layout)
-            }
-        }?;
-        match self.kind {
-            Kind::Allocate | Kind::AllocateZeroed | Kind::Deallocate => {
-                write!(f, "\n\tlayout: ")
-            }
-            Kind::Grow(_) | Kind::GrowZeroed(_) | Kind::Shrink(_) => {
-                write!(f, "\n\tnew_layout: ")
-            }
-        }?;
-        fmt_layout(f, self.layout)?;
-        if let Some(addr) = self.addr {
-            writeln!(f, "\n\taddress: {:p}", addr)
+        if f.alternate() {
+            format::fmt_compact(self, f)
         } else {
-            writeln!(f, "\n\taddress: Allocation Error")
+            ActionFormatter::DEFAULT.fmt(self, f)
+        }
+    }
+}
+
+/// 内部の割り当て器を呼ばずに確保を失敗させた理由
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Denial {
+    /// [`DebugAlloc::set_admission_hook`]のコールバックが拒否した
+    Hook,
+    /// [`DebugAlloc::set_fault_config`]の設定で失敗させた
+    Fault,
+    /// [`DebugAlloc::set_byte_budget`]の上限を超える
+    Budget,
+    /// grow/shrinkの事前条件を満たさない([`AllocAnomaly::BrokenContract`])
+    Contract,
+    /// [`DebugAlloc::set_byte_quota`]の累計の上限に達した
+    Quota,
+}
+
+impl Display for Denial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Denial::Hook => write!(f, "admission hook"),
+            Denial::Fault => write!(f, "fault injection"),
+            Denial::Budget => write!(f, "byte budget"),
+            Denial::Contract => write!(f, "broken contract"),
+            Denial::Quota => write!(f, "byte quota"),
         }
     }
 }
@@ -71,52 +263,432 @@ pub enum Kind {
     Shrink(Layout),
 }
 
-#[derive(Clone, Debug)]
+impl Kind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kind::Allocate => "allocate",
+            Kind::Deallocate => "deallocate",
+            Kind::AllocateZeroed => "allocate_zeroed",
+            Kind::Grow(_) => "grow",
+            Kind::GrowZeroed(_) => "grow_zeroed",
+            Kind::Shrink(_) => "shrink",
+        }
+    }
+
+    /// grow/shrinkの場合は変更前のレイアウトを返す
+    pub fn old_layout(&self) -> Option<Layout> {
+        match *self {
+            Kind::Grow(layout) | Kind::GrowZeroed(layout) | Kind::Shrink(layout) => Some(layout),
+            Kind::Allocate | Kind::Deallocate | Kind::AllocateZeroed => None,
+        }
+    }
+}
+
+#[derive(Debug)]
 pub struct DebugAlloc<A> {
     alloc: A,
-    history: Arc<RwLock<VecDeque<Action>>>,
+    shared: Arc<Shared, System>,
+}
+
+impl<A: Clone> Clone for DebugAlloc<A> {
+    fn clone(&self) -> Self {
+        self.shared.handles.fetch_add(1, Ordering::Relaxed);
+        Self {
+            alloc: self.alloc.clone(),
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+/// 記録のための内部のデータ構造は、`System`から直接確保する
+///
+/// グローバルアロケータとして使っても、記録のための確保が自分自身を通らず、
+/// 履歴に現れたり再帰したりしない。
+#[derive(Debug)]
+struct Shared {
+    history: RwLock<VecDeque<Action, System>>,
+    tracker: RwLock<Tracker>,
+    /// この記録を共有している[`DebugAlloc`]の数
+    handles: AtomicUsize,
+    /// 生成した時刻
+    created: Instant,
+    anomalies: RwLock<Vec<AllocAnomaly, System>>,
+    sampler: Mutex<Option<sampling::AdaptiveSampler>>,
+    thread_confined: AtomicBool,
+    /// [`DebugAlloc::set_strict`]の設定
+    strict: AtomicBool,
+    /// [`DebugAlloc::set_color`]の設定
+    #[cfg(feature = "color")]
+    color: std::sync::atomic::AtomicU8,
+    /// [`DebugAlloc::set_human_sizes`]の設定
+    human_sizes: AtomicBool,
+    /// 異常を検出したらpanicする
+    panic_on_anomaly: AtomicBool,
+    /// [`DebugAlloc::set_skip_double_free`]の設定
+    skip_double_free: AtomicBool,
+    /// [`DebugAlloc::set_sample_rate`]の設定
+    sample_every: AtomicU32,
+    /// [`DebugAlloc::set_notify_sample`]の設定
+    notify_every: AtomicU32,
+    top_n: Mutex<Option<top_n::TopN>>,
+    /// 通常の履歴に記録しない
+    history_disabled: AtomicBool,
+    /// [`DebugAlloc::set_recording`]の設定
+    recording: AtomicBool,
+    /// 履歴に残す操作の最大数
+    history_limit: Option<usize>,
+    backend: backend::HistoryBackend,
+    admission_hook: RwLock<Option<hooks::AdmissionHook>>,
+    fault: Mutex<Option<fault::FaultState>>,
+    /// 解放するメモリを埋める
+    poison_freed: AtomicBool,
+    quarantine: Mutex<quarantine::Quarantine>,
+    /// 各ブロックの前後に置くレッドゾーンのバイト数(0なら置かない)
+    red_zone: usize,
+    /// 生存バイト数の上限(`u64::MAX`なら上限なし)
+    byte_budget: AtomicU64,
+    /// 要求されたバイト数の累計の上限(`u64::MAX`なら上限なし)
+    byte_quota: AtomicU64,
+    /// 上限に数えた累計
+    quota_used: AtomicU64,
+    /// 累計の上限に達した
+    quota_exhausted: AtomicBool,
+    watches: RwLock<hooks::Watches>,
+    next_watch_id: AtomicU64,
+    /// 今のエポック
+    epoch: AtomicU64,
+    checkpoints: Mutex<Vec<checkpoint::Checkpoint>>,
+    /// [`DebugAlloc::set_backtrace_depth`]の設定
+    #[cfg(feature = "backtrace")]
+    backtrace_depth: AtomicUsize,
+    #[cfg(all(feature = "syslog", unix))]
+    syslog: Option<syslog::SyslogSink>,
+}
+
+/// 二重解放を調べるために覚えておく、解放されたアドレスの最大数
+const FREED_LIMIT: usize = 1 << 14;
+
+/// 履歴の削除に影響されない集計
+#[derive(Debug)]
+struct Tracker {
+    /// 生存中の確保(アドレス → 最後にそのブロックを返した操作)
+    live: BTreeMap<usize, Action, System>,
+    /// `live`の合計バイト数
+    live_bytes: u64,
+    /// `live_bytes`の最大値
+    peak_bytes: u64,
+    /// 累計の集計(`live_*`は使わない)
+    stats: AllocStats,
+    /// 次に振る通し番号
+    next_seq: u64,
+    /// [`DebugAlloc::begin_measurement`]を呼んだときの通し番号
+    measurement_start: Option<u64>,
+    /// アドレスごとの世代(そのアドレスに新しくブロックが置かれた回数)
+    generations: BTreeMap<usize, u64, System>,
+    /// 解放されてから新しく確保されていないアドレス(アドレス → 解放した操作の通し番号)
+    ///
+    /// 直近の[`FREED_LIMIT`]個の解放だけを覚えておく。
+    freed: BTreeMap<usize, u64, System>,
+    /// `freed`に加えた順の`(アドレス, 通し番号)`(古いものから`freed`を削るのに使う)
+    freed_order: VecDeque<(usize, u64), System>,
+    /// [`DebugAlloc::set_record_on_step`]の設定
+    record_step: Option<sampling::RecordStep>,
+    /// 破棄したときに生存中の確保が残っていた場合の扱い
+    leak_check: LeakCheck,
+    /// 実行中の[`DebugAlloc::measure`]ごとの`(識別子, 生存バイト数の最大値)`
+    measure_peaks: Vec<(u64, u64), System>,
+    next_measure_id: u64,
+}
+
+impl Default for Shared {
+    fn default() -> Self {
+        Self {
+            history: RwLock::new(VecDeque::new_in(System)),
+            tracker: Default::default(),
+            handles: AtomicUsize::new(1),
+            created: Instant::now(),
+            anomalies: RwLock::new(Vec::new_in(System)),
+            sampler: Default::default(),
+            thread_confined: Default::default(),
+            strict: Default::default(),
+            #[cfg(feature = "color")]
+            color: Default::default(),
+            human_sizes: Default::default(),
+            panic_on_anomaly: Default::default(),
+            skip_double_free: Default::default(),
+            sample_every: Default::default(),
+            notify_every: Default::default(),
+            top_n: Default::default(),
+            history_disabled: Default::default(),
+            recording: AtomicBool::new(true),
+            history_limit: None,
+            backend: Default::default(),
+            admission_hook: Default::default(),
+            fault: Default::default(),
+            byte_budget: u64::MAX.into(),
+            byte_quota: u64::MAX.into(),
+            quota_used: Default::default(),
+            quota_exhausted: Default::default(),
+            poison_freed: Default::default(),
+            quarantine: Default::default(),
+            red_zone: 0,
+            watches: RwLock::new(hooks::empty_watches()),
+            next_watch_id: Default::default(),
+            epoch: Default::default(),
+            checkpoints: Default::default(),
+            #[cfg(feature = "backtrace")]
+            backtrace_depth: backtrace::DEFAULT_DEPTH.into(),
+            #[cfg(all(feature = "syslog", unix))]
+            syslog: Default::default(),
+        }
+    }
+}
+
+impl Default for Tracker {
+    fn default() -> Self {
+        Self {
+            live: BTreeMap::new_in(System),
+            live_bytes: 0,
+            peak_bytes: 0,
+            stats: Default::default(),
+            next_seq: 0,
+            measurement_start: None,
+            generations: BTreeMap::new_in(System),
+            freed: BTreeMap::new_in(System),
+            freed_order: VecDeque::new_in(System),
+            record_step: None,
+            leak_check: Default::default(),
+            measure_peaks: Vec::new_in(System),
+            next_measure_id: 0,
+        }
+    }
+}
+
+impl Tracker {
+    fn insert(&mut self, action: &Action) {
+        let addr = action.addr.unwrap().as_ptr() as usize;
+        self.live_bytes += action.layout.size() as u64;
+        if let Some(prev) = self.live.insert(addr, action.clone()) {
+            self.live_bytes -= prev.layout.size() as u64;
+        }
+        self.peak_bytes = self.peak_bytes.max(self.live_bytes);
+        for (_, peak) in &mut self.measure_peaks {
+            *peak = (*peak).max(self.live_bytes);
+        }
+    }
+
+    fn remove(&mut self, addr: usize) -> Option<Action> {
+        let prev = self.live.remove(&addr)?;
+        self.live_bytes -= prev.layout.size() as u64;
+        Some(prev)
+    }
+
+    /// `addr`を通し番号`seq`の操作で解放されたものとして覚える
+    fn mark_freed(&mut self, addr: usize, seq: u64) {
+        self.freed.insert(addr, seq);
+        self.freed_order.push_back((addr, seq));
+        while self.freed_order.len() > FREED_LIMIT {
+            let Some((addr, seq)) = self.freed_order.pop_front() else {
+                break;
+            };
+            // その後に同じアドレスが解放し直されていれば残す
+            if self.freed.get(&addr) == Some(&seq) {
+                self.freed.remove(&addr);
+            }
+        }
+    }
+
+    /// 解放や移動で生存中の一覧から外れたブロックの記録を返す
+    fn update(&mut self, action: &Action) -> Option<Action> {
+        let mut released = None;
+        match (action.kind, action.addr) {
+            (Kind::Deallocate, Some(addr)) => {
+                released = self.remove(addr.as_ptr() as usize);
+                if released.is_some() {
+                    self.mark_freed(addr.as_ptr() as usize, action.seq);
+                }
+            }
+            (_, Some(addr)) => {
+                if let Some(old_addr) = action.old_addr {
+                    released = self.remove(old_addr.as_ptr() as usize);
+                    if released.is_some() && old_addr != addr {
+                        self.mark_freed(old_addr.as_ptr() as usize, action.seq);
+                    }
+                }
+                // サイズ0の確保は同じダングリングポインタを返しうるので追跡しない
+                if action.layout.size() != 0 {
+                    self.freed.remove(&(addr.as_ptr() as usize));
+                    if action.old_addr != Some(addr) {
+                        *self.generations.entry(addr.as_ptr() as usize).or_insert(0) += 1;
+                    }
+                    self.insert(action);
+                }
+            }
+            (_, None) => {}
+        }
+        released
+    }
 }
 
 impl<A> DebugAlloc<A> {
     pub fn new(alloc: A) -> Self {
         Self {
             alloc,
-            history: Arc::new(RwLock::new(VecDeque::new())),
+            shared: Arc::new_in(Shared::default(), System),
         }
     }
 
-    pub fn history(&self) -> RwLockReadGuard<'_, VecDeque<Action>> {
-        self.history.read().unwrap()
+    /// 直近の`n`個の操作だけを履歴に残す
+    ///
+    /// 古い操作は記録するたびに自動で削除されるので、長時間動かしても履歴が増え続けない。
+    /// 集計や生存中の確保の一覧は削除の影響を受けない。
+    pub fn with_capacity_limit(alloc: A, n: usize) -> Self {
+        Self {
+            alloc,
+            shared: Arc::new_in(
+                Shared {
+                    history: RwLock::new(VecDeque::with_capacity_in(n, System)),
+                    history_limit: Some(n),
+                    ..Default::default()
+                },
+                System,
+            ),
+        }
+    }
+
+    /// 保存した操作を記録済みの履歴として持つ割り当て器を作る
+    ///
+    /// 操作は通し番号を保ったまま、並んだ順に履歴と集計に加える。異常の検出や
+    /// 監視のコールバックは働かない。ファイルから読み込んだ履歴を、集計や書き出しの
+    /// メソッドで調べるのに使う。以降の確保には`alloc`を使う。
+    pub fn from_actions(alloc: A, actions: impl IntoIterator<Item = Action>) -> Self {
+        let this = Self::new(alloc);
+        {
+            let mut tracker = this.shared.tracker.write().unwrap();
+            let mut history = this.shared.history.write().unwrap();
+            for action in actions {
+                tracker.next_seq = tracker.next_seq.max(action.seq + 1);
+                tracker.stats.count(&action);
+                tracker.update(&action);
+                history.push_back(action);
+            }
+        }
+        this
+    }
+
+    pub fn history(&self) -> RwLockReadGuard<'_, VecDeque<Action, System>> {
+        self.flush_pending();
+        self.shared.history.read().unwrap()
+    }
+
+    /// 履歴の写しを返す
+    ///
+    /// [`DebugAlloc::history`]のガードを持っている間は全スレッドの記録が止まるが、
+    /// こちらはロックの中で複製するだけなので、返した値は時間をかけて調べてよい。
+    pub fn snapshot(&self) -> Vec<Action> {
+        let history = self.history();
+        let mut snapshot = Vec::with_capacity(history.len());
+        snapshot.extend(history.iter().cloned());
+        snapshot
+    }
+
+    /// 直近の`n`個の履歴の写しを返す(古い順)
+    pub fn snapshot_last_n(&self, n: usize) -> Vec<Action> {
+        let history = self.history();
+        let start = history.len().saturating_sub(n);
+        let mut snapshot = Vec::with_capacity(history.len() - start);
+        snapshot.extend(history.range(start..).cloned());
+        snapshot
     }
 
     pub fn poisoned(&self) -> bool {
-        self.history.is_poisoned()
+        self.shared.history.is_poisoned()
     }
 
     /// 全ての履歴を表示する
     pub fn dump_all_history(&self) {
+        let color = self.use_color(io::stdout().is_terminal());
+        self.dump_all_with(&mut io::stdout(), color)
+            .expect("failed printing to stdout");
+    }
+
+    /// 全ての履歴を[`DebugAlloc::dump_all_history`]と同じ形式で`w`に書き出す
+    pub fn dump_all_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.dump_all_with(w, self.use_color(false))
+    }
+
+    fn dump_all_with<W: Write>(&self, w: &mut W, color: bool) -> io::Result<()> {
+        let formatter = self.formatter();
         let history = self.history();
         for action in history.iter().rev() {
-            println!("{action}");
+            write_action(w, action, &formatter, color)?;
         }
+        Ok(())
+    }
+
+    /// 全ての履歴を`formatter`の設定で`w`に書き出す
+    ///
+    /// 並び順は[`DebugAlloc::dump_all_history`]と同じ。[`DebugAlloc::set_human_sizes`]の
+    /// 設定は使わず、`formatter`の[`ActionFormatter::human_sizes`]に従う。
+    pub fn dump_all_to_with<W: Write>(
+        &self,
+        w: &mut W,
+        formatter: &ActionFormatter,
+    ) -> io::Result<()> {
+        let history = self.history();
+        for action in history.iter().rev() {
+            writeln!(w, "{}", formatter.display(action))?;
+        }
+        Ok(())
     }
 
     /// 直近の`n`個の履歴を表示する
     pub fn dump_n(&self, n: usize) {
+        let color = self.use_color(io::stdout().is_terminal());
+        self.dump_n_with(&mut io::stdout(), n, color)
+            .expect("failed printing to stdout");
+    }
+
+    /// 直近の`n`個の履歴を[`DebugAlloc::dump_n`]と同じ形式で`w`に書き出す
+    pub fn dump_n_to<W: Write>(&self, w: &mut W, n: usize) -> io::Result<()> {
+        self.dump_n_with(w, n, self.use_color(false))
+    }
+
+    fn dump_n_with<W: Write>(&self, w: &mut W, n: usize, color: bool) -> io::Result<()> {
+        let formatter = self.formatter();
         let history = self.history();
         for action in history.iter().rev().take(n) {
-            println!("{action}");
+            write_action(w, action, &formatter, color)?;
+        }
+        Ok(())
+    }
+
+    /// 書き出し先が端末かどうか(`terminal`)から、色を付けるかどうかを決める
+    fn use_color(&self, terminal: bool) -> bool {
+        #[cfg(feature = "color")]
+        return match self.color() {
+            ColorMode::Never => false,
+            ColorMode::Auto => terminal,
+            ColorMode::Always => true,
+        };
+        #[cfg(not(feature = "color"))]
+        {
+            let _ = terminal;
+            false
         }
     }
 
     /// 履歴をすべて削除する
+    ///
+    /// 累計や生存中の確保の一覧には影響しない。
+    /// 他のリセットとの違いは[`DebugAlloc::reset_counters`]を参照。
     pub fn clear_history(&self) {
-        self.history.write().unwrap().clear();
+        self.history_mut().clear();
     }
 
     /// 履歴を古いものから`n`個削除する
     pub fn pop_history_n(&self, n: usize) {
-        let mut wlock = self.history.write().unwrap();
+        let mut wlock = self.history_mut();
         if wlock.len() < n {
             wlock.clear();
         } else {
@@ -127,53 +699,193 @@ impl<A> DebugAlloc<A> {
 
     /// 直近の`n`個の履歴を残してそれ以外を削除する
     pub fn shrink_history(&self, n: usize) {
-        let mut wlock = self.history.write().unwrap();
+        let mut wlock = self.history_mut();
         let len = wlock.len();
         if len > n {
             let new = wlock.split_off(len - n);
             *wlock = new;
         }
     }
+
+    /// 通し番号が`seq`の操作を履歴から探す
+    ///
+    /// 履歴を削除しても通し番号は変わらないので、特定の操作を指すのに使える。
+    /// 削除された操作や、間引きで履歴に入らなかった操作は`None`
+    pub fn action_by_seq(&self, seq: u64) -> Option<Action> {
+        let history = self.history();
+        let i = history.partition_point(|action| action.seq < seq);
+        history.get(i).filter(|action| action.seq == seq).cloned()
+    }
+
+    /// 生存中の確保の合計バイト数
+    pub fn live_bytes(&self) -> u64 {
+        self.shared.tracker.read().unwrap().live_bytes
+    }
+
+    /// 確保したスレッドと別のスレッドで解放されたときに
+    /// [`AllocAnomaly::CrossThreadFree`]を記録するかどうかを設定する
+    ///
+    /// 記録するだけで、解放自体はそのまま行う。
+    pub fn set_thread_confined(&self, confined: bool) {
+        self.shared
+            .thread_confined
+            .store(confined, Ordering::Relaxed);
+    }
+
+    /// 確保を伴う操作を許可されていれば`f`で行い、記録する
+    fn handle(
+        &self,
+        kind: Kind,
+        layout: Layout,
+        old_ptr: Option<NonNull<u8>>,
+        f: impl FnOnce() -> Result<NonNull<[u8]>, AllocError>,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if let (Some(ptr), Some(old_layout)) = (old_ptr, kind.old_layout()) {
+            self.check_layout(ptr.cast(), old_layout);
+        }
+        let violation = old_ptr.and_then(|ptr| self.check_contract(kind, layout, ptr));
+        let denied = match violation {
+            Some(_) => Some(Denial::Contract),
+            None => self.admit(kind, layout),
+        };
+        let result = match denied {
+            Some(_) => Err(AllocError),
+            None => f(),
+        };
+        if let Ok(ptr) = result {
+            self.audit(kind, layout, ptr);
+        }
+        let mut action = Action::new(
+            kind,
+            layout,
+            result.ok().map(NonNull::cast),
+            result.map_or(0, |ptr| ptr.len()),
+            old_ptr.map(NonNull::cast),
+        );
+        action.denied = denied;
+        #[cfg(feature = "backtrace")]
+        if action.addr.is_some() {
+            action.backtrace = self.capture_backtrace();
+        }
+        match violation {
+            Some(violation) => {
+                let mut context = action.clone();
+                context.seq = self.record(action);
+                context.epoch = self.epoch();
+                self.report_anomaly(AllocAnomaly::BrokenContract {
+                    violation,
+                    action: context,
+                });
+            }
+            None => {
+                self.record(action);
+            }
+        }
+        result
+    }
+
+    /// 操作を記録し、振った通し番号を返す
+    fn record(&self, mut action: Action) -> u64 {
+        action.epoch = self.shared.epoch.load(Ordering::Relaxed);
+        match action.addr {
+            Some(addr) if !(addr.as_ptr() as usize).is_multiple_of(action.layout.align()) => {
+                self.report_anomaly(AllocAnomaly::UnderAligned {
+                    addr,
+                    layout: action.layout,
+                });
+            }
+            #[cfg(all(feature = "syslog", unix))]
+            None => {
+                if let Some(syslog) = &self.shared.syslog {
+                    syslog.failure(&action);
+                }
+            }
+            _ => {}
+        }
+        let (released, crossed_step) = match self.shared.tracker.write() {
+            Ok(mut tracker) => {
+                action.seq = tracker.next_seq;
+                tracker.next_seq += 1;
+                tracker.stats.count(&action);
+                let released = tracker.update(&action);
+                let live_bytes = tracker.live_bytes;
+                let crossed_step = tracker
+                    .record_step
+                    .as_mut()
+                    .is_none_or(|step| step.crossed(live_bytes));
+                (released, crossed_step)
+            }
+            Err(_) => (None, true),
+        };
+        if let (Kind::Deallocate, Some(prev)) = (action.kind, released) {
+            if prev.thread_id != action.thread_id
+                && self.shared.thread_confined.load(Ordering::Relaxed)
+            {
+                self.report_anomaly(AllocAnomaly::CrossThreadFree {
+                    alloc_thread: prev.thread_id,
+                    free_thread: action.thread_id,
+                    addr: prev.addr.unwrap(),
+                });
+            }
+        }
+        self.notify_watches(&action, self.should_notify(action.seq));
+        if let Ok(mut top_n) = self.shared.top_n.lock() {
+            if let Some(top_n) = &mut *top_n {
+                top_n.push(&action);
+            }
+        }
+        if !crossed_step
+            || self.shared.history_disabled.load(Ordering::Relaxed)
+            || !self.shared.recording.load(Ordering::Relaxed)
+            || !self.sample(action.seq)
+        {
+            return action.seq;
+        }
+        let seq = action.seq;
+        self.store(action);
+        seq
+    }
 }
 
 unsafe impl<A: Allocator> Allocator for DebugAlloc<A> {
     fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
-        let result = self.alloc.allocate(layout);
-        if let Ok(mut wlock) = self.history.write() {
-            wlock.push_back(
-                Action {
-                    addr: result.ok().map(|ptr| ptr.cast()),
-                    layout,
-                    kind: Kind::Allocate,
-                }
-            );
-        }
-        result
+        self.handle(Kind::Allocate, layout, None, || {
+            self.inner_allocate(layout, false)
+        })
     }
 
     unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
-        self.alloc.deallocate(ptr, layout);
-        if let Ok(mut wlock) = self.history.write() {
-            wlock.push_back(Action {
-                addr: Some(ptr.cast()),
-                layout,
-                kind: Kind::Deallocate,
+        if let Some(prior_seq) = self.double_free(ptr.cast()) {
+            self.report_anomaly(AllocAnomaly::DoubleFree {
+                addr: ptr.cast(),
+                prior_seq,
             });
+            if self.shared.skip_double_free.load(Ordering::Relaxed) {
+                return;
+            }
+        }
+        self.check_layout(ptr.cast(), layout);
+        self.poison(ptr, layout.size());
+        let held = self.hold_freed(ptr, layout);
+        if !held {
+            self.inner_deallocate(ptr, layout);
+        }
+        let seq = self.record(Action::new(
+            Kind::Deallocate,
+            layout,
+            Some(ptr.cast()),
+            0,
+            None,
+        ));
+        if held {
+            self.enqueue_quarantine(ptr, layout, seq);
         }
     }
 
     fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
-        let result = self.alloc.allocate_zeroed(layout);
-        if let Ok(mut wlock) = self.history.write() {
-            wlock.push_back(
-                Action {
-                    addr: result.ok().map(|ptr| ptr.cast()),
-                    layout,
-                    kind: Kind::AllocateZeroed,
-                }
-            );
-        }
-        result
+        self.handle(Kind::AllocateZeroed, layout, None, || {
+            self.inner_allocate(layout, true)
+        })
     }
 
     unsafe fn grow(
@@ -182,16 +894,9 @@ unsafe impl<A: Allocator> Allocator for DebugAlloc<A> {
         old_layout: Layout,
         new_layout: Layout,
     ) -> Result<NonNull<[u8]>, AllocError> {
-        let result = self.alloc.grow(ptr, old_layout, new_layout);
-        if let Ok(mut wlock) = self.history.write() {
-            wlock.push_back(Action {
-                addr: result.ok().map(|ptr| ptr.cast()),
-                layout: new_layout,
-                kind: Kind::Grow(old_layout),
-            }
-            );
-        }
-        result
+        self.handle(Kind::Grow(old_layout), new_layout, Some(ptr), || {
+            self.inner_resize(ptr, old_layout, new_layout, false)
+        })
     }
 
     unsafe fn grow_zeroed(
@@ -200,17 +905,9 @@ unsafe impl<A: Allocator> Allocator for DebugAlloc<A> {
         old_layout: Layout,
         new_layout: Layout,
     ) -> Result<NonNull<[u8]>, AllocError> {
-        let result = self.alloc.grow_zeroed(ptr, old_layout, new_layout);
-        if let Ok(mut wlock) = self.history.write() {
-            wlock.push_back(
-                Action {
-                    addr: result.ok().map(|ptr| ptr.cast()),
-                    layout: new_layout,
-                    kind: Kind::GrowZeroed(old_layout),
-                }
-            );
-        }
-        result
+        self.handle(Kind::GrowZeroed(old_layout), new_layout, Some(ptr), || {
+            self.inner_resize(ptr, old_layout, new_layout, true)
+        })
     }
 
     unsafe fn shrink(
@@ -219,16 +916,16 @@ unsafe impl<A: Allocator> Allocator for DebugAlloc<A> {
         old_layout: Layout,
         new_layout: Layout,
     ) -> Result<NonNull<[u8]>, AllocError> {
-        let result = self.alloc.shrink(ptr, old_layout, new_layout);
-        if let Ok(mut wlock) = self.history.write() {
-            wlock.push_back(
-                Action {
-                    addr: result.ok().map(|ptr| ptr.cast()),
-                    layout: new_layout,
-                    kind: Kind::Shrink(old_layout),
-                }
-            );
-        }
-        result
+        self.handle(Kind::Shrink(old_layout), new_layout, Some(ptr), || {
+            // 失敗したら呼び出し側のブロックを元に戻す
+            let tail = ptr.add(new_layout.size());
+            let saved =
+                self.poison_saving(tail, old_layout.size().saturating_sub(new_layout.size()));
+            let result = self.inner_resize(ptr, old_layout, new_layout, false);
+            if result.is_err() {
+                Self::unpoison(tail, &saved);
+            }
+            result
+        })
     }
 }
@@ -0,0 +1,452 @@
+use std::{
+    alloc::Layout,
+    fmt::{self, Display},
+    ptr::NonNull,
+    sync::atomic::Ordering,
+};
+
+use super::{fmt_layout, Action, ActionFormatter, ContractViolation, DebugAlloc};
+
+/// 割り当て器の使い方や振る舞いの異常
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AllocAnomaly {
+    /// 内部の割り当て器が要求されたアラインメントを満たさないアドレスを返した
+    UnderAligned { addr: NonNull<()>, layout: Layout },
+    /// 基準とした時点から、そのアドレスに別のブロックが置き直された
+    StaleGeneration {
+        addr: NonNull<()>,
+        expected: u64,
+        actual: u64,
+    },
+    /// 確保したスレッドとは別のスレッドで解放された
+    CrossThreadFree {
+        alloc_thread: u64,
+        free_thread: u64,
+        addr: NonNull<()>,
+    },
+    /// 既に解放されたブロックが、そのアドレスに新しく確保されないまま再び解放された
+    ///
+    /// [`DebugAlloc::set_skip_double_free`]を設定していなければ、解放はそのまま
+    /// 内部の割り当て器に渡す。
+    DoubleFree {
+        addr: NonNull<()>,
+        /// 前回の解放の通し番号
+        prior_seq: u64,
+    },
+    /// 解放やgrow/shrinkに渡されたレイアウトが、そのブロックを確保したときと違う
+    LayoutMismatch {
+        addr: NonNull<()>,
+        /// ブロックを確保(またはサイズ変更)したときのレイアウト
+        expected: Layout,
+        /// 渡されたレイアウト
+        actual: Layout,
+    },
+    /// [`DebugAlloc::with_red_zone`]で置いたレッドゾーンが書き換えられていた
+    RedZoneCorrupted {
+        addr: NonNull<()>,
+        layout: Layout,
+        /// 書き換えられた最初のバイトの、ブロックの先頭からの位置(負なら前のレッドゾーン)
+        offset: isize,
+        /// ブロックを確保(またはサイズ変更)した操作の通し番号
+        alloc_seq: Option<u64>,
+    },
+    /// [`DebugAlloc::set_quarantine`]で保留していた解放済みのブロックが書き換えられていた
+    UseAfterFree {
+        addr: NonNull<()>,
+        layout: Layout,
+        /// 書き換えられた最初のバイトの、ブロックの先頭からの位置
+        offset: usize,
+        /// ブロックを解放した操作の通し番号
+        free_seq: u64,
+    },
+    /// `allocate_zeroed`/`grow_zeroed`が返した領域が0で埋められていなかった
+    ///
+    /// [`DebugAlloc::set_strict`]が有効なときだけ調べる。
+    NotZeroed {
+        addr: NonNull<()>,
+        layout: Layout,
+        /// 0でなかった最初のバイトの、ブロックの先頭からの位置
+        offset: usize,
+    },
+    /// 内部の割り当て器が返したスライスの長さが要求したサイズより短い
+    ///
+    /// [`DebugAlloc::set_strict`]が有効なときだけ調べる。
+    ShortLength {
+        addr: NonNull<()>,
+        layout: Layout,
+        /// 返されたスライスの長さ
+        len: usize,
+    },
+    /// grow/shrinkが事前条件を満たさない引数で呼ばれた
+    ///
+    /// 内部の割り当て器には渡さず、[`Denial::Contract`](super::Denial::Contract)の付いた
+    /// 失敗として記録する。
+    BrokenContract {
+        violation: ContractViolation,
+        /// 記録した操作
+        action: Action,
+    },
+}
+
+unsafe impl Send for AllocAnomaly {}
+unsafe impl Sync for AllocAnomaly {}
+
+impl Display for AllocAnomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AllocAnomaly::UnderAligned { addr, layout } => {
+                write!(f, "under-aligned\n\tlayout: ")?;
+                fmt_layout(f, *layout)?;
+                writeln!(f, "\n\taddress: {:p}", *addr)
+            }
+            AllocAnomaly::StaleGeneration {
+                addr,
+                expected,
+                actual,
+            } => writeln!(
+                f,
+                "stale generation\n\texpected: {expected}\n\tactual: {actual}\n\taddress: {:p}",
+                *addr
+            ),
+            AllocAnomaly::CrossThreadFree {
+                alloc_thread,
+                free_thread,
+                addr,
+            } => writeln!(
+                f,
+                "cross-thread free\n\talloc_thread: {alloc_thread}\n\tfree_thread: {free_thread}\n\taddress: {:p}",
+                *addr
+            ),
+            AllocAnomaly::DoubleFree { addr, prior_seq } => writeln!(
+                f,
+                "double free\n\tprior deallocation: #{prior_seq}\n\taddress: {:p}",
+                *addr
+            ),
+            AllocAnomaly::LayoutMismatch {
+                addr,
+                expected,
+                actual,
+            } => {
+                write!(f, "layout mismatch\n\texpected: ")?;
+                fmt_layout(f, *expected)?;
+                write!(f, "\n\tactual: ")?;
+                fmt_layout(f, *actual)?;
+                writeln!(f, "\n\taddress: {:p}", *addr)
+            }
+            AllocAnomaly::RedZoneCorrupted {
+                addr,
+                layout,
+                offset,
+                alloc_seq,
+            } => {
+                write!(f, "red zone corrupted\n\tlayout: ")?;
+                fmt_layout(f, *layout)?;
+                write!(f, "\n\toffset: {offset}")?;
+                if let Some(seq) = alloc_seq {
+                    write!(f, "\n\tallocated by: #{seq}")?;
+                }
+                writeln!(f, "\n\taddress: {:p}", *addr)
+            }
+            AllocAnomaly::UseAfterFree {
+                addr,
+                layout,
+                offset,
+                free_seq,
+            } => {
+                write!(f, "use after free\n\tlayout: ")?;
+                fmt_layout(f, *layout)?;
+                writeln!(
+                    f,
+                    "\n\toffset: {offset}\n\tfreed by: #{free_seq}\n\taddress: {:p}",
+                    *addr
+                )
+            }
+            AllocAnomaly::NotZeroed {
+                addr,
+                layout,
+                offset,
+            } => {
+                write!(f, "not zeroed\n\tlayout: ")?;
+                fmt_layout(f, *layout)?;
+                writeln!(f, "\n\toffset: {offset}\n\taddress: {:p}", *addr)
+            }
+            AllocAnomaly::ShortLength { addr, layout, len } => {
+                write!(f, "short length\n\tlayout: ")?;
+                fmt_layout(f, *layout)?;
+                writeln!(f, "\n\tlen: {len}\n\taddress: {:p}", *addr)
+            }
+            AllocAnomaly::BrokenContract { violation, action } => {
+                write!(f, "broken contract\n\tviolation: {violation}\n\taction: ")?;
+                ActionFormatter::new().indent("\t\t").fmt(action, f)
+            }
+        }
+    }
+}
+
+impl<A> DebugAlloc<A> {
+    /// 検出された異常の一覧を返す
+    pub fn anomalies(&self) -> Vec<AllocAnomaly> {
+        self.shared.anomalies.read().unwrap().to_vec()
+    }
+
+    /// 検出された異常をすべて削除する
+    pub fn clear_anomalies(&self) {
+        self.shared.anomalies.write().unwrap().clear();
+    }
+
+    /// `ptr`に今置かれているブロックの世代
+    ///
+    /// 世代はそのアドレスに新しくブロックが置かれるたびに増える(その場での`grow`/`shrink`では
+    /// 増えない)。一度もブロックが置かれていなければ`None`
+    pub fn address_generation<T: ?Sized>(&self, ptr: *const T) -> Option<u64> {
+        let tracker = self.shared.tracker.read().unwrap();
+        tracker
+            .generations
+            .get(&(ptr as *const () as usize))
+            .copied()
+    }
+
+    /// `ptr`の世代が`expected`から進んでいないか調べる
+    ///
+    /// 進んでいれば(解放後に同じアドレスが再利用されていれば)
+    /// [`AllocAnomaly::StaleGeneration`]を記録して`false`を返す。
+    pub fn check_generation<T: ?Sized>(&self, ptr: *const T, expected: u64) -> bool {
+        let actual = self.address_generation(ptr).unwrap_or(0);
+        if actual == expected {
+            return true;
+        }
+        if let Some(addr) = NonNull::new(ptr as *mut ()) {
+            self.report_anomaly(AllocAnomaly::StaleGeneration {
+                addr,
+                expected,
+                actual,
+            });
+        }
+        false
+    }
+
+    /// `addr`が解放済みで、新しく確保されていなければ前回の解放の通し番号を返す
+    pub(super) fn double_free(&self, addr: NonNull<()>) -> Option<u64> {
+        let tracker = self.shared.tracker.read().ok()?;
+        let addr = addr.as_ptr() as usize;
+        if tracker.live.contains_key(&addr) {
+            return None;
+        }
+        tracker.freed.get(&addr).copied()
+    }
+
+    /// 記録せずに`addr`のブロックが確保されたことを伝え、二重解放の判定から外す
+    ///
+    /// 確保の記録の中から呼ばれることがあるので、ロックを取れなければ何もしない。
+    pub(crate) fn forget_freed(&self, addr: NonNull<()>) {
+        if let Ok(mut tracker) = self.shared.tracker.try_write() {
+            tracker.freed.remove(&(addr.as_ptr() as usize));
+        }
+    }
+
+    /// [`AllocAnomaly::DoubleFree`]と判断した解放を、内部の割り当て器に渡さずに捨てるかどうかを設定する
+    ///
+    /// 既定では報告したうえで通常どおり解放する。記録していない確保(例えば
+    /// [`DebugGlobalAlloc::with`](crate::DebugGlobalAlloc::with)の中での確保)が同じアドレスを
+    /// 再利用していると誤って報告されることがあり、捨てるとそのブロックはリークする。
+    pub fn set_skip_double_free(&self, skip: bool) {
+        self.shared.skip_double_free.store(skip, Ordering::Relaxed);
+    }
+
+    /// 異常を検出したときに、記録に加えてpanicするかどうかを設定する
+    pub fn set_panic_on_anomaly(&self, panic: bool) {
+        self.shared.panic_on_anomaly.store(panic, Ordering::Relaxed);
+    }
+
+    /// `layout`が`addr`の生存中のブロックのレイアウトと違えば
+    /// [`AllocAnomaly::LayoutMismatch`]を報告する
+    pub(super) fn check_layout(&self, addr: NonNull<()>, layout: Layout) {
+        let expected = match self.shared.tracker.read() {
+            Ok(tracker) => tracker
+                .live
+                .get(&(addr.as_ptr() as usize))
+                .map(|action| action.layout),
+            Err(_) => None,
+        };
+        if let Some(expected) = expected.filter(|&expected| expected != layout) {
+            self.report_anomaly(AllocAnomaly::LayoutMismatch {
+                addr,
+                expected,
+                actual: layout,
+            });
+        }
+    }
+
+    pub(super) fn report_anomaly(&self, anomaly: AllocAnomaly) {
+        #[cfg(all(feature = "syslog", unix))]
+        if let Some(syslog) = &self.shared.syslog {
+            syslog.anomaly(&anomaly);
+        }
+        let panic = self.shared.panic_on_anomaly.load(Ordering::Relaxed);
+        let message = panic.then(|| anomaly.to_string());
+        if let Ok(mut anomalies) = self.shared.anomalies.write() {
+            anomalies.push(anomaly);
+        }
+        if let Some(message) = message {
+            panic!("allocator anomaly detected: {message}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        alloc::{Allocator, Layout},
+        ptr::NonNull,
+    };
+
+    use crate::{alloc::FREED_LIMIT, AllocAnomaly, DebugAlloc, Kind, MockAlloc, MockResult};
+
+    fn deallocations(mock: &MockAlloc) -> usize {
+        mock.calls()
+            .iter()
+            .filter(|call| call.kind == Kind::Deallocate)
+            .count()
+    }
+
+    #[test]
+    fn reused_address_is_not_a_double_free() {
+        let mut block = [0u64; 4];
+        let at = NonNull::from(&mut block).cast::<u8>();
+        let mock = MockAlloc::new([MockResult::At(at), MockResult::At(at)]);
+        let alloc = DebugAlloc::new(mock.clone());
+        let layout = Layout::new::<[u64; 4]>();
+        for _ in 0..2 {
+            let ptr = alloc.allocate(layout).unwrap();
+            assert_eq!(ptr.cast(), at);
+            unsafe { alloc.deallocate(ptr.cast(), layout) };
+        }
+        assert!(alloc.anomalies().is_empty());
+        assert_eq!(deallocations(&mock), 2);
+    }
+
+    #[test]
+    fn double_free_is_reported_and_forwarded() {
+        let mut block = [0u64; 4];
+        let at = NonNull::from(&mut block).cast::<u8>();
+        let mock = MockAlloc::new([MockResult::At(at)]);
+        let alloc = DebugAlloc::new(mock.clone());
+        let layout = Layout::new::<[u64; 4]>();
+        let ptr = alloc.allocate(layout).unwrap().cast();
+        unsafe {
+            alloc.deallocate(ptr, layout);
+            alloc.deallocate(ptr, layout);
+        }
+        assert_eq!(
+            alloc.anomalies(),
+            [AllocAnomaly::DoubleFree {
+                addr: at.cast(),
+                prior_seq: 1,
+            }]
+        );
+        assert_eq!(deallocations(&mock), 2);
+    }
+
+    #[test]
+    fn skip_double_free() {
+        let mut block = [0u64; 4];
+        let at = NonNull::from(&mut block).cast::<u8>();
+        let mock = MockAlloc::new([MockResult::At(at)]);
+        let alloc = DebugAlloc::new(mock.clone());
+        alloc.set_skip_double_free(true);
+        let layout = Layout::new::<[u64; 4]>();
+        let ptr = alloc.allocate(layout).unwrap().cast();
+        unsafe {
+            alloc.deallocate(ptr, layout);
+            alloc.deallocate(ptr, layout);
+        }
+        assert_eq!(alloc.anomalies().len(), 1);
+        assert_eq!(deallocations(&mock), 1);
+        assert_eq!(alloc.history().len(), 2);
+    }
+
+    #[test]
+    fn untracked_reuse_is_still_freed() {
+        let mut block = [0u64; 4];
+        let at = NonNull::from(&mut block).cast::<u8>();
+        let mock = MockAlloc::new([MockResult::At(at), MockResult::At(at)]);
+        let alloc = DebugAlloc::new(mock.clone());
+        let layout = Layout::new::<[u64; 4]>();
+        let ptr = alloc.allocate(layout).unwrap().cast();
+        unsafe { alloc.deallocate(ptr, layout) };
+        // 記録されずに同じアドレスが再利用された
+        let untracked = mock.allocate(layout).unwrap().cast();
+        unsafe { alloc.deallocate(untracked, layout) };
+        assert_eq!(alloc.anomalies().len(), 1);
+        assert_eq!(deallocations(&mock), 2);
+    }
+
+    #[test]
+    fn layout_mismatch_on_deallocate() {
+        let mut block = [0u64; 4];
+        let at = NonNull::from(&mut block).cast::<u8>();
+        let alloc = DebugAlloc::new(MockAlloc::new([MockResult::At(at)]));
+        let layout = Layout::new::<[u64; 4]>();
+        let ptr = alloc.allocate(layout).unwrap().cast();
+        let wrong = Layout::new::<[u64; 2]>();
+        unsafe { alloc.deallocate(ptr, wrong) };
+        assert_eq!(
+            alloc.anomalies(),
+            [AllocAnomaly::LayoutMismatch {
+                addr: at.cast(),
+                expected: layout,
+                actual: wrong,
+            }]
+        );
+    }
+
+    #[test]
+    fn layout_mismatch_on_grow_and_shrink() {
+        let mut block = [0u64; 8];
+        let at = NonNull::from(&mut block).cast::<u8>();
+        let alloc = DebugAlloc::new(MockAlloc::new([MockResult::At(at); 3]));
+        let small = Layout::new::<[u64; 2]>();
+        let large = Layout::new::<[u64; 8]>();
+        unsafe {
+            let ptr = alloc.allocate(small).unwrap().cast();
+            // 確保したときのレイアウトを正しく渡せば報告しない
+            let ptr = alloc.grow(ptr, small, large).unwrap().cast();
+            assert!(alloc.anomalies().is_empty());
+            let ptr = alloc.shrink(ptr, small, small).unwrap().cast();
+            alloc.deallocate(ptr, small);
+        }
+        assert_eq!(
+            alloc.anomalies(),
+            [AllocAnomaly::LayoutMismatch {
+                addr: at.cast(),
+                expected: large,
+                actual: small,
+            }]
+        );
+    }
+
+    #[test]
+    fn freed_addresses_are_bounded() {
+        let mut arena = vec![0u8; FREED_LIMIT + 1];
+        let base = NonNull::new(arena.as_mut_ptr()).unwrap();
+        let mock =
+            MockAlloc::new((0..=FREED_LIMIT).map(|i| MockResult::At(unsafe { base.add(i) })));
+        let alloc = DebugAlloc::new(mock);
+        let layout = Layout::new::<u8>();
+        let blocks = (0..=FREED_LIMIT)
+            .map(|_| alloc.allocate(layout).unwrap().cast())
+            .collect::<Vec<_>>();
+        for &ptr in &blocks {
+            unsafe { alloc.deallocate(ptr, layout) };
+        }
+        assert_eq!(
+            alloc.shared.tracker.read().unwrap().freed.len(),
+            FREED_LIMIT
+        );
+        // 最も古い解放は忘れているので報告しない
+        unsafe { alloc.deallocate(blocks[0], layout) };
+        assert!(alloc.anomalies().is_empty());
+        unsafe { alloc.deallocate(blocks[FREED_LIMIT], layout) };
+        assert_eq!(alloc.anomalies().len(), 1);
+    }
+}
@@ -0,0 +1,364 @@
+use std::{
+    alloc::System,
+    cell::RefCell,
+    collections::{BTreeMap, VecDeque},
+    fmt, ptr,
+    sync::{
+        atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock, RwLockWriteGuard, Weak,
+    },
+};
+
+use super::{Action, DebugAlloc, Shared};
+
+/// 溜まった操作がこの数を超えたら、記録したスレッドが履歴への反映を試みる
+const FLUSH_THRESHOLD: usize = 4096;
+
+/// 履歴への書き込み方
+#[derive(Debug, Default)]
+pub(super) enum HistoryBackend {
+    /// 記録のたびに履歴の書き込みロックを取る
+    #[default]
+    Locked,
+    /// ロックを取らずに積んでおき、履歴を読むときにまとめて反映する
+    LockFree(PendingStack),
+    /// スレッドごとの履歴に書き込み、履歴を読むときにまとめて反映する
+    Sharded(Shards),
+}
+
+/// 1つのスレッドの履歴
+#[derive(Debug)]
+struct Shard {
+    actions: Mutex<VecDeque<Action, System>>,
+    /// 書き込んでいたスレッドが終了した
+    retired: AtomicBool,
+}
+
+/// [`Shards`]の識別子を振る
+static NEXT_SHARDS_ID: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// このスレッドが書き込む履歴の控え([`Shards`]の識別子ごと)
+    static LOCAL_SHARDS: RefCell<LocalShards> =
+        const { RefCell::new(LocalShards(Vec::new_in(System))) };
+}
+
+/// スレッドが終了したときに、書き込んでいた履歴に印を付ける
+struct LocalShards(Vec<(u64, Weak<Shard, System>), System>);
+
+impl Drop for LocalShards {
+    fn drop(&mut self) {
+        for (_, shard) in &self.0 {
+            if let Some(shard) = shard.upgrade() {
+                shard.retired.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// スレッドごとの履歴
+#[derive(Debug)]
+pub(super) struct Shards {
+    id: u64,
+    /// スレッドID → そのスレッドの履歴
+    ///
+    /// 各スレッドは最初に書き込むときだけここに登録し、それ以降はスレッドローカルの
+    /// 控えから直接書き込む。ロックを取るのは登録と読み出しのときだけ。
+    shards: RwLock<BTreeMap<u64, Arc<Shard, System>, System>>,
+}
+
+impl Default for Shards {
+    fn default() -> Self {
+        Self {
+            id: NEXT_SHARDS_ID.fetch_add(1, Ordering::Relaxed),
+            shards: RwLock::new(BTreeMap::new_in(System)),
+        }
+    }
+}
+
+impl Shards {
+    /// 今のスレッド(`thread_id`)の履歴
+    fn shard(&self, thread_id: u64) -> Option<Arc<Shard, System>> {
+        let cached = LOCAL_SHARDS.try_with(|local| {
+            let local = local.try_borrow().ok()?;
+            let (_, shard) = local.0.iter().find(|(id, _)| *id == self.id)?;
+            shard.upgrade()
+        });
+        if let Ok(Some(shard)) = cached {
+            return Some(shard);
+        }
+        let shard = self
+            .shards
+            .write()
+            .ok()?
+            .entry(thread_id)
+            .or_insert_with(|| {
+                Arc::new_in(
+                    Shard {
+                        actions: Mutex::new(VecDeque::new_in(System)),
+                        retired: AtomicBool::new(false),
+                    },
+                    System,
+                )
+            })
+            .clone();
+        // スレッドローカルの変数が破棄された後は、毎回ここで探す
+        let _ = LOCAL_SHARDS.try_with(|local| {
+            if let Ok(mut local) = local.try_borrow_mut() {
+                // 破棄された割り当て器の分を除く
+                local.0.retain(|(_, shard)| shard.strong_count() != 0);
+                local.0.push((self.id, Arc::downgrade(&shard)));
+            }
+        });
+        Some(shard)
+    }
+
+    fn push(&self, action: Action, limit: Option<usize>) {
+        let Some(shard) = self.shard(action.thread_id) else {
+            return;
+        };
+        let Ok(mut actions) = shard.actions.lock() else {
+            return;
+        };
+        actions.push_back(action);
+        if let Some(limit) = limit {
+            while actions.len() > limit {
+                actions.pop_front();
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.shards.read().map_or(true, |shards| {
+            shards.values().all(|shard| {
+                shard
+                    .actions
+                    .lock()
+                    .map_or(true, |actions| actions.is_empty())
+            })
+        })
+    }
+
+    /// すべてのスレッドの履歴を取り出す(順序は保証しない)
+    ///
+    /// 終了したスレッドの履歴は取り出した後に一覧から外す。
+    fn take(&self) -> Vec<Action, System> {
+        let mut actions = Vec::new_in(System);
+        if let Ok(mut shards) = self.shards.write() {
+            for shard in shards.values() {
+                if let Ok(mut shard) = shard.actions.lock() {
+                    actions.extend(shard.drain(..));
+                }
+            }
+            // 書き込み中のスレッドが参照を持っていれば外さない
+            shards.retain(|_, shard| {
+                !shard.retired.load(Ordering::Relaxed) || Arc::strong_count(shard) != 1
+            });
+        }
+        actions
+    }
+}
+
+struct Node {
+    action: Action,
+    next: *mut Node,
+}
+
+/// 複数のスレッドからロックなしで積めるスタック
+pub(super) struct PendingStack {
+    head: AtomicPtr<Node>,
+    len: AtomicUsize,
+}
+
+unsafe impl Send for PendingStack {}
+unsafe impl Sync for PendingStack {}
+
+impl Default for PendingStack {
+    fn default() -> Self {
+        Self {
+            head: AtomicPtr::new(ptr::null_mut()),
+            len: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl fmt::Debug for PendingStack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PendingStack")
+            .field("len", &self.len.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+impl PendingStack {
+    /// `action`を積み、積んだ後のおおよその数を返す
+    fn push(&self, action: Action) -> usize {
+        let (node, _) = Box::into_raw_with_allocator(Box::new_in(
+            Node {
+                action,
+                next: ptr::null_mut(),
+            },
+            System,
+        ));
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            unsafe { (*node).next = head };
+            match self
+                .head
+                .compare_exchange_weak(head, node, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => break,
+                Err(current) => head = current,
+            }
+        }
+        self.len.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// 積まれた操作をすべて取り出す(順序は保証しない)
+    fn take(&self) -> Vec<Action, System> {
+        let mut node = self.head.swap(ptr::null_mut(), Ordering::Acquire);
+        let mut actions = Vec::new_in(System);
+        while !node.is_null() {
+            let boxed = unsafe { Box::from_raw_in(node, System) };
+            node = boxed.next;
+            actions.push(boxed.action);
+        }
+        self.len.fetch_sub(actions.len(), Ordering::Relaxed);
+        actions
+    }
+
+    fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Relaxed).is_null()
+    }
+}
+
+impl Drop for PendingStack {
+    fn drop(&mut self) {
+        self.take();
+    }
+}
+
+/// 履歴が通し番号の順に並ぶように`action`を入れ、`limit`を超えた古い操作を削除する
+pub(super) fn push_history(
+    history: &mut VecDeque<Action, System>,
+    action: Action,
+    limit: Option<usize>,
+) {
+    match history.back() {
+        Some(last) if last.seq > action.seq => {
+            let i = history.partition_point(|other| other.seq < action.seq);
+            history.insert(i, action);
+        }
+        _ => history.push_back(action),
+    }
+    if let Some(limit) = limit {
+        while history.len() > limit {
+            history.pop_front();
+        }
+    }
+}
+
+impl<A> DebugAlloc<A> {
+    /// 履歴への書き込みにロックを取らない
+    ///
+    /// 記録した操作はいったんロックなしのスタックに積み、履歴を読むとき
+    /// ([`DebugAlloc::history`]など)にまとめて通し番号の順に反映する。
+    /// 多数のスレッドが同時に確保する場合の競合が減る。
+    /// 集計と生存中の確保の一覧は通常どおり更新される。
+    pub fn with_lock_free_history(alloc: A) -> Self {
+        Self {
+            alloc,
+            shared: Arc::new_in(
+                Shared {
+                    backend: HistoryBackend::LockFree(PendingStack::default()),
+                    ..Default::default()
+                },
+                System,
+            ),
+        }
+    }
+
+    /// スレッドごとの履歴に書き込む
+    ///
+    /// 各スレッドは自分の履歴にだけ書き込むので、記録のたびに全体のロックを取らない。
+    /// 履歴を読むとき([`DebugAlloc::history`]など)に全スレッドの分を通し番号の順に
+    /// まとめる。集計と生存中の確保の一覧は通常どおり更新される。
+    pub fn with_sharded_history(alloc: A) -> Self {
+        Self {
+            alloc,
+            shared: Arc::new_in(
+                Shared {
+                    backend: HistoryBackend::Sharded(Shards::default()),
+                    ..Default::default()
+                },
+                System,
+            ),
+        }
+    }
+
+    /// スレッド`thread_id`([`Action::thread_id`])の操作だけを履歴から取り出す
+    pub fn thread_history(&self, thread_id: u64) -> Vec<Action> {
+        self.history()
+            .iter()
+            .filter(|action| action.thread_id == thread_id)
+            .cloned()
+            .collect()
+    }
+
+    /// 記録した操作を履歴に入れる
+    pub(super) fn store(&self, action: Action) {
+        let limit = self.shared.history_limit;
+        match &self.shared.backend {
+            HistoryBackend::Locked => {
+                if let Ok(mut history) = self.shared.history.write() {
+                    push_history(&mut history, action, limit);
+                }
+            }
+            HistoryBackend::LockFree(pending) => {
+                if pending.push(action) >= FLUSH_THRESHOLD {
+                    // 溜まりすぎないように、ロックが空いていれば反映する
+                    if let Ok(mut history) = self.shared.history.try_write() {
+                        let mut actions = pending.take();
+                        actions.sort_unstable_by_key(|action| action.seq);
+                        for action in actions {
+                            push_history(&mut history, action, limit);
+                        }
+                    }
+                }
+            }
+            HistoryBackend::Sharded(shards) => shards.push(action, limit),
+        }
+    }
+
+    /// まだ履歴に反映していない操作を反映する
+    pub(super) fn flush_pending(&self) {
+        let pending = match &self.shared.backend {
+            HistoryBackend::Locked => false,
+            HistoryBackend::LockFree(pending) => !pending.is_empty(),
+            HistoryBackend::Sharded(shards) => !shards.is_empty(),
+        };
+        if !pending {
+            return;
+        }
+        let Ok(mut history) = self.shared.history.write() else {
+            return;
+        };
+        // 取り出してから反映するまでの間に他の読み手が割り込まないように、
+        // 書き込みロックを取ってから取り出す
+        let mut actions = match &self.shared.backend {
+            HistoryBackend::Locked => return,
+            HistoryBackend::LockFree(pending) => pending.take(),
+            HistoryBackend::Sharded(shards) => shards.take(),
+        };
+        actions.sort_unstable_by_key(|action| action.seq);
+        for action in actions {
+            push_history(&mut history, action, self.shared.history_limit);
+        }
+    }
+
+    /// 反映を済ませてから履歴の書き込みロックを取る
+    pub(super) fn history_mut(&self) -> RwLockWriteGuard<'_, VecDeque<Action, System>> {
+        self.flush_pending();
+        self.shared.history.write().unwrap()
+    }
+}
@@ -0,0 +1,188 @@
+use std::{
+    alloc::System,
+    fmt::{self, Display},
+    hash::{Hash, Hasher},
+    sync::{atomic::Ordering, Arc, OnceLock},
+};
+
+#[cfg(all(target_os = "linux", not(target_arch = "arm")))]
+use super::unwind;
+use super::DebugAlloc;
+
+/// 記録する呼び出し履歴のフレーム数の初期値
+pub(super) const DEFAULT_DEPTH: usize = 16;
+
+/// 取ったフレームのうち先頭のこのクレートの中の呼び出しに使う分
+#[cfg(all(target_os = "linux", not(target_arch = "arm")))]
+const INTERNAL_FRAMES: usize = 16;
+
+/// 確保した場所の呼び出し履歴
+///
+/// このクレートのフレームは除いてある。複製しても共有するだけで確保しない。
+///
+/// Linuxでは記録するときに戻りアドレスだけを`System`から確保した領域に取り、
+/// [`AllocBacktrace::frames`]を最初に呼んだときに実行ファイルや共有ライブラリの
+/// シンボル表で関数名に直す。記録の処理はグローバルアロケータを通らないが、
+/// フレームは関数名だけでファイル名と行番号は分からない。ほかの環境では
+/// `std::backtrace`で取るので、フレームは`関数名 at ファイル:行:列`の形になり、
+/// 記録のたびにグローバルアロケータから確保する。
+#[derive(Clone)]
+pub struct AllocBacktrace {
+    inner: Arc<Frames, System>,
+}
+
+struct Frames {
+    /// 取った戻りアドレス(呼び出し元に近い順で、このクレートの中の呼び出しを含む)
+    addrs: Box<[usize], System>,
+    /// 残すフレーム数
+    depth: usize,
+    /// 名前に直したフレーム
+    names: OnceLock<Box<[String]>>,
+}
+
+/// このクレートか`std::backtrace`の中のフレームか
+fn is_internal(frame: &str) -> bool {
+    let path = frame.trim_start_matches(['<', '&']);
+    path.starts_with("debug_allocator::") || path.starts_with("std::backtrace")
+}
+
+impl AllocBacktrace {
+    /// 今の呼び出し履歴を、呼び出し元に近いものから最大`depth`フレーム取る
+    #[cfg(all(target_os = "linux", not(target_arch = "arm")))]
+    pub(super) fn capture(depth: usize) -> Self {
+        let mut addrs = Vec::with_capacity_in(depth + INTERNAL_FRAMES, System);
+        unwind::trace(&mut addrs);
+        Self {
+            inner: Arc::new_in(
+                Frames {
+                    addrs: addrs.into_boxed_slice(),
+                    depth,
+                    names: OnceLock::new(),
+                },
+                System,
+            ),
+        }
+    }
+
+    /// 今の呼び出し履歴を、呼び出し元に近いものから最大`depth`フレーム取る
+    #[cfg(not(all(target_os = "linux", not(target_arch = "arm"))))]
+    pub(super) fn capture(depth: usize) -> Self {
+        let backtrace = std::backtrace::Backtrace::force_capture().to_string();
+        let mut frames = Vec::<String>::new();
+        for line in backtrace.lines() {
+            let line = line.trim();
+            match line.strip_prefix("at ") {
+                Some(location) => {
+                    if let Some(frame) = frames.last_mut() {
+                        frame.push_str(" at ");
+                        frame.push_str(location);
+                    }
+                }
+                None => {
+                    // `  12: 関数名`
+                    if let Some((_, symbol)) = line.split_once(": ") {
+                        frames.push(symbol.to_string());
+                    }
+                }
+            }
+        }
+        Self::from_frames(
+            frames
+                .into_iter()
+                .skip_while(|frame| is_internal(frame))
+                .take(depth)
+                .collect(),
+        )
+    }
+
+    /// 呼び出し元に近い順に並んだフレームから作る
+    pub(super) fn from_frames(frames: Vec<String>) -> Self {
+        Self {
+            inner: Arc::new_in(
+                Frames {
+                    addrs: Vec::new_in(System).into_boxed_slice(),
+                    depth: frames.len(),
+                    names: OnceLock::from(frames.into_boxed_slice()),
+                },
+                System,
+            ),
+        }
+    }
+
+    /// 呼び出し元に近い順のフレーム
+    ///
+    /// 名前に直すのは最初に呼んだときだけで、このときはグローバルアロケータから確保する。
+    pub fn frames(&self) -> &[String] {
+        let Frames {
+            addrs,
+            depth,
+            names,
+        } = &*self.inner;
+        names.get_or_init(|| {
+            #[cfg(all(target_os = "linux", not(target_arch = "arm")))]
+            let names = addrs
+                .iter()
+                .map(|&addr| unwind::symbolize(addr))
+                .skip_while(|frame| is_internal(frame))
+                .take(*depth)
+                .collect();
+            #[cfg(not(all(target_os = "linux", not(target_arch = "arm"))))]
+            let names = {
+                let _ = (addrs, depth);
+                Box::default()
+            };
+            names
+        })
+    }
+}
+
+impl PartialEq for AllocBacktrace {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner) || self.frames() == other.frames()
+    }
+}
+
+impl Eq for AllocBacktrace {}
+
+impl Hash for AllocBacktrace {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.frames().hash(state);
+    }
+}
+
+impl fmt::Debug for AllocBacktrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AllocBacktrace")
+            .field("frames", &self.frames())
+            .finish()
+    }
+}
+
+impl Display for AllocBacktrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, frame) in self.frames().iter().enumerate() {
+            if i != 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{i:>3}: {frame}")?;
+        }
+        Ok(())
+    }
+}
+
+impl<A> DebugAlloc<A> {
+    /// 確保(allocate/grow/shrink)のときに記録する呼び出し履歴のフレーム数を設定する
+    ///
+    /// 初期値は16。呼び出し履歴の取得は遅いので、`0`を渡すと取得をやめる。
+    /// 取り方とグローバルアロケータを通るかどうかは[`AllocBacktrace`]を参照。
+    pub fn set_backtrace_depth(&self, depth: usize) {
+        self.shared.backtrace_depth.store(depth, Ordering::Relaxed);
+    }
+
+    pub(super) fn capture_backtrace(&self) -> Option<AllocBacktrace> {
+        match self.shared.backtrace_depth.load(Ordering::Relaxed) {
+            0 => None,
+            depth => Some(AllocBacktrace::capture(depth)),
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use std::{alloc::Layout, sync::atomic::Ordering};
+
+use super::{DebugAlloc, Kind};
+
+impl<A> DebugAlloc<A> {
+    /// 生存中の確保の合計バイト数の上限を設定する
+    ///
+    /// 確保や拡張で上限を超える場合は、内部の割り当て器を呼ばずに`Err(AllocError)`を返し、
+    /// [`Denial::Budget`](super::Denial::Budget)の付いた失敗として記録する。
+    /// 縮小と解放は常に許可する。`None`を渡すと上限をなくす。
+    ///
+    /// 上限の判定と確保は不可分ではないので、複数のスレッドから同時に確保すると
+    /// わずかに上限を超えることがある。
+    pub fn set_byte_budget(&self, max_live_bytes: Option<u64>) {
+        self.shared
+            .byte_budget
+            .store(max_live_bytes.unwrap_or(u64::MAX), Ordering::Relaxed);
+    }
+
+    /// [`DebugAlloc::set_byte_budget`]で設定した上限
+    pub fn byte_budget(&self) -> Option<u64> {
+        match self.shared.byte_budget.load(Ordering::Relaxed) {
+            u64::MAX => None,
+            budget => Some(budget),
+        }
+    }
+
+    /// 上限を超える確保なら`true`
+    pub(super) fn over_budget(&self, kind: Kind, layout: Layout) -> bool {
+        let Some(budget) = self.byte_budget() else {
+            return false;
+        };
+        let old_size = match kind {
+            Kind::Allocate | Kind::AllocateZeroed => 0,
+            Kind::Grow(old_layout) | Kind::GrowZeroed(old_layout) => old_layout.size(),
+            Kind::Deallocate | Kind::Shrink(_) => return false,
+        };
+        let growth = layout.size().saturating_sub(old_size) as u64;
+        let live_bytes = self.shared.tracker.read().map_or(0, |t| t.live_bytes);
+        live_bytes.saturating_add(growth) > budget
+    }
+}
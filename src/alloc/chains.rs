@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+
+use super::{Action, DebugAlloc, Kind};
+
+/// 1つのブロックが確保されてからgrow/shrinkで移り変わった一連の操作
+#[derive(Clone, Debug)]
+pub(super) struct Chain {
+    /// 最初の確保操作
+    pub(super) start: Action,
+    /// 最後の操作
+    pub(super) last: Action,
+    pub(super) max_size: usize,
+    pub(super) size: usize,
+    pub(super) grows: usize,
+    pub(super) shrinks: usize,
+    pub(super) freed: bool,
+}
+
+impl<A> DebugAlloc<A> {
+    /// 履歴を再生してgrow/shrinkを`old_addr`でつなぎ、ブロックごとの連鎖を作る(確保した順)
+    pub(super) fn resize_chains(&self) -> Vec<Chain> {
+        let mut chains = Vec::<Chain>::new();
+        // 現在のアドレス → `chains`の添字
+        let mut current = HashMap::<usize, usize>::new();
+        for action in self.history().iter() {
+            let Some(addr) = action.addr else {
+                continue;
+            };
+            let addr = addr.as_ptr() as usize;
+            let size = action.layout.size();
+            match action.kind {
+                Kind::Allocate | Kind::AllocateZeroed => {
+                    if size == 0 {
+                        continue;
+                    }
+                    current.insert(addr, chains.len());
+                    chains.push(Chain {
+                        start: action.clone(),
+                        last: action.clone(),
+                        max_size: size,
+                        size,
+                        grows: 0,
+                        shrinks: 0,
+                        freed: false,
+                    });
+                }
+                Kind::Deallocate => {
+                    if let Some(i) = current.remove(&addr) {
+                        chains[i].freed = true;
+                    }
+                }
+                Kind::Grow(_) | Kind::GrowZeroed(_) | Kind::Shrink(_) => {
+                    let old_addr = action.old_addr.map(|ptr| ptr.as_ptr() as usize);
+                    let Some(i) = old_addr.and_then(|old_addr| current.remove(&old_addr)) else {
+                        continue;
+                    };
+                    let chain = &mut chains[i];
+                    chain.last = action.clone();
+                    chain.size = size;
+                    chain.max_size = chain.max_size.max(size);
+                    if let Kind::Shrink(_) = action.kind {
+                        chain.shrinks += 1;
+                    } else {
+                        chain.grows += 1;
+                    }
+                    current.insert(addr, i);
+                }
+            }
+        }
+        chains
+    }
+
+    /// 一度でも拡張されたブロックについて、`(最初の確保の通し番号, 到達した最大サイズ)`を返す
+    ///
+    /// 最初からこのサイズで確保していれば(`with_capacity`など)再確保は起きなかった。
+    pub fn ideal_initial_sizes(&self) -> Vec<(u64, usize)> {
+        self.resize_chains()
+            .into_iter()
+            .filter(|chain| chain.grows > 0)
+            .map(|chain| (chain.start.seq, chain.max_size))
+            .collect()
+    }
+
+    /// growだけを繰り返し、shrinkも解放もされずに今も生存しているブロックについて
+    /// `(最初の確保の通し番号, 今のサイズ, growの回数)`を返す
+    ///
+    /// 伸び続けるキャッシュやログのバッファなど、際限なく大きくなるデータ構造の候補になる。
+    pub fn unbounded_growth_candidates(&self) -> Vec<(u64, usize, usize)> {
+        let chains = self.resize_chains();
+        let tracker = self.shared.tracker.read().unwrap();
+        chains
+            .into_iter()
+            .filter(|chain| chain.grows > 0 && chain.shrinks == 0 && !chain.freed)
+            .filter(|chain| {
+                chain.last.addr.is_some_and(|addr| {
+                    tracker
+                        .live
+                        .get(&(addr.as_ptr() as usize))
+                        .is_some_and(|live| live.seq == chain.last.seq)
+                })
+            })
+            .map(|chain| (chain.start.seq, chain.size, chain.grows))
+            .collect()
+    }
+}
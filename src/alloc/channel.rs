@@ -0,0 +1,361 @@
+use std::{
+    alloc::System,
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex, MutexGuard, Weak},
+    time::{Duration, Instant},
+};
+
+use super::{Action, DebugAlloc, Shared, WatchHandle};
+
+/// [`DebugAlloc::subscribe_bounded`]の受信側が追いつかず、キューが一杯のときの扱い
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Overflow {
+    /// 新しい操作を捨てる
+    DropNewest,
+    /// 一番古い操作を捨てて新しい操作を入れる
+    DropOldest,
+    /// 空きができるまで確保したスレッドを止める
+    ///
+    /// **危険**: 受信側のスレッドがこの割り当て器で確保するとデッドロックする。
+    /// また受信側が止まると、この割り当て器を使うすべてのスレッドが止まる。
+    BlockAllocator,
+}
+
+#[derive(Debug)]
+struct State {
+    queue: VecDeque<Action, System>,
+    cap: usize,
+    dropped: u64,
+    sender_alive: bool,
+    receiver_alive: bool,
+    /// 操作を待っている[`ActionStream`]のタスク
+    #[cfg(feature = "async")]
+    waker: Option<std::task::Waker>,
+}
+
+#[derive(Debug)]
+struct Channel {
+    state: Mutex<State>,
+    not_empty: Condvar,
+    not_full: Condvar,
+}
+
+impl Channel {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// [`DebugAlloc::subscribe`]や[`DebugAlloc::subscribe_bounded`]で登録した受信側
+///
+/// 割り当て器が破棄され、キューが空になると受信が終わる。
+/// 破棄すると割り当て器から登録を外す。
+#[derive(Debug)]
+pub struct Receiver {
+    channel: Arc<Channel>,
+    shared: Weak<Shared, System>,
+    watch: WatchHandle,
+}
+
+impl Receiver {
+    /// 次の操作を待って受け取る。送信側がなくなり、キューも空なら`None`を返す
+    pub fn recv(&self) -> Option<Action> {
+        let mut state = self.channel.lock();
+        loop {
+            if let Some(action) = self.pop(&mut state) {
+                return Some(action);
+            }
+            if !state.sender_alive {
+                return None;
+            }
+            state = self
+                .channel
+                .not_empty
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// 最大`timeout`だけ待って次の操作を受け取る
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Action> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.channel.lock();
+        loop {
+            if let Some(action) = self.pop(&mut state) {
+                return Some(action);
+            }
+            let now = Instant::now();
+            if !state.sender_alive || now >= deadline {
+                return None;
+            }
+            state = self
+                .channel
+                .not_empty
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+
+    /// 待たずに次の操作を受け取る
+    pub fn try_recv(&self) -> Option<Action> {
+        self.pop(&mut self.channel.lock())
+    }
+
+    /// キューに溜まっている操作の数
+    pub fn len(&self) -> usize {
+        self.channel.lock().queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// キューが一杯で捨てた操作の数
+    pub fn dropped(&self) -> u64 {
+        self.channel.lock().dropped
+    }
+
+    /// 割り当て器に登録した監視のハンドル
+    pub fn watch_handle(&self) -> WatchHandle {
+        self.watch
+    }
+
+    fn pop(&self, state: &mut State) -> Option<Action> {
+        let action = state.queue.pop_front()?;
+        self.channel.not_full.notify_one();
+        Some(action)
+    }
+}
+
+impl Iterator for Receiver {
+    type Item = Action;
+
+    fn next(&mut self) -> Option<Action> {
+        self.recv()
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        if let Some(shared) = self.shared.upgrade() {
+            shared.unwatch(self.watch);
+        }
+        self.channel.lock().receiver_alive = false;
+        self.channel.not_full.notify_all();
+    }
+}
+
+struct Sender {
+    channel: Arc<Channel>,
+    policy: Overflow,
+}
+
+impl Sender {
+    fn send(&self, action: &Action) {
+        let mut state = self.channel.lock();
+        if !state.receiver_alive {
+            return;
+        }
+        if state.queue.len() >= state.cap {
+            match self.policy {
+                Overflow::DropNewest => {
+                    state.dropped += 1;
+                    return;
+                }
+                Overflow::DropOldest => {
+                    state.queue.pop_front();
+                    state.dropped += 1;
+                }
+                Overflow::BlockAllocator => {
+                    while state.queue.len() >= state.cap && state.receiver_alive {
+                        state = self
+                            .channel
+                            .not_full
+                            .wait(state)
+                            .unwrap_or_else(|e| e.into_inner());
+                    }
+                    if !state.receiver_alive {
+                        return;
+                    }
+                }
+            }
+        }
+        state.queue.push_back(action.clone());
+        self.channel.not_empty.notify_one();
+        #[cfg(feature = "async")]
+        if let Some(waker) = state.waker.take() {
+            drop(state);
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        let mut state = self.channel.lock();
+        state.sender_alive = false;
+        self.channel.not_empty.notify_all();
+        #[cfg(feature = "async")]
+        if let Some(waker) = state.waker.take() {
+            drop(state);
+            waker.wake();
+        }
+    }
+}
+
+impl<A> DebugAlloc<A> {
+    /// 記録した操作をすべて送る受信側を作る
+    ///
+    /// キューに上限はなく、受信側が追いつかなければたまり続ける。監視用のスレッドで
+    /// 履歴のロックを取らずに操作を順に受け取れる。キューは`System`から確保するので、
+    /// 受信側のスレッドがこの割り当て器で確保しても再帰しない。
+    /// [`DebugAlloc::set_notify_sample`]で間引いた操作は送らない。
+    pub fn subscribe(&self) -> Receiver {
+        self.subscribe_bounded(usize::MAX, Overflow::DropNewest)
+    }
+
+    /// 記録した操作を最大`cap`件のキューに送る受信側を作る
+    ///
+    /// 受信側が追いつかずキューが一杯になったときの扱いを`policy`で選ぶ。
+    /// [`DebugAlloc::set_notify_sample`]で間引いた操作は送らないが、
+    /// [`DebugAlloc::set_watch_unsampled`]で受信側ごとにすべて送るようにできる。
+    /// [`Overflow::BlockAllocator`]は確保したスレッドを止めるので、使い方に注意する。
+    /// 受信側を破棄すると登録を外し、それ以降は何も送らない。
+    pub fn subscribe_bounded(&self, cap: usize, policy: Overflow) -> Receiver {
+        let channel = Arc::new(Channel {
+            state: Mutex::new(State {
+                queue: VecDeque::new_in(System),
+                cap: cap.max(1),
+                dropped: 0,
+                sender_alive: true,
+                receiver_alive: true,
+                #[cfg(feature = "async")]
+                waker: None,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+        });
+        let sender = Sender {
+            channel: channel.clone(),
+            policy,
+        };
+        let watch = self.on_action(move |action| sender.send(action));
+        Receiver {
+            channel,
+            shared: Arc::downgrade(&self.shared),
+            watch,
+        }
+    }
+}
+
+/// [`DebugAlloc::action_stream`]が返す非同期の受信側
+///
+/// [`ActionStream::recv`]を`await`すると次の操作を受け取れる。実行環境に依存しないので、
+/// tokioなどどの実行環境のタスクからでも使える。
+///
+/// このクレートは依存を持たないので`futures::Stream`は実装していない。実装しているのは
+/// 不安定な[`AsyncIterator`](std::async_iter::AsyncIterator)だけ。
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub struct ActionStream {
+    receiver: Receiver,
+}
+
+#[cfg(feature = "async")]
+impl ActionStream {
+    /// 次の操作があれば受け取り、なければ`cx`のタスクを起こすように登録する
+    ///
+    /// 送信側がなくなり、キューも空なら`Ready(None)`を返す。
+    pub fn poll_next(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Action>> {
+        use std::task::Poll;
+
+        let mut state = self.receiver.channel.lock();
+        if let Some(action) = self.receiver.pop(&mut state) {
+            return Poll::Ready(Some(action));
+        }
+        if !state.sender_alive {
+            return Poll::Ready(None);
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    /// 次の操作を待って受け取る。送信側がなくなり、キューも空なら`None`を返す
+    pub fn recv(&mut self) -> impl std::future::Future<Output = Option<Action>> + '_ {
+        std::future::poll_fn(|cx| self.poll_next(cx))
+    }
+
+    /// キューが一杯で捨てた操作の数
+    pub fn dropped(&self) -> u64 {
+        self.receiver.dropped()
+    }
+
+    /// 割り当て器に登録した監視のハンドル
+    pub fn watch_handle(&self) -> WatchHandle {
+        self.receiver.watch
+    }
+}
+
+#[cfg(feature = "async")]
+impl std::async_iter::AsyncIterator for ActionStream {
+    type Item = Action;
+
+    fn poll_next(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Action>> {
+        self.get_mut().poll_next(cx)
+    }
+}
+
+#[cfg(feature = "async")]
+impl<A> DebugAlloc<A> {
+    /// 記録した操作を最大`cap`件のキューに送る非同期の受信側を作る
+    ///
+    /// 確保したスレッドを止めないように、キューが一杯なら一番古い操作を捨てる
+    /// ([`Overflow::DropOldest`])。受信を待っているタスクは、操作を送った
+    /// スレッドの上で起こされる。
+    pub fn action_stream(&self, cap: usize) -> ActionStream {
+        ActionStream {
+            receiver: self.subscribe_bounded(cap, Overflow::DropOldest),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "async"))]
+mod tests {
+    use std::{
+        alloc::{Allocator, Layout, System},
+        future::Future,
+        pin::pin,
+        task::{Context, Poll, Waker},
+    };
+
+    use crate::{DebugAlloc, Kind};
+
+    #[test]
+    fn action_stream_recv() {
+        let alloc = DebugAlloc::new(System);
+        let mut stream = alloc.action_stream(16);
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(pin!(stream.recv()).poll(&mut cx).is_pending());
+        let layout = Layout::new::<u64>();
+        let ptr = alloc.allocate(layout).unwrap();
+        unsafe { alloc.deallocate(ptr.cast(), layout) };
+        for kind in [Kind::Allocate, Kind::Deallocate] {
+            match pin!(stream.recv()).poll(&mut cx) {
+                Poll::Ready(Some(action)) => assert_eq!(action.kind, kind),
+                poll => panic!("unexpected {poll:?}"),
+            }
+        }
+        drop(alloc);
+        assert!(matches!(
+            pin!(stream.recv()).poll(&mut cx),
+            Poll::Ready(None)
+        ));
+    }
+}
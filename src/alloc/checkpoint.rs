@@ -0,0 +1,73 @@
+use super::{Action, DebugAlloc};
+
+/// [`DebugAlloc::checkpoint`]で付けた印
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Checkpoint {
+    pub label: String,
+    /// 印を付けた後に最初に記録される操作の通し番号
+    pub seq: u64,
+    /// 印を付けたときの生存バイト数
+    pub live_bytes: u64,
+}
+
+/// [`DebugAlloc::diff`]の結果
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CheckpointDiff {
+    /// 2つの印の間に記録された操作(通し番号順)
+    pub actions: Vec<Action>,
+    /// 生存バイト数の増減(後の印 − 前の印)
+    pub net_bytes: i64,
+}
+
+impl<A> DebugAlloc<A> {
+    /// 今の位置に`label`という名前の印を付ける
+    ///
+    /// 同じ名前の印が既にあれば付け直す。
+    pub fn checkpoint(&self, label: &str) {
+        let (seq, live_bytes) = {
+            let tracker = self.shared.tracker.read().unwrap();
+            (tracker.next_seq, tracker.live_bytes)
+        };
+        let mut checkpoints = self.shared.checkpoints.lock().unwrap();
+        checkpoints.retain(|checkpoint| checkpoint.label != label);
+        checkpoints.push(Checkpoint {
+            label: label.to_owned(),
+            seq,
+            live_bytes,
+        });
+    }
+
+    /// 付けた印の一覧(付けた順)
+    pub fn checkpoints(&self) -> Vec<Checkpoint> {
+        self.shared.checkpoints.lock().unwrap().clone()
+    }
+
+    /// 印`a`と`b`の間の操作と生存バイト数の増減を返す
+    ///
+    /// 順番はどちらでもよく、前の印から後の印までを対象にする。操作は履歴から取るので、
+    /// 削除や間引きで欠けた操作は含まれないが、増減は常に正しい値になる。
+    /// どちらかの印がなければ`None`を返す。
+    pub fn diff(&self, a: &str, b: &str) -> Option<CheckpointDiff> {
+        let (a, b) = {
+            let checkpoints = self.shared.checkpoints.lock().unwrap();
+            let find = |label| {
+                checkpoints
+                    .iter()
+                    .find(|checkpoint| checkpoint.label == label)
+                    .cloned()
+            };
+            (find(a)?, find(b)?)
+        };
+        let (start, end) = if a.seq <= b.seq { (a, b) } else { (b, a) };
+        let actions = self
+            .history()
+            .iter()
+            .filter(|action| (start.seq..end.seq).contains(&action.seq))
+            .cloned()
+            .collect();
+        Some(CheckpointDiff {
+            actions,
+            net_bytes: end.live_bytes as i64 - start.live_bytes as i64,
+        })
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    process,
+    time::Duration,
+};
+
+use super::{json, Action, DebugAlloc, Kind};
+
+/// 経過時間をTrace Event Formatの`ts`(µs、小数)として書く
+fn micros(t: Duration) -> f64 {
+    t.as_nanos() as f64 / 1000.0
+}
+
+/// 1つのイベントを書き出す(2つ目以降は前に`,`を付ける)
+struct Events<'a, W: Write> {
+    w: &'a mut W,
+    first: bool,
+    pid: u32,
+}
+
+impl<W: Write> Events<'_, W> {
+    fn begin(&mut self, ph: &str, name: &str, action: &Action) -> io::Result<()> {
+        if !std::mem::take(&mut self.first) {
+            self.w.write_all(b",")?;
+        }
+        write!(self.w, "\n{{\"ph\":\"{ph}\",\"name\":")?;
+        json::write_str(self.w, name)?;
+        write!(
+            self.w,
+            ",\"pid\":{},\"tid\":{},\"ts\":{:.3}",
+            self.pid,
+            action.thread_id,
+            micros(action.timestamp)
+        )
+    }
+
+    /// ブロックの生存期間の始まり(`b`)か終わり(`e`)
+    fn lifetime(&mut self, ph: &str, block: &Block, action: &Action) -> io::Result<()> {
+        self.begin(ph, &format!("{} bytes", block.size), action)?;
+        write!(
+            self.w,
+            ",\"cat\":\"alloc\",\"id\":\"{:#x}\",\"args\":{{\"seq\":{},\"addr\":\"{:#x}\",\"align\":{}}}}}",
+            block.seq, block.seq, block.addr, block.align
+        )
+    }
+}
+
+/// 生存中のブロック
+struct Block {
+    /// 置いた操作の通し番号(イベントの`id`に使う)
+    seq: u64,
+    addr: usize,
+    size: usize,
+    align: usize,
+}
+
+impl<A> DebugAlloc<A> {
+    /// 履歴をChromeのTrace Event Formatで`path`に書き出す
+    ///
+    /// 書き出したファイルはPerfettoや`chrome://tracing`で開ける。詳しくは
+    /// [`DebugAlloc::write_chrome_trace`]を参照。
+    pub fn export_chrome_trace(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write_chrome_trace(&mut w)?;
+        w.flush()
+    }
+
+    /// 履歴をChromeのTrace Event Formatで`w`に書き出す
+    ///
+    /// 各ブロックの確保から解放までを操作したスレッドの非同期イベント(`b`/`e`)にし、
+    /// 生存バイト数を`live bytes`のカウンタ(`C`)として出す。grow/shrinkは元のブロックの
+    /// 終わりと新しいブロックの始まりになり、失敗した操作は瞬間イベント(`i`)になる。
+    /// 生存バイト数は履歴の先頭を0として積み上げる。
+    pub fn write_chrome_trace<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let history = self.history();
+        let mut events = Events {
+            w,
+            first: true,
+            pid: process::id(),
+        };
+        let mut live = HashMap::<usize, Block>::new();
+        let mut live_bytes = 0u64;
+        let mut thread_names = BTreeMap::new();
+        events
+            .w
+            .write_all(b"{\"displayTimeUnit\":\"ns\",\"traceEvents\":[")?;
+        for action in history.iter() {
+            if let Some(name) = action.thread_name {
+                thread_names.entry(action.thread_id).or_insert(name);
+            }
+            let Some(addr) = action.addr else {
+                events.begin("i", &format!("{} failed", action.kind.name()), action)?;
+                write!(
+                    events.w,
+                    ",\"s\":\"t\",\"args\":{{\"seq\":{},\"size\":{},\"align\":{}}}}}",
+                    action.seq,
+                    action.layout.size(),
+                    action.layout.align()
+                )?;
+                continue;
+            };
+            let addr = addr.as_ptr() as usize;
+            let released = match action.kind {
+                Kind::Deallocate => live.remove(&addr),
+                _ => action
+                    .old_addr
+                    .and_then(|old_addr| live.remove(&(old_addr.as_ptr() as usize))),
+            };
+            if let Some(block) = released {
+                events.lifetime("e", &block, action)?;
+            }
+            if action.kind != Kind::Deallocate && action.layout.size() != 0 {
+                let block = Block {
+                    seq: action.seq,
+                    addr,
+                    size: action.layout.size(),
+                    align: action.layout.align(),
+                };
+                events.lifetime("b", &block, action)?;
+                live.insert(addr, block);
+            }
+            live_bytes = live_bytes.saturating_add_signed(action.net_bytes());
+            events.begin("C", "live bytes", action)?;
+            write!(events.w, ",\"args\":{{\"bytes\":{live_bytes}}}}}")?;
+        }
+        for (tid, name) in thread_names {
+            if !std::mem::take(&mut events.first) {
+                events.w.write_all(b",")?;
+            }
+            write!(
+                events.w,
+                "\n{{\"ph\":\"M\",\"name\":\"thread_name\",\"pid\":{},\"tid\":{tid},\"args\":{{\"name\":",
+                events.pid
+            )?;
+            json::write_str(events.w, name.as_str())?;
+            events.w.write_all(b"}}")?;
+        }
+        events.w.write_all(b"\n]}\n")
+    }
+}
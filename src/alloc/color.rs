@@ -0,0 +1,64 @@
+use std::{
+    io::{self, Write},
+    sync::atomic::Ordering,
+};
+
+use super::{Action, ActionFormatter, DebugAlloc, Kind};
+
+/// 履歴を表示するときに色を付けるかどうか
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ColorMode {
+    /// 色を付けない
+    #[default]
+    Never,
+    /// 標準出力が端末のときだけ色を付ける(`*_to`には付けない)
+    Auto,
+    /// 常に色を付ける(`*_to`にも付ける)
+    Always,
+}
+
+impl ColorMode {
+    fn from_u8(n: u8) -> Self {
+        match n {
+            1 => ColorMode::Auto,
+            2 => ColorMode::Always,
+            _ => ColorMode::Never,
+        }
+    }
+}
+
+/// 種類ごとのANSIエスケープシーケンス
+fn style(action: &Action) -> &'static str {
+    if action.addr.is_none() {
+        return "\x1b[1;31m";
+    }
+    match action.kind {
+        Kind::Allocate | Kind::AllocateZeroed => "\x1b[32m",
+        Kind::Deallocate => "\x1b[31m",
+        Kind::Grow(_) | Kind::GrowZeroed(_) => "\x1b[33m",
+        Kind::Shrink(_) => "\x1b[36m",
+    }
+}
+
+/// `action`を種類ごとの色で書き出す
+///
+/// 確保は緑、解放は赤、拡張は黄、縮小はシアン、失敗は太字の赤。
+pub(super) fn write_colored<W: Write>(
+    w: &mut W,
+    action: &Action,
+    formatter: &ActionFormatter,
+) -> io::Result<()> {
+    writeln!(w, "{}{}\x1b[0m", style(action), formatter.display(action))
+}
+
+impl<A> DebugAlloc<A> {
+    /// `dump_*`で履歴を表示するときに色を付けるかどうかを設定する
+    pub fn set_color(&self, mode: ColorMode) {
+        self.shared.color.store(mode as u8, Ordering::Relaxed);
+    }
+
+    /// [`DebugAlloc::set_color`]の設定
+    pub fn color(&self) -> ColorMode {
+        ColorMode::from_u8(self.shared.color.load(Ordering::Relaxed))
+    }
+}
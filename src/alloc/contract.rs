@@ -0,0 +1,132 @@
+use std::{
+    alloc::Layout,
+    fmt::{self, Display},
+    ptr::NonNull,
+};
+
+use super::{DebugAlloc, Kind};
+
+/// grow/shrinkの事前条件の違反
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ContractViolation {
+    /// `grow`/`grow_zeroed`の新しいサイズが元のサイズより小さい
+    GrowToSmaller,
+    /// `shrink`の新しいサイズが元のサイズより大きい
+    ShrinkToLarger,
+    /// 渡されたポインタが元のレイアウトのアラインメントを満たさない
+    MisalignedPointer,
+}
+
+impl Display for ContractViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContractViolation::GrowToSmaller => write!(f, "grow to a smaller size"),
+            ContractViolation::ShrinkToLarger => write!(f, "shrink to a larger size"),
+            ContractViolation::MisalignedPointer => {
+                write!(f, "pointer does not fit the old layout")
+            }
+        }
+    }
+}
+
+impl<A> DebugAlloc<A> {
+    /// `ptr`に対する`kind`の操作が事前条件を満たさなければ違反の種類を返す
+    pub(super) fn check_contract(
+        &self,
+        kind: Kind,
+        layout: Layout,
+        ptr: NonNull<u8>,
+    ) -> Option<ContractViolation> {
+        let old_layout = kind.old_layout()?;
+        match kind {
+            Kind::Grow(_) | Kind::GrowZeroed(_) if layout.size() < old_layout.size() => {
+                Some(ContractViolation::GrowToSmaller)
+            }
+            Kind::Shrink(_) if layout.size() > old_layout.size() => {
+                Some(ContractViolation::ShrinkToLarger)
+            }
+            _ if !(ptr.as_ptr() as usize).is_multiple_of(old_layout.align()) => {
+                Some(ContractViolation::MisalignedPointer)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        alloc::{Allocator, Layout},
+        ptr::NonNull,
+    };
+
+    use super::ContractViolation;
+    use crate::{AllocAnomaly, DebugAlloc, Denial, MockAlloc, MockResult};
+
+    /// `f`で違反した操作をして、報告された違反と内部の割り当て器が呼ばれた回数を返す
+    fn violate(
+        f: impl FnOnce(&DebugAlloc<MockAlloc>, NonNull<u8>) -> bool,
+    ) -> (ContractViolation, usize) {
+        let mut block = [0u64; 8];
+        let at = NonNull::from(&mut block).cast::<u8>();
+        let mock = MockAlloc::new([MockResult::At(at)]);
+        let alloc = DebugAlloc::new(mock.clone());
+        let ptr = alloc.allocate(Layout::new::<[u64; 4]>()).unwrap().cast();
+        assert!(f(&alloc, ptr), "the violating call succeeded");
+        let last = alloc.history().back().cloned().unwrap();
+        assert_eq!(last.denied, Some(Denial::Contract));
+        let [AllocAnomaly::BrokenContract { violation, action }] = &alloc.anomalies()[..] else {
+            panic!("unexpected anomalies: {:?}", alloc.anomalies());
+        };
+        assert_eq!(action.seq, last.seq);
+        (*violation, mock.calls().len())
+    }
+
+    #[test]
+    fn grow_to_smaller() {
+        let old = Layout::new::<[u64; 4]>();
+        let (violation, calls) =
+            violate(|alloc, ptr| unsafe { alloc.grow(ptr, old, Layout::new::<u64>()).is_err() });
+        assert_eq!(violation, ContractViolation::GrowToSmaller);
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn shrink_to_larger() {
+        let old = Layout::new::<[u64; 4]>();
+        let (violation, calls) = violate(|alloc, ptr| unsafe {
+            alloc.shrink(ptr, old, Layout::new::<[u64; 8]>()).is_err()
+        });
+        assert_eq!(violation, ContractViolation::ShrinkToLarger);
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn misaligned_pointer() {
+        let old = Layout::new::<[u64; 2]>();
+        let (violation, calls) = violate(|alloc, ptr| unsafe {
+            alloc
+                .grow_zeroed(ptr.add(1), old, Layout::new::<[u64; 3]>())
+                .is_err()
+        });
+        assert_eq!(violation, ContractViolation::MisalignedPointer);
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn valid_resize_is_not_a_violation() {
+        let mut block = [0u64; 8];
+        let at = NonNull::from(&mut block).cast::<u8>();
+        let alloc = DebugAlloc::new(MockAlloc::new([MockResult::At(at); 2]));
+        let old = Layout::new::<[u64; 4]>();
+        let new = Layout::new::<[u64; 4]>();
+        unsafe {
+            let ptr = alloc.allocate(old).unwrap().cast();
+            // 同じサイズへのgrowは許される
+            let ptr = alloc.grow(ptr, old, new).unwrap().cast();
+            alloc.deallocate(ptr, new);
+        }
+        assert!(alloc.anomalies().is_empty());
+        assert!(alloc.history().iter().all(|action| action.denied.is_none()));
+    }
+}
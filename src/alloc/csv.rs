@@ -0,0 +1,35 @@
+use std::io::{self, Write};
+
+use super::DebugAlloc;
+
+impl<A> DebugAlloc<A> {
+    /// 履歴全体を古い順に1操作1行のCSVで書き出す
+    ///
+    /// 列は`seq,kind,size,align,old_size,old_align,addr,result`で、1行目は見出し。
+    /// grow/shrink以外では`old_size`と`old_align`が空になる。
+    /// `addr`は16進数で、失敗した操作では空。`result`は`ok`、`error`、
+    /// または`denied (理由)`
+    pub fn export_csv<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, "seq,kind,size,align,old_size,old_align,addr,result")?;
+        for action in self.history().iter() {
+            write!(
+                w,
+                "{},{},{},{},",
+                action.seq,
+                action.kind.name(),
+                action.layout.size(),
+                action.layout.align()
+            )?;
+            match action.kind.old_layout() {
+                Some(old_layout) => write!(w, "{},{},", old_layout.size(), old_layout.align())?,
+                None => write!(w, ",,")?,
+            }
+            match (action.addr, action.denied) {
+                (Some(addr), _) => writeln!(w, "{:p},ok", addr),
+                (None, Some(denial)) => writeln!(w, ",denied ({denial})"),
+                (None, None) => writeln!(w, ",error"),
+            }?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,533 @@
+//! マングルされたシンボル名をRustのパスに直す
+//!
+//! v0形式(`_R`)と旧形式(`_ZN`)に対応する。ジェネリクスの定数引数は整数、`bool`、
+//! `char`だけを読み、それ以外を含む名前は読めないものとして扱う。
+
+/// `symbol`を直す。Rustの名前でないか、読めない形なら`None`
+pub(super) fn demangle(symbol: &str) -> Option<String> {
+    v0(symbol).or_else(|| legacy(symbol))
+}
+
+fn strip_prefix<'a>(symbol: &'a str, prefix: &str) -> Option<&'a str> {
+    ["_", "", "__"]
+        .iter()
+        .find_map(|underscore| symbol.strip_prefix(underscore)?.strip_prefix(prefix))
+}
+
+fn v0(symbol: &str) -> Option<String> {
+    let rest = strip_prefix(symbol, "R")?;
+    // `.llvm.1234`のような後ろの付け足しは落とす
+    let rest = rest.split('.').next()?;
+    if !rest.starts_with(|c: char| c.is_ascii_uppercase()) {
+        return None;
+    }
+    let mut parser = Parser {
+        sym: rest.as_bytes(),
+        next: 0,
+        out: String::new(),
+        skip: 0,
+        depth: 0,
+    };
+    parser.path(true)?;
+    // 実体化したクレート
+    if parser.peek().is_some_and(|c| c.is_ascii_uppercase()) {
+        parser.skip += 1;
+        parser.path(false)?;
+        parser.skip -= 1;
+    }
+    (parser.next == parser.sym.len()).then_some(parser.out)
+}
+
+/// 後方参照をたどる深さの上限
+const MAX_DEPTH: u32 = 256;
+
+struct Parser<'a> {
+    sym: &'a [u8],
+    next: usize,
+    out: String,
+    /// 0でなければ読むだけで書き出さない
+    skip: u32,
+    depth: u32,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.sym.get(self.next).copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let c = self.peek()?;
+        self.next += 1;
+        Some(c)
+    }
+
+    fn eat(&mut self, c: u8) -> bool {
+        let matched = self.peek() == Some(c);
+        if matched {
+            self.next += 1;
+        }
+        matched
+    }
+
+    fn print(&mut self, s: &str) {
+        if self.skip == 0 {
+            self.out.push_str(s);
+        }
+    }
+
+    /// `_`で終わる62進数(`_`だけなら0、それ以外は1を足した値)
+    fn base62(&mut self) -> Option<u64> {
+        if self.eat(b'_') {
+            return Some(0);
+        }
+        let mut x = 0u64;
+        loop {
+            let digit = match self.next()? {
+                b'_' => return x.checked_add(1),
+                c @ b'0'..=b'9' => c - b'0',
+                c @ b'a'..=b'z' => c - b'a' + 10,
+                c @ b'A'..=b'Z' => c - b'A' + 36,
+                _ => return None,
+            };
+            x = x.checked_mul(62)?.checked_add(digit as u64)?;
+        }
+    }
+
+    /// `tag`が続けば62進数に1を足した値、続かなければ0
+    fn opt_base62(&mut self, tag: u8) -> Option<u64> {
+        if self.eat(tag) {
+            self.base62()?.checked_add(1)
+        } else {
+            Some(0)
+        }
+    }
+
+    fn ident(&mut self) -> Option<&'a str> {
+        // punycodeは直さずにそのまま出す
+        self.eat(b'u');
+        let start = self.next;
+        // 長さは`0`で始まるなら`0`だけ
+        if !self.eat(b'0') {
+            while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                self.next += 1;
+            }
+        }
+        let len = std::str::from_utf8(&self.sym[start..self.next])
+            .ok()?
+            .parse::<usize>()
+            .ok()?;
+        self.eat(b'_');
+        let bytes = self.sym.get(self.next..self.next.checked_add(len)?)?;
+        self.next += len;
+        std::str::from_utf8(bytes).ok()
+    }
+
+    /// 後方参照の先に移り、戻る位置を返す
+    fn enter_backref(&mut self) -> Option<usize> {
+        let start = self.next - 1;
+        let target = self.base62()? as usize;
+        if target >= start || self.depth >= MAX_DEPTH {
+            return None;
+        }
+        self.depth += 1;
+        Some(std::mem::replace(&mut self.next, target))
+    }
+
+    fn leave_backref(&mut self, saved: usize) {
+        self.depth -= 1;
+        self.next = saved;
+    }
+
+    /// パスを書き出す。`in_value`なら型引数を`::<>`で書く
+    fn path(&mut self, in_value: bool) -> Option<()> {
+        match self.next()? {
+            b'C' => {
+                self.opt_base62(b's')?;
+                let name = self.ident()?;
+                self.print(name);
+            }
+            b'N' => {
+                let ns = self.next()?;
+                if !ns.is_ascii_alphabetic() {
+                    return None;
+                }
+                self.path(in_value)?;
+                let disambiguator = self.opt_base62(b's')?;
+                let name = self.ident()?;
+                if ns.is_ascii_uppercase() {
+                    let ns = match ns {
+                        b'C' => "closure".to_string(),
+                        b'S' => "shim".to_string(),
+                        _ => (ns as char).to_string(),
+                    };
+                    self.print("::{");
+                    self.print(&ns);
+                    if !name.is_empty() {
+                        self.print(":");
+                        self.print(name);
+                    }
+                    self.print(&format!("#{disambiguator}}}"));
+                } else if !name.is_empty() {
+                    self.print("::");
+                    self.print(name);
+                }
+            }
+            b'M' => {
+                self.impl_path()?;
+                self.print("<");
+                self.ty()?;
+                self.print(">");
+            }
+            b'X' => {
+                self.impl_path()?;
+                self.trait_impl()?;
+            }
+            b'Y' => self.trait_impl()?,
+            b'I' => {
+                self.path(in_value)?;
+                if in_value {
+                    self.print("::");
+                }
+                self.print("<");
+                let mut first = true;
+                while !self.eat(b'E') {
+                    if !first {
+                        self.print(", ");
+                    }
+                    first = false;
+                    self.generic_arg()?;
+                }
+                self.print(">");
+            }
+            b'B' => {
+                let saved = self.enter_backref()?;
+                self.path(in_value)?;
+                self.leave_backref(saved);
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+
+    /// パスを書き出す。型引数があれば閉じずに`true`を返す(関連型の指定を続けて書くため)
+    fn path_open_generics(&mut self) -> Option<bool> {
+        match self.peek()? {
+            b'B' => {
+                self.next += 1;
+                let saved = self.enter_backref()?;
+                let open = self.path_open_generics()?;
+                self.leave_backref(saved);
+                Some(open)
+            }
+            b'I' => {
+                self.next += 1;
+                self.path(false)?;
+                self.print("<");
+                let mut first = true;
+                while !self.eat(b'E') {
+                    if !first {
+                        self.print(", ");
+                    }
+                    first = false;
+                    self.generic_arg()?;
+                }
+                Some(true)
+            }
+            _ => {
+                self.path(false)?;
+                Some(false)
+            }
+        }
+    }
+
+    /// 実装のあるパス(書き出さない)
+    fn impl_path(&mut self) -> Option<()> {
+        self.skip += 1;
+        self.opt_base62(b's')?;
+        self.path(false)?;
+        self.skip -= 1;
+        Some(())
+    }
+
+    /// `<型 as トレイト>`
+    fn trait_impl(&mut self) -> Option<()> {
+        self.print("<");
+        self.ty()?;
+        self.print(" as ");
+        self.path(false)?;
+        self.print(">");
+        Some(())
+    }
+
+    fn generic_arg(&mut self) -> Option<()> {
+        if self.eat(b'L') {
+            self.base62()?;
+            self.print("'_");
+            Some(())
+        } else if self.eat(b'K') {
+            self.konst()
+        } else {
+            self.ty()
+        }
+    }
+
+    fn ty(&mut self) -> Option<()> {
+        let c = self.next()?;
+        if let Some(name) = basic_type(c) {
+            self.print(name);
+            return Some(());
+        }
+        match c {
+            b'R' | b'Q' => {
+                self.print("&");
+                if self.eat(b'L') {
+                    self.base62()?;
+                }
+                if c == b'Q' {
+                    self.print("mut ");
+                }
+                self.ty()?;
+            }
+            b'P' => {
+                self.print("*const ");
+                self.ty()?;
+            }
+            b'O' => {
+                self.print("*mut ");
+                self.ty()?;
+            }
+            b'A' => {
+                self.print("[");
+                self.ty()?;
+                self.print("; ");
+                self.konst()?;
+                self.print("]");
+            }
+            b'S' => {
+                self.print("[");
+                self.ty()?;
+                self.print("]");
+            }
+            b'T' => {
+                self.print("(");
+                let mut len = 0;
+                while !self.eat(b'E') {
+                    if len != 0 {
+                        self.print(", ");
+                    }
+                    self.ty()?;
+                    len += 1;
+                }
+                if len == 1 {
+                    self.print(",");
+                }
+                self.print(")");
+            }
+            b'F' => self.fn_sig()?,
+            b'D' => {
+                self.dyn_bounds()?;
+                if !self.eat(b'L') {
+                    return None;
+                }
+                self.base62()?;
+            }
+            b'B' => {
+                let saved = self.enter_backref()?;
+                self.ty()?;
+                self.leave_backref(saved);
+            }
+            _ => {
+                self.next -= 1;
+                self.path(false)?;
+            }
+        }
+        Some(())
+    }
+
+    fn fn_sig(&mut self) -> Option<()> {
+        if self.eat(b'G') {
+            self.base62()?;
+        }
+        if self.eat(b'U') {
+            self.print("unsafe ");
+        }
+        if self.eat(b'K') {
+            let abi = if self.eat(b'C') {
+                "C".to_string()
+            } else {
+                self.ident()?.replace('_', "-")
+            };
+            self.print(&format!("extern \"{abi}\" "));
+        }
+        self.print("fn(");
+        let mut first = true;
+        while !self.eat(b'E') {
+            if !first {
+                self.print(", ");
+            }
+            first = false;
+            self.ty()?;
+        }
+        self.print(")");
+        if self.eat(b'u') {
+            return Some(());
+        }
+        self.print(" -> ");
+        self.ty()
+    }
+
+    fn dyn_bounds(&mut self) -> Option<()> {
+        if self.eat(b'G') {
+            self.base62()?;
+        }
+        self.print("dyn ");
+        let mut first = true;
+        while !self.eat(b'E') {
+            if !first {
+                self.print(" + ");
+            }
+            first = false;
+            let mut open = self.path_open_generics()?;
+            while self.eat(b'p') {
+                self.print(if open { ", " } else { "<" });
+                open = true;
+                let name = self.ident()?;
+                self.print(name);
+                self.print(" = ");
+                self.ty()?;
+            }
+            if open {
+                self.print(">");
+            }
+        }
+        Some(())
+    }
+
+    /// `_`で終わる16進数の数字
+    fn hex_digits(&mut self) -> Option<&'a str> {
+        let start = self.next;
+        while self.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
+            self.next += 1;
+        }
+        let digits = std::str::from_utf8(&self.sym[start..self.next]).ok()?;
+        self.eat(b'_').then_some(digits)
+    }
+
+    fn konst(&mut self) -> Option<()> {
+        match self.next()? {
+            b'p' => self.print("_"),
+            b'B' => {
+                let saved = self.enter_backref()?;
+                self.konst()?;
+                self.leave_backref(saved);
+            }
+            b'a' | b'h' | b'i' | b'j' | b'l' | b'm' | b'n' | b'o' | b's' | b't' | b'x' | b'y' => {
+                let negative = self.eat(b'n');
+                let digits = self.hex_digits()?;
+                let value = match digits {
+                    "" => 0,
+                    digits => u128::from_str_radix(digits, 16).ok()?,
+                };
+                let sign = if negative { "-" } else { "" };
+                self.print(&format!("{sign}{value}"));
+            }
+            b'b' => match self.hex_digits()? {
+                "0" => self.print("false"),
+                "1" => self.print("true"),
+                _ => return None,
+            },
+            b'c' => {
+                let c = char::from_u32(u32::from_str_radix(self.hex_digits()?, 16).ok()?)?;
+                self.print(&format!("{c:?}"));
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+}
+
+fn basic_type(c: u8) -> Option<&'static str> {
+    Some(match c {
+        b'a' => "i8",
+        b'b' => "bool",
+        b'c' => "char",
+        b'd' => "f64",
+        b'e' => "str",
+        b'f' => "f32",
+        b'h' => "u8",
+        b'i' => "isize",
+        b'j' => "usize",
+        b'l' => "i32",
+        b'm' => "u32",
+        b'n' => "i128",
+        b'o' => "u128",
+        b'p' => "_",
+        b's' => "i16",
+        b't' => "u16",
+        b'u' => "()",
+        b'v' => "...",
+        b'x' => "i64",
+        b'y' => "u64",
+        b'z' => "!",
+        _ => return None,
+    })
+}
+
+fn legacy(symbol: &str) -> Option<String> {
+    let mut rest = strip_prefix(symbol, "ZN")?;
+    if let Some((head, _)) = rest.split_once(".llvm.") {
+        rest = head;
+    }
+    let mut parts = Vec::new();
+    while !rest.starts_with('E') {
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let len = rest[..digits].parse::<usize>().ok()?;
+        rest = &rest[digits..];
+        parts.push(rest.get(..len)?);
+        rest = &rest[len..];
+    }
+    // 末尾の`h`と16桁のハッシュ
+    if let Some(hash) = parts.last().and_then(|part| part.strip_prefix('h')) {
+        if hash.len() == 16 && hash.bytes().all(|c| c.is_ascii_hexdigit()) {
+            parts.pop();
+        }
+    }
+    if parts.is_empty() {
+        return None;
+    }
+    let parts = parts
+        .iter()
+        .map(|part| unescape(part))
+        .collect::<Option<Vec<_>>>()?;
+    Some(parts.join("::"))
+}
+
+/// 旧形式の`$LT$`や`..`などを元の文字に戻す
+fn unescape(part: &str) -> Option<String> {
+    let mut rest = part.strip_prefix("_$").map_or(part, |_| &part[1..]);
+    let mut out = String::new();
+    while let Some(c) = rest.chars().next() {
+        if let Some(tail) = rest.strip_prefix("..") {
+            out.push_str("::");
+            rest = tail;
+        } else if c == '$' {
+            let (code, tail) = rest[1..].split_once('$')?;
+            out.push(match code {
+                "SP" => '@',
+                "BP" => '*',
+                "RF" => '&',
+                "LT" => '<',
+                "GT" => '>',
+                "LP" => '(',
+                "RP" => ')',
+                "C" => ',',
+                code => char::from_u32(u32::from_str_radix(code.strip_prefix('u')?, 16).ok()?)?,
+            });
+            rest = tail;
+        } else {
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    Some(out)
+}
@@ -0,0 +1,205 @@
+use std::{
+    collections::HashMap,
+    env,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    process,
+    time::Duration,
+};
+
+use super::{json, Action, DebugAlloc, Kind};
+
+/// 1つのプログラムポイント(確保した場所)ごとの集計
+#[derive(Clone, Debug, Default)]
+struct PpStats {
+    /// 確保したバイト数とブロック数の合計
+    total_bytes: u64,
+    total_blocks: u64,
+    /// ブロックの生存期間の合計[µs]
+    total_lifetime: u64,
+    /// 生存中のバイト数とブロック数
+    curr_bytes: u64,
+    curr_blocks: u64,
+    /// 生存中のバイト数が最大だったときの値
+    max_bytes: u64,
+    max_blocks: u64,
+    /// 全体の生存バイト数が最大だったときの値
+    at_gmax_bytes: u64,
+    at_gmax_blocks: u64,
+}
+
+struct Block {
+    pp: usize,
+    size: u64,
+    start: Duration,
+}
+
+/// 確保した場所を表すフレーム名の列
+fn frames_of(action: &Action) -> Vec<String> {
+    #[cfg(feature = "backtrace")]
+    if let Some(backtrace) = &action.backtrace {
+        if !backtrace.frames().is_empty() {
+            return backtrace.frames().to_vec();
+        }
+    }
+    let _ = action;
+    vec!["[unknown]".to_string()]
+}
+
+fn micros(t: Duration) -> u64 {
+    t.as_micros() as u64
+}
+
+impl<A> DebugAlloc<A> {
+    /// 履歴をDHATの形式で`path`に書き出す
+    ///
+    /// 書き出したファイルはValgrindの`dh_view.html`で開ける。`backtrace`機能で
+    /// 呼び出し履歴を記録していれば確保した場所ごとに、そうでなければ1つの
+    /// `[unknown]`にまとめて集計する。
+    pub fn export_dhat(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write_dhat(&mut w)?;
+        w.flush()
+    }
+
+    /// 履歴をDHATの`dh_view.html`で読める形式で書き出す
+    ///
+    /// grow/shrinkは元のブロックの解放と新しいブロックの確保として扱う。
+    pub fn write_dhat<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let history = self.history();
+
+        let mut frame_table = vec!["[root]".to_string()];
+        let mut frame_index = HashMap::<String, usize>::new();
+        let mut pp_index = HashMap::<Vec<usize>, usize>::new();
+        let mut pp_frames = Vec::<Vec<usize>>::new();
+        let mut pps = Vec::<PpStats>::new();
+        let mut blocks = HashMap::<usize, Block>::new();
+        let (mut curr, mut gmax, mut t_gmax) = (0u64, 0u64, Duration::ZERO);
+        let t_end = history
+            .back()
+            .map_or(Duration::ZERO, |action| action.timestamp);
+
+        let free = |pps: &mut Vec<PpStats>, block: Block, now: Duration, curr: &mut u64| {
+            let pp = &mut pps[block.pp];
+            pp.curr_bytes -= block.size;
+            pp.curr_blocks -= 1;
+            pp.total_lifetime += micros(now.saturating_sub(block.start));
+            *curr -= block.size;
+        };
+
+        for action in history.iter() {
+            let Some(addr) = action.addr else {
+                continue;
+            };
+            let addr = addr.as_ptr() as usize;
+            let released = match action.kind {
+                Kind::Deallocate => blocks.remove(&addr),
+                _ => action
+                    .old_addr
+                    .and_then(|old_addr| blocks.remove(&(old_addr.as_ptr() as usize))),
+            };
+            if let Some(block) = released {
+                free(&mut pps, block, action.timestamp, &mut curr);
+            }
+            if action.kind == Kind::Deallocate {
+                continue;
+            }
+
+            let frames = frames_of(action)
+                .into_iter()
+                .map(|frame| {
+                    *frame_index.entry(frame).or_insert_with_key(|frame| {
+                        frame_table.push(frame.clone());
+                        frame_table.len() - 1
+                    })
+                })
+                .collect::<Vec<_>>();
+            let pp = *pp_index.entry(frames).or_insert_with_key(|frames| {
+                pp_frames.push(frames.clone());
+                pps.push(PpStats::default());
+                pps.len() - 1
+            });
+            let size = action.layout.size() as u64;
+            let stats = &mut pps[pp];
+            stats.total_bytes += size;
+            stats.total_blocks += 1;
+            stats.curr_bytes += size;
+            stats.curr_blocks += 1;
+            if stats.curr_bytes > stats.max_bytes {
+                stats.max_bytes = stats.curr_bytes;
+                stats.max_blocks = stats.curr_blocks;
+            }
+            blocks.insert(
+                addr,
+                Block {
+                    pp,
+                    size,
+                    start: action.timestamp,
+                },
+            );
+            curr += size;
+            if curr > gmax {
+                gmax = curr;
+                t_gmax = action.timestamp;
+                for stats in &mut pps {
+                    stats.at_gmax_bytes = stats.curr_bytes;
+                    stats.at_gmax_blocks = stats.curr_blocks;
+                }
+            }
+        }
+        for block in blocks.into_values() {
+            let pp = &mut pps[block.pp];
+            pp.total_lifetime += micros(t_end.saturating_sub(block.start));
+        }
+
+        write!(
+            w,
+            "{{\"dhatFileVersion\":2,\"mode\":\"rust-heap\",\"verb\":\"Allocated\",\
+             \"bklt\":true,\"bkacc\":false,\"tu\":\"µs\",\"Mtu\":\"s\",\"tuth\":10,\"cmd\":"
+        )?;
+        json::write_str(&mut w, &env::args().collect::<Vec<_>>().join(" "))?;
+        write!(
+            w,
+            ",\"pid\":{},\"tg\":{},\"te\":{},\"pps\":[",
+            process::id(),
+            micros(t_gmax),
+            micros(t_end)
+        )?;
+        for (i, (stats, frames)) in pps.iter().zip(&pp_frames).enumerate() {
+            if i != 0 {
+                write!(w, ",")?;
+            }
+            write!(
+                w,
+                "\n{{\"tb\":{},\"tbk\":{},\"tl\":{},\"mb\":{},\"mbk\":{},\"gb\":{},\"gbk\":{},\
+                 \"eb\":{},\"ebk\":{},\"fs\":[",
+                stats.total_bytes,
+                stats.total_blocks,
+                stats.total_lifetime,
+                stats.max_bytes,
+                stats.max_blocks,
+                stats.at_gmax_bytes,
+                stats.at_gmax_blocks,
+                stats.curr_bytes,
+                stats.curr_blocks
+            )?;
+            for (j, frame) in frames.iter().enumerate() {
+                if j != 0 {
+                    write!(w, ",")?;
+                }
+                write!(w, "{frame}")?;
+            }
+            write!(w, "]}}")?;
+        }
+        write!(w, "\n],\"ftbl\":[")?;
+        for (i, frame) in frame_table.iter().enumerate() {
+            if i != 0 {
+                write!(w, ",")?;
+            }
+            writeln!(w)?;
+            json::write_str(&mut w, frame)?;
+        }
+        writeln!(w, "\n]}}")
+    }
+}
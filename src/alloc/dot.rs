@@ -0,0 +1,94 @@
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+
+use super::{Action, DebugAlloc, Kind};
+
+/// 操作を表すノードを書き出す
+fn write_node<W: Write>(w: &mut W, action: &Action) -> io::Result<()> {
+    let addr = action.addr.map_or(0, |addr| addr.as_ptr() as usize);
+    write!(
+        w,
+        "        n{} [label=\"#{} {}\\n{addr:#x}",
+        action.seq,
+        action.seq,
+        action.kind.name()
+    )?;
+    if action.kind != Kind::Deallocate {
+        write!(w, "\\n{} bytes", action.layout.size())?;
+    }
+    writeln!(w, "\"];")
+}
+
+impl<A> DebugAlloc<A> {
+    /// 確保からgrow/shrinkを経て解放されるまでの各ブロックの移り変わりを
+    /// Graphvizのdot形式で書き出す
+    ///
+    /// ブロックごとに`cluster`を作り、各操作を通し番号、種類、アドレス、サイズを書いた
+    /// ノードに、操作の順を辺にする。辺にはサイズの増減を書き、アドレスが移った辺は
+    /// 太くする。サイズ0の確保と失敗した操作は含めない。
+    /// `dot -Tsvg`などで画像にできる。
+    pub fn export_dot<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let history = self.history();
+        let mut chains = Vec::<Vec<&Action>>::new();
+        // 現在のアドレス → `chains`の添字
+        let mut current = HashMap::<usize, usize>::new();
+        for action in history.iter() {
+            let Some(addr) = action.addr else {
+                continue;
+            };
+            let addr = addr.as_ptr() as usize;
+            match action.kind {
+                Kind::Allocate | Kind::AllocateZeroed => {
+                    if action.layout.size() == 0 {
+                        continue;
+                    }
+                    current.insert(addr, chains.len());
+                    chains.push(vec![action]);
+                }
+                Kind::Deallocate => {
+                    if let Some(i) = current.remove(&addr) {
+                        chains[i].push(action);
+                    }
+                }
+                Kind::Grow(_) | Kind::GrowZeroed(_) | Kind::Shrink(_) => {
+                    let old_addr = action.old_addr.map(|ptr| ptr.as_ptr() as usize);
+                    let Some(i) = old_addr.and_then(|old_addr| current.remove(&old_addr)) else {
+                        continue;
+                    };
+                    chains[i].push(action);
+                    current.insert(addr, i);
+                }
+            }
+        }
+
+        writeln!(w, "digraph allocations {{")?;
+        writeln!(w, "    rankdir=LR;")?;
+        writeln!(w, "    node [shape=box, fontname=monospace];")?;
+        for chain in &chains {
+            writeln!(w, "    subgraph cluster_{} {{", chain[0].seq)?;
+            writeln!(w, "        label=\"block #{}\";", chain[0].seq)?;
+            for action in chain {
+                write_node(w, action)?;
+            }
+            for pair in chain.windows(2) {
+                let (from, to) = (pair[0], pair[1]);
+                let label = match to.kind {
+                    Kind::Deallocate => "free".to_string(),
+                    _ => format!("{:+}", to.net_bytes()),
+                };
+                let moved = to.kind != Kind::Deallocate && to.addr != from.addr;
+                writeln!(
+                    w,
+                    "        n{} -> n{} [label=\"{label}\"{}];",
+                    from.seq,
+                    to.seq,
+                    if moved { ", penwidth=2" } else { "" }
+                )?;
+            }
+            writeln!(w, "    }}")?;
+        }
+        writeln!(w, "}}")
+    }
+}
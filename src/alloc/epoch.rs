@@ -0,0 +1,43 @@
+use std::{collections::BTreeMap, sync::atomic::Ordering};
+
+use super::{Action, AllocStats, DebugAlloc};
+
+impl<A> DebugAlloc<A> {
+    /// エポックを1つ進めて、新しいエポックを返す
+    ///
+    /// これ以降の操作には新しいエポックが付く。フレームごとに呼ぶと、確保をフレームに対応付けられる。
+    pub fn tick_epoch(&self) -> u64 {
+        self.shared.epoch.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// 今のエポック
+    pub fn epoch(&self) -> u64 {
+        self.shared.epoch.load(Ordering::Relaxed)
+    }
+
+    /// エポックごとの集計
+    ///
+    /// 回数と累計のバイト数は履歴から、`live_*`は生存中の確保を最後に操作したエポックで数える。
+    pub fn stats_by_epoch(&self) -> BTreeMap<u64, AllocStats> {
+        let mut groups = BTreeMap::<u64, AllocStats>::new();
+        for action in self.history().iter() {
+            groups.entry(action.epoch).or_default().count(action);
+        }
+        let tracker = self.shared.tracker.read().unwrap();
+        for action in tracker.live.values() {
+            let stats = groups.entry(action.epoch).or_default();
+            stats.live_allocations += 1;
+            stats.live_bytes += action.layout.size() as u64;
+        }
+        groups
+    }
+
+    /// エポック`n`に記録された操作(履歴の順)
+    pub fn actions_in_epoch(&self, n: u64) -> Vec<Action> {
+        self.history()
+            .iter()
+            .filter(|action| action.epoch == n)
+            .cloned()
+            .collect()
+    }
+}
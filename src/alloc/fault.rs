@@ -0,0 +1,109 @@
+use super::DebugAlloc;
+
+/// わざと確保を失敗させる呼び出しの選び方
+///
+/// 呼び出しは[`DebugAlloc::set_fault_config`]を呼んでからの確保を伴う操作
+/// (allocate/allocate_zeroed/grow/grow_zeroed/shrink)を、最初を0として数える。
+/// deallocateは数えない。
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FaultConfig {
+    /// `n`番目の呼び出しだけを失敗させる
+    Nth(u64),
+    /// `n`回に1回(`n - 1`、`2n - 1`、...番目)失敗させる。`0`なら失敗させない
+    EveryNth(u64),
+    /// 指定した番号の呼び出しを失敗させる
+    Calls(Vec<u64>),
+}
+
+impl FaultConfig {
+    fn fails(&self, call: u64) -> bool {
+        match self {
+            FaultConfig::Nth(n) => call == *n,
+            FaultConfig::EveryNth(0) => false,
+            FaultConfig::EveryNth(n) => (call + 1).is_multiple_of(*n),
+            FaultConfig::Calls(calls) => calls.contains(&call),
+        }
+    }
+}
+
+/// 再現できる疑似乱数(SplitMix64)
+#[derive(Debug)]
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// `[0, 1)`の一様乱数
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[derive(Debug)]
+enum Schedule {
+    Config(FaultConfig),
+    Random { probability: f64, rng: SplitMix64 },
+}
+
+#[derive(Debug)]
+pub(super) struct FaultState {
+    schedule: Schedule,
+    /// 次の呼び出しの番号
+    calls: u64,
+}
+
+impl<A> DebugAlloc<A> {
+    /// 確保を伴う操作を`config`に従ってわざと失敗させる
+    ///
+    /// 失敗させた操作は内部の割り当て器を呼ばずに`Err(AllocError)`を返し、
+    /// [`Denial::Fault`](super::Denial::Fault)の付いた失敗として記録する。
+    /// 呼び出しの番号は0から数え直す。メモリ不足の処理を試すのに使う。
+    pub fn set_fault_config(&self, config: FaultConfig) {
+        *self.shared.fault.lock().unwrap() = Some(FaultState {
+            schedule: Schedule::Config(config),
+            calls: 0,
+        });
+    }
+
+    /// 確保を伴う操作をそれぞれ確率`p`でわざと失敗させる
+    ///
+    /// 乱数は`seed`から決まるので、同じ`seed`と同じ操作の列なら同じ呼び出しが失敗する。
+    /// `p`は`0.0`〜`1.0`に丸める。失敗の記録は[`DebugAlloc::set_fault_config`]と同じで、
+    /// [`DebugAlloc::clear_fault_config`]で外す。
+    pub fn fail_with_probability(&self, p: f64, seed: u64) {
+        *self.shared.fault.lock().unwrap() = Some(FaultState {
+            schedule: Schedule::Random {
+                probability: p.clamp(0.0, 1.0),
+                rng: SplitMix64(seed),
+            },
+            calls: 0,
+        });
+    }
+
+    /// [`DebugAlloc::set_fault_config`]と[`DebugAlloc::fail_with_probability`]の設定を外す
+    pub fn clear_fault_config(&self) {
+        *self.shared.fault.lock().unwrap() = None;
+    }
+
+    /// 今回の呼び出しを失敗させるかどうか
+    pub(super) fn inject_fault(&self) -> bool {
+        let Ok(mut fault) = self.shared.fault.lock() else {
+            return false;
+        };
+        let Some(fault) = &mut *fault else {
+            return false;
+        };
+        let call = fault.calls;
+        fault.calls += 1;
+        match &mut fault.schedule {
+            Schedule::Config(config) => config.fails(call),
+            Schedule::Random { probability, rng } => rng.next_f64() < *probability,
+        }
+    }
+}
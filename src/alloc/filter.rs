@@ -0,0 +1,118 @@
+use std::{
+    fmt,
+    io::{self, IsTerminal, Write},
+};
+
+use super::{write_action, Action, DebugAlloc};
+
+type Predicate = Box<dyn Fn(&Action) -> bool + Send + Sync>;
+
+/// [`DebugAlloc::dump_filtered`]などで履歴から操作を選ぶ条件
+///
+/// 設定した条件をすべて満たす操作を選ぶ。何も設定しなければすべての操作を選ぶ。
+#[derive(Default)]
+pub struct HistoryFilter {
+    kinds: Vec<String>,
+    min_size: Option<usize>,
+    addr: Option<usize>,
+    predicate: Option<Predicate>,
+}
+
+impl fmt::Debug for HistoryFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HistoryFilter")
+            .field("kinds", &self.kinds)
+            .field("min_size", &self.min_size)
+            .field("addr", &self.addr)
+            .field("predicate", &self.predicate.as_ref().map(|_| "Fn"))
+            .finish()
+    }
+}
+
+impl HistoryFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 種類([`Kind::name`](super::Kind::name)、`"deallocate"`など)を選ぶ
+    ///
+    /// 複数回呼ぶと、いずれかの種類に当てはまる操作を選ぶ。
+    pub fn kind(mut self, name: &str) -> Self {
+        self.kinds.push(name.to_owned());
+        self
+    }
+
+    /// サイズ(grow/shrinkでは新しいサイズ)が`size`バイト以上の操作を選ぶ
+    pub fn min_size(mut self, size: usize) -> Self {
+        self.min_size = Some(size);
+        self
+    }
+
+    /// アドレスか変更前のアドレスが`addr`である操作を選ぶ
+    pub fn addr<T: ?Sized>(mut self, addr: *const T) -> Self {
+        self.addr = Some(addr as *const () as usize);
+        self
+    }
+
+    /// `predicate`が`true`を返す操作を選ぶ
+    pub fn predicate(
+        mut self,
+        predicate: impl Fn(&Action) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.predicate = Some(Box::new(predicate));
+        self
+    }
+
+    /// `action`が条件をすべて満たすかどうか
+    pub fn matches(&self, action: &Action) -> bool {
+        let addr = |addr: Option<std::ptr::NonNull<()>>| addr.map(|addr| addr.as_ptr() as usize);
+        (self.kinds.is_empty() || self.kinds.iter().any(|kind| kind == action.kind.name()))
+            && self
+                .min_size
+                .is_none_or(|size| action.layout.size() >= size)
+            && self.addr.is_none_or(|target| {
+                addr(action.addr) == Some(target) || addr(action.old_addr) == Some(target)
+            })
+            && self
+                .predicate
+                .as_ref()
+                .is_none_or(|predicate| predicate(action))
+    }
+}
+
+impl<A> DebugAlloc<A> {
+    /// `filter`に当てはまる操作の写し(履歴の順)
+    pub fn filtered(&self, filter: &HistoryFilter) -> Vec<Action> {
+        self.history()
+            .iter()
+            .filter(|action| filter.matches(action))
+            .cloned()
+            .collect()
+    }
+
+    /// `filter`に当てはまる操作を[`DebugAlloc::dump_all_history`]と同じ形式で表示する
+    pub fn dump_filtered(&self, filter: &HistoryFilter) {
+        let color = self.use_color(io::stdout().is_terminal());
+        self.dump_filtered_with(&mut io::stdout(), filter, color)
+            .expect("failed printing to stdout");
+    }
+
+    /// `filter`に当てはまる操作を[`DebugAlloc::dump_filtered`]と同じ形式で`w`に書き出す
+    pub fn dump_filtered_to<W: Write>(&self, w: &mut W, filter: &HistoryFilter) -> io::Result<()> {
+        self.dump_filtered_with(w, filter, self.use_color(false))
+    }
+
+    fn dump_filtered_with<W: Write>(
+        &self,
+        w: &mut W,
+        filter: &HistoryFilter,
+        color: bool,
+    ) -> io::Result<()> {
+        let formatter = self.formatter();
+        let history = self.history();
+        for action in history.iter().rev().filter(|action| filter.matches(action)) {
+            write_action(w, action, &formatter, color)?;
+        }
+        Ok(())
+    }
+}
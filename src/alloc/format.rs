@@ -0,0 +1,212 @@
+use std::{
+    alloc::Layout,
+    fmt::{self, Display},
+    sync::atomic::Ordering,
+};
+
+use super::{fmt_layout, Action, DebugAlloc, Kind};
+
+/// [`Action`]を表示するときの字下げと区切りの設定
+///
+/// [`Action`]の`Display`は[`ActionFormatter::DEFAULT`]を使う。
+/// `{:#}`で表示すると、ログで扱いやすいように1行にまとめる
+/// (`#42 grow 16/4 -> 32/4 @0x7f3e...`)。
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ActionFormatter<'a> {
+    indent: &'a str,
+    separator: &'a str,
+    human_sizes: bool,
+}
+
+impl Default for ActionFormatter<'_> {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl<'a> ActionFormatter<'a> {
+    /// 項目を改行とタブで区切る
+    pub const DEFAULT: Self = Self {
+        indent: "\t",
+        separator: "\n",
+        human_sizes: false,
+    };
+
+    pub const fn new() -> Self {
+        Self::DEFAULT
+    }
+
+    /// 2行目以降の各項目の前に付ける字下げ
+    pub const fn indent(self, indent: &'a str) -> Self {
+        Self { indent, ..self }
+    }
+
+    /// 行の区切り(最後の項目の後にも付く)
+    pub const fn separator(self, separator: &'a str) -> Self {
+        Self { separator, ..self }
+    }
+
+    /// サイズを[`ByteSize`]の形式(`4.0 KiB`など)で表示する
+    pub const fn human_sizes(self, human_sizes: bool) -> Self {
+        Self {
+            human_sizes,
+            ..self
+        }
+    }
+
+    /// `action`をこの設定で表示する値を返す
+    pub fn display<'b>(&'b self, action: &'b Action) -> impl Display + 'b {
+        struct Formatted<'b, 'a>(&'b ActionFormatter<'a>, &'b Action);
+        impl Display for Formatted<'_, '_> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.fmt(self.1, f)
+            }
+        }
+        Formatted(self, action)
+    }
+
+    fn field(&self, f: &mut fmt::Formatter<'_>, name: &str) -> fmt::Result {
+        write!(f, "{}{}{name}: ", self.separator, self.indent)
+    }
+
+    fn layout(&self, f: &mut fmt::Formatter<'_>, layout: Layout) -> fmt::Result {
+        if self.human_sizes {
+            write!(
+                f,
+                "{{ size: {}, align: {} }}",
+                ByteSize(layout.size() as u64),
+                layout.align()
+            )
+        } else {
+            fmt_layout(f, layout)
+        }
+    }
+
+    /// `action`をこの設定で書き出す
+    pub fn fmt(&self, action: &Action, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{} {}", action.seq, action.kind.name())?;
+        if let Some(layout) = action.kind.old_layout() {
+            self.field(f, "old_layout")?;
+            self.layout(f, layout)?;
+        }
+        match action.kind {
+            Kind::Allocate | Kind::AllocateZeroed | Kind::Deallocate => self.field(f, "layout"),
+            Kind::Grow(_) | Kind::GrowZeroed(_) | Kind::Shrink(_) => self.field(f, "new_layout"),
+        }?;
+        self.layout(f, action.layout)?;
+        self.field(f, "address")?;
+        if let Some(addr) = action.addr {
+            write!(f, "{:p}", addr)
+        } else if let Some(denial) = action.denied {
+            write!(f, "Denied ({denial})")
+        } else {
+            write!(f, "Allocation Error")
+        }?;
+        self.field(f, "timestamp")?;
+        write!(f, "{:?}", action.timestamp)?;
+        #[cfg(feature = "backtrace")]
+        if let Some(backtrace) = &action.backtrace {
+            write!(f, "{}{}backtrace:", self.separator, self.indent)?;
+            for frame in backtrace.frames() {
+                write!(f, "{}{}{}{frame}", self.separator, self.indent, self.indent)?;
+            }
+        }
+        f.write_str(self.separator)
+    }
+}
+
+impl<A> DebugAlloc<A> {
+    /// `dump_*`や[`DebugAlloc::report`]でサイズを[`ByteSize`]の形式(`4.0 KiB`など)で
+    /// 表示するかどうかを設定する
+    pub fn set_human_sizes(&self, human_sizes: bool) {
+        self.shared
+            .human_sizes
+            .store(human_sizes, Ordering::Relaxed);
+    }
+
+    /// [`DebugAlloc::set_human_sizes`]の設定
+    pub fn human_sizes(&self) -> bool {
+        self.shared.human_sizes.load(Ordering::Relaxed)
+    }
+
+    /// `dump_*`で使う設定
+    pub(super) fn formatter(&self) -> ActionFormatter<'static> {
+        ActionFormatter::DEFAULT.human_sizes(self.human_sizes())
+    }
+}
+
+/// 1024の冪の単位で表示するバイト数(`512 B`、`4.0 KiB`、`2.5 MiB`など)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ByteSize(pub u64);
+
+impl Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+        if self.0 < 1024 {
+            return write!(f, "{} B", self.0);
+        }
+        let mut value = self.0 as f64 / 1024.0;
+        let mut unit = 0;
+        while value >= 1024.0 && unit + 1 < UNITS.len() {
+            value /= 1024.0;
+            unit += 1;
+        }
+        write!(f, "{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// `action`を1行で書き出す(`{:#}`)
+pub(super) fn fmt_compact(action: &Action, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "#{} {} ", action.seq, action.kind.name())?;
+    if let Some(layout) = action.kind.old_layout() {
+        write!(f, "{}/{} -> ", layout.size(), layout.align())?;
+    }
+    write!(f, "{}/{}", action.layout.size(), action.layout.align())?;
+    if let Some(addr) = action.addr {
+        write!(f, " @{:p}", addr)
+    } else if let Some(denial) = action.denied {
+        write!(f, " denied ({denial})")
+    } else {
+        write!(f, " failed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{Allocator, Layout, System};
+
+    use crate::{ByteSize, DebugAlloc, HistoryFilter};
+
+    #[test]
+    fn byte_size() {
+        assert_eq!(ByteSize(512).to_string(), "512 B");
+        assert_eq!(ByteSize(4096).to_string(), "4.0 KiB");
+        assert_eq!(ByteSize(5 << 19).to_string(), "2.5 MiB");
+    }
+
+    #[test]
+    fn human_sizes_apply_to_every_dump() {
+        let alloc = DebugAlloc::new(System);
+        let layout = Layout::from_size_align(4096, 8).unwrap();
+        let ptr = alloc.allocate(layout).unwrap();
+        alloc.set_human_sizes(true);
+        let mut out = Vec::new();
+        alloc.dump_all_to(&mut out).unwrap();
+        alloc.dump_n_to(&mut out, 1).unwrap();
+        alloc
+            .dump_filtered_to(&mut out, &HistoryFilter::new())
+            .unwrap();
+        alloc.dump_by_thread_to(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.matches("size: 4.0 KiB").count(), 4);
+        assert!(!out.contains("size: 4096"));
+        assert!(alloc.report().to_string().contains("4.0 KiB (4096 bytes)"));
+
+        alloc.set_human_sizes(false);
+        let mut out = Vec::new();
+        alloc.dump_n_to(&mut out, 1).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains("size: 4096"));
+        assert!(!alloc.report().to_string().contains("KiB"));
+        unsafe { alloc.deallocate(ptr.cast(), layout) };
+    }
+}
@@ -0,0 +1,176 @@
+use std::{
+    collections::HashMap,
+    env,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use super::{Action, DebugAlloc, Kind};
+
+/// 呼び出し履歴の根から順のフレーム(記録していなければ`[unknown]`だけ)
+fn frames_of(action: &Action) -> Vec<&str> {
+    #[cfg(feature = "backtrace")]
+    if let Some(backtrace) = &action.backtrace {
+        if !backtrace.frames().is_empty() {
+            return backtrace
+                .frames()
+                .iter()
+                .rev()
+                .map(String::as_str)
+                .collect();
+        }
+    }
+    let _ = action;
+    vec!["[unknown]"]
+}
+
+/// heaptrackの文字列・命令・呼び出し履歴の表を作りながら書き出す
+///
+/// 文字列、命令、呼び出し履歴の番号は1から、確保の情報の番号は0から振る。
+struct Tables<'a, W: Write> {
+    w: &'a mut W,
+    strings: HashMap<&'a str, usize>,
+    /// フレーム → 命令の番号
+    ips: HashMap<&'a str, usize>,
+    /// (親の呼び出し履歴, 命令) → 呼び出し履歴の番号
+    traces: HashMap<(usize, usize), usize>,
+    /// (サイズ, 呼び出し履歴) → 確保の情報の番号
+    infos: HashMap<(usize, usize), usize>,
+    module: usize,
+}
+
+impl<'a, W: Write> Tables<'a, W> {
+    fn string(&mut self, s: &'a str) -> io::Result<usize> {
+        if let Some(&index) = self.strings.get(s) {
+            return Ok(index);
+        }
+        let index = self.strings.len() + 1;
+        writeln!(self.w, "s {s}")?;
+        self.strings.insert(s, index);
+        Ok(index)
+    }
+
+    /// `関数名 at ファイル:行:列`の形式のフレームを命令として登録する
+    fn ip(&mut self, frame: &'a str) -> io::Result<usize> {
+        if let Some(&index) = self.ips.get(frame) {
+            return Ok(index);
+        }
+        let (name, location) = frame.split_once(" at ").unwrap_or((frame, ""));
+        let mut parts = location.rsplitn(3, ':');
+        let (_, line, file) = (parts.next(), parts.next(), parts.next());
+        let line = line.and_then(|line| line.parse::<u32>().ok());
+        let function = self.string(name)?;
+        let index = self.ips.len() + 1;
+        match (file, line) {
+            (Some(file), Some(line)) => {
+                let file = self.string(file)?;
+                writeln!(
+                    self.w,
+                    "i {index:x} {:x} {function:x} {file:x} {line:x}",
+                    self.module
+                )?;
+            }
+            _ => writeln!(self.w, "i {index:x} {:x} {function:x}", self.module)?,
+        }
+        self.ips.insert(frame, index);
+        Ok(index)
+    }
+
+    fn trace(&mut self, frames: &[&'a str]) -> io::Result<usize> {
+        let mut parent = 0;
+        for &frame in frames {
+            let ip = self.ip(frame)?;
+            let next = self.traces.len() + 1;
+            parent = match self.traces.get(&(parent, ip)) {
+                Some(&index) => index,
+                None => {
+                    writeln!(self.w, "t {ip:x} {parent:x}")?;
+                    self.traces.insert((parent, ip), next);
+                    next
+                }
+            };
+        }
+        Ok(parent)
+    }
+
+    fn info(&mut self, size: usize, trace: usize) -> io::Result<usize> {
+        let next = self.infos.len();
+        match self.infos.get(&(size, trace)) {
+            Some(&index) => Ok(index),
+            None => {
+                writeln!(self.w, "a {size:x} {trace:x}")?;
+                self.infos.insert((size, trace), next);
+                Ok(next)
+            }
+        }
+    }
+}
+
+impl<A> DebugAlloc<A> {
+    /// 履歴をheaptrackの形式で`path`に書き出す
+    ///
+    /// 書き出したファイルは`heaptrack_gui`や`heaptrack_print`で開ける。詳しくは
+    /// [`DebugAlloc::write_heaptrack`]を参照。
+    pub fn export_heaptrack(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write_heaptrack(&mut w)?;
+        w.flush()
+    }
+
+    /// 履歴を`heaptrack_interpret`が出力するテキスト形式(ファイル形式の版2)で`w`に書き出す
+    ///
+    /// 圧縮はしないので、必要なら書き出した後にgzipなどで圧縮する。grow/shrinkは
+    /// 元のブロックの解放と新しいブロックの確保として扱い、失敗した操作は書き出さない。
+    /// `backtrace`機能で呼び出し履歴を記録していなければ、すべての確保を1つの
+    /// `[unknown]`にまとめる。
+    pub fn write_heaptrack<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let history = self.history();
+        let cmd = env::args().collect::<Vec<_>>().join(" ");
+        let exe = env::args()
+            .next()
+            .unwrap_or_else(|| "[unknown]".to_string());
+        writeln!(w, "v 10200 2")?;
+        writeln!(w, "X {cmd}")?;
+        let mut tables = Tables {
+            w,
+            strings: HashMap::new(),
+            ips: HashMap::new(),
+            traces: HashMap::new(),
+            infos: HashMap::new(),
+            module: 0,
+        };
+        tables.module = tables.string(&exe)?;
+
+        // アドレス → 確保の情報の番号
+        let mut live = HashMap::<usize, usize>::new();
+        let mut last_ms = None;
+        for action in history.iter() {
+            let Some(addr) = action.addr else {
+                continue;
+            };
+            let ms = action.timestamp.as_millis();
+            if last_ms != Some(ms) {
+                writeln!(tables.w, "c {ms:x}")?;
+                last_ms = Some(ms);
+            }
+            let released = match action.kind {
+                Kind::Deallocate => live.remove(&(addr.as_ptr() as usize)),
+                _ => action
+                    .old_addr
+                    .and_then(|old_addr| live.remove(&(old_addr.as_ptr() as usize))),
+            };
+            if let Some(info) = released {
+                writeln!(tables.w, "- {info:x}")?;
+            }
+            if action.kind == Kind::Deallocate || action.layout.size() == 0 {
+                continue;
+            }
+            let trace = tables.trace(&frames_of(action))?;
+            let info = tables.info(action.layout.size(), trace)?;
+            writeln!(tables.w, "+ {info:x}")?;
+            live.insert(addr.as_ptr() as usize, info);
+        }
+        Ok(())
+    }
+}
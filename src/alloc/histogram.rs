@@ -0,0 +1,128 @@
+use std::fmt::{self, Display};
+
+use super::{DebugAlloc, Kind};
+
+/// 棒グラフの最大の幅
+const BAR_WIDTH: usize = 40;
+
+/// [`SizeHistogram`]の1つの区間
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SizeBucket {
+    /// 区間の最小のサイズ
+    pub min: usize,
+    /// 区間の最大のサイズ(`None`なら上限なし)
+    pub max: Option<usize>,
+    /// 区間に入った確保の数
+    pub count: usize,
+}
+
+/// 確保のサイズの分布
+///
+/// `Display`で区間ごとの棒グラフを表示する。
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SizeHistogram {
+    /// サイズの小さい順の区間
+    pub buckets: Vec<SizeBucket>,
+}
+
+impl SizeHistogram {
+    /// 確保の総数
+    pub fn total(&self) -> usize {
+        self.buckets.iter().map(|bucket| bucket.count).sum()
+    }
+}
+
+impl Display for SizeHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let labels = self
+            .buckets
+            .iter()
+            .map(|bucket| match bucket.max {
+                Some(max) if max == bucket.min => format!("{max}"),
+                Some(max) => format!("{}..={max}", bucket.min),
+                None => format!("{}..", bucket.min),
+            })
+            .collect::<Vec<_>>();
+        let label_width = labels.iter().map(String::len).max().unwrap_or(0);
+        let max_count = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.count)
+            .max()
+            .unwrap_or(0);
+        for (label, bucket) in labels.iter().zip(&self.buckets) {
+            let bar = if max_count == 0 {
+                0
+            } else {
+                (bucket.count * BAR_WIDTH).div_ceil(max_count)
+            };
+            writeln!(
+                f,
+                "{label:>label_width$} | {} {}",
+                "█".repeat(bar),
+                bucket.count
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl<A> DebugAlloc<A> {
+    /// 履歴の確保(`allocate`/`allocate_zeroed`)のサイズの分布
+    ///
+    /// `bounds`は各区間の最大のサイズで、昇順に並べる。`bounds`の最後の値より大きい確保が
+    /// あれば、上限のない区間を最後に加える。
+    pub fn size_histogram(&self, bounds: &[usize]) -> SizeHistogram {
+        let mut buckets = Vec::with_capacity(bounds.len() + 1);
+        let mut min = 0;
+        for &max in bounds {
+            buckets.push(SizeBucket {
+                min,
+                max: Some(max),
+                count: 0,
+            });
+            min = max + 1;
+        }
+        let mut overflow = SizeBucket {
+            min,
+            max: None,
+            count: 0,
+        };
+        for action in self.history().iter() {
+            if !matches!(action.kind, Kind::Allocate | Kind::AllocateZeroed)
+                || action.addr.is_none()
+            {
+                continue;
+            }
+            let size = action.layout.size();
+            let i = bounds.partition_point(|&max| max < size);
+            match buckets.get_mut(i) {
+                Some(bucket) => bucket.count += 1,
+                None => overflow.count += 1,
+            }
+        }
+        if overflow.count != 0 {
+            buckets.push(overflow);
+        }
+        SizeHistogram { buckets }
+    }
+
+    /// 2の冪ごとの区間(`0..=1`、`2`、`3..=4`、`5..=8`、...)で
+    /// [`DebugAlloc::size_histogram`]を求める
+    ///
+    /// 区間は履歴の最大のサイズを含むところまで作る。
+    pub fn size_histogram_pow2(&self) -> SizeHistogram {
+        let max = self
+            .history()
+            .iter()
+            .filter(|action| matches!(action.kind, Kind::Allocate | Kind::AllocateZeroed))
+            .map(|action| action.layout.size())
+            .max()
+            .unwrap_or(0);
+        let bounds = (0..usize::BITS)
+            .map(|i| 1usize << i)
+            .take_while(|&bound| bound / 2 < max)
+            .collect::<Vec<_>>();
+        self.size_histogram(&bounds)
+    }
+}
@@ -0,0 +1,248 @@
+use std::{
+    alloc::{Layout, System},
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use super::{Action, DebugAlloc, Denial, Kind, Shared};
+
+/// 登録されたコールバック
+pub(super) struct Hook<F: ?Sized>(pub(super) Box<F>);
+
+impl<F: ?Sized> fmt::Debug for Hook<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Hook")
+    }
+}
+
+/// 確保を許可するかどうかを決めるコールバック
+///
+/// 呼ぶときは複製してからロックを外すので、コールバックの中で設定し直してもよい。
+pub(super) type AdmissionHook = Arc<Hook<dyn Fn(&AllocRequest) -> bool + Send + Sync>, System>;
+
+#[derive(Debug)]
+pub(super) struct Watch {
+    id: u64,
+    pred: Hook<dyn Fn(&Action) -> bool + Send + Sync>,
+    on_match: Hook<dyn Fn(&Action) + Send + Sync>,
+    /// [`DebugAlloc::set_watch_unsampled`]の設定
+    unsampled: AtomicBool,
+}
+
+/// 登録されている監視の一覧
+///
+/// 通知するときは一覧を複製してからロックを外し、コールバックはロックの外で呼ぶ。
+/// 登録と解除のたびに一覧を作り直す。
+pub(super) type Watches = Arc<[Arc<Watch, System>], System>;
+
+pub(super) fn empty_watches() -> Watches {
+    Arc::from(Vec::new_in(System))
+}
+
+/// [`DebugAlloc::watch`]で登録した監視を外すためのハンドル
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct WatchHandle(u64);
+
+/// 内部の割り当て器に渡す前の確保の要求
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct AllocRequest {
+    pub kind: Kind,
+    /// 要求されたレイアウト(grow/shrinkでは新しいレイアウト)
+    pub layout: Layout,
+    /// grow/shrinkの変更前のレイアウト
+    pub old_layout: Option<Layout>,
+    /// 要求の時点で生存中の確保の合計バイト数
+    pub live_bytes: u64,
+}
+
+impl Shared {
+    fn find_watch(&self, handle: WatchHandle) -> Option<Arc<Watch, System>> {
+        let watches = self.watches.read().ok()?;
+        watches.iter().find(|watch| watch.id == handle.0).cloned()
+    }
+
+    pub(super) fn unwatch(&self, handle: WatchHandle) -> bool {
+        let mut watches = self.watches.write().unwrap();
+        let mut list = Vec::with_capacity_in(watches.len(), System);
+        list.extend(watches.iter().filter(|watch| watch.id != handle.0).cloned());
+        if list.len() == watches.len() {
+            return false;
+        }
+        *watches = Arc::from(list);
+        true
+    }
+}
+
+impl<A> DebugAlloc<A> {
+    /// 確保(allocate/grow/shrink)を許可するかどうかを決めるコールバックを設定する
+    ///
+    /// `hook`が`false`を返すと、内部の割り当て器を呼ばずに`Err(AllocError)`を返し、
+    /// [`Denial::Hook`]の付いた失敗として記録する。`hook`は設定のロックの外で呼ぶので、
+    /// `hook`の中で設定し直したり外したりしてもよい。
+    pub fn set_admission_hook(&self, hook: impl Fn(&AllocRequest) -> bool + Send + Sync + 'static) {
+        let hook: AdmissionHook = Arc::new_in(Hook(Box::new(hook)), System);
+        *self.shared.admission_hook.write().unwrap() = Some(hook);
+    }
+
+    /// [`DebugAlloc::set_admission_hook`]で設定したコールバックを外す
+    pub fn clear_admission_hook(&self) {
+        *self.shared.admission_hook.write().unwrap() = None;
+    }
+
+    /// 記録した操作が`pred`を満たしたときに、すぐに`on_match`を呼ぶ
+    ///
+    /// コールバックは履歴のロックの外で、操作したスレッド上で呼ばれる。
+    /// 間引きなどで履歴に入らない操作も対象になる。複数登録できる。
+    /// 監視の一覧のロックも外して呼ぶので、コールバックの中で登録や解除をしてもよい。
+    /// ただし外した直後に、他のスレッドで実行中の通知から一度呼ばれることがある。
+    pub fn watch(
+        &self,
+        pred: impl Fn(&Action) -> bool + Send + Sync + 'static,
+        on_match: impl Fn(&Action) + Send + Sync + 'static,
+    ) -> WatchHandle {
+        let id = self.shared.next_watch_id.fetch_add(1, Ordering::Relaxed);
+        let watch = Arc::new_in(
+            Watch {
+                id,
+                pred: Hook(Box::new(pred)),
+                on_match: Hook(Box::new(on_match)),
+                unsampled: AtomicBool::new(false),
+            },
+            System,
+        );
+        let mut watches = self.shared.watches.write().unwrap();
+        let mut list = Vec::with_capacity_in(watches.len() + 1, System);
+        list.extend(watches.iter().cloned());
+        list.push(watch);
+        *watches = Arc::from(list);
+        WatchHandle(id)
+    }
+
+    /// 操作を記録するたびに`f`を呼ぶ
+    ///
+    /// 各`Allocator`のメソッドの中で、集計を更新した後に操作したスレッド上で呼ばれる。
+    /// 数えたり、条件を確かめてその場でpanicしたりするのに使う。
+    /// 条件付きの[`DebugAlloc::watch`]と同じもので、[`DebugAlloc::unwatch`]で外す。
+    pub fn on_action(&self, f: impl Fn(&Action) + Send + Sync + 'static) -> WatchHandle {
+        self.watch(|_| true, f)
+    }
+
+    /// `handle`の監視を[`DebugAlloc::set_notify_sample`]で間引かず、毎回呼ぶかどうかを設定する
+    ///
+    /// [`Receiver::watch_handle`](super::Receiver::watch_handle)を渡せば、受信側が
+    /// すべての操作を受け取るようにできる。既に外されていれば`false`を返す。
+    pub fn set_watch_unsampled(&self, handle: WatchHandle, unsampled: bool) -> bool {
+        let Some(watch) = self.shared.find_watch(handle) else {
+            return false;
+        };
+        watch.unsampled.store(unsampled, Ordering::Relaxed);
+        true
+    }
+
+    /// [`DebugAlloc::watch`]で登録した監視を外す
+    ///
+    /// 既に外されていれば`false`を返す。
+    pub fn unwatch(&self, handle: WatchHandle) -> bool {
+        self.shared.unwatch(handle)
+    }
+
+    /// 監視に操作を知らせる。`sampled`が`false`なら間引かない監視だけに送る
+    pub(super) fn notify_watches(&self, action: &Action, sampled: bool) {
+        // コールバックの中で確保したスレッドが止まっても登録や解除を妨げないように、
+        // 一覧を複製してロックを外す
+        let Ok(watches) = self.shared.watches.read().map(|watches| watches.clone()) else {
+            return;
+        };
+        for watch in watches.iter() {
+            if (sampled || watch.unsampled.load(Ordering::Relaxed)) && (watch.pred.0)(action) {
+                (watch.on_match.0)(action);
+            }
+        }
+    }
+
+    /// 確保を許可しなければ理由を返す
+    pub(super) fn admit(&self, kind: Kind, layout: Layout) -> Option<Denial> {
+        if self.inject_fault() {
+            return Some(Denial::Fault);
+        }
+        if self.over_budget(kind, layout) {
+            return Some(Denial::Budget);
+        }
+        if self.rejected_by_hook(kind, layout) {
+            return Some(Denial::Hook);
+        }
+        // 許可する確保だけを累計に数えるように最後に確かめる
+        self.over_quota(kind, layout).then_some(Denial::Quota)
+    }
+
+    /// 設定されたコールバックが確保を拒否すれば`true`
+    fn rejected_by_hook(&self, kind: Kind, layout: Layout) -> bool {
+        let hook = match self.shared.admission_hook.read() {
+            Ok(hook) => hook.clone(),
+            Err(_) => None,
+        };
+        let Some(hook) = hook else {
+            return false;
+        };
+        let request = AllocRequest {
+            kind,
+            layout,
+            old_layout: kind.old_layout(),
+            live_bytes: self.shared.tracker.read().map_or(0, |t| t.live_bytes),
+        };
+        !(hook.0)(&request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{Allocator, Layout, System};
+
+    use crate::DebugAlloc;
+
+    fn churn(alloc: &DebugAlloc<System>, n: usize) {
+        let layout = Layout::new::<u64>();
+        for _ in 0..n {
+            let ptr = alloc.allocate(layout).unwrap();
+            unsafe { alloc.deallocate(ptr.cast(), layout) };
+        }
+    }
+
+    #[test]
+    fn notify_sample_thins_subscribers() {
+        let alloc = DebugAlloc::new(System);
+        alloc.set_notify_sample(4);
+        let sampled = alloc.subscribe();
+        let unsampled = alloc.subscribe();
+        assert!(alloc.set_watch_unsampled(unsampled.watch_handle(), true));
+        churn(&alloc, 8);
+        assert_eq!(sampled.len(), 4);
+        assert_eq!(unsampled.len(), 16);
+        assert_eq!(alloc.history().len(), 16);
+    }
+
+    #[test]
+    fn admission_hook_can_clear_itself() {
+        let alloc = DebugAlloc::new(System);
+        let inner = alloc.clone();
+        alloc.set_admission_hook(move |_| {
+            inner.clear_admission_hook();
+            false
+        });
+        let layout = Layout::new::<u64>();
+        assert!(alloc.allocate(layout).is_err());
+        let ptr = alloc.allocate(layout).unwrap();
+        unsafe { alloc.deallocate(ptr.cast(), layout) };
+    }
+
+    #[test]
+    fn set_watch_unsampled_after_unwatch() {
+        let alloc = DebugAlloc::new(System);
+        let handle = alloc.on_action(|_| {});
+        assert!(alloc.unwatch(handle));
+        assert!(!alloc.set_watch_unsampled(handle, true));
+    }
+}
@@ -0,0 +1,139 @@
+//! 出力用の最小限のJSON書き出し
+
+use std::io::{self, Write};
+
+use super::{Action, DebugAlloc};
+
+/// `s`をJSONの文字列リテラルとして書き出す
+pub(super) fn write_str<W: Write + ?Sized>(w: &mut W, s: &str) -> io::Result<()> {
+    w.write_all(b"\"")?;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        let escaped = match c {
+            '"' => "\\\"",
+            '\\' => "\\\\",
+            '\n' => "\\n",
+            '\r' => "\\r",
+            '\t' => "\\t",
+            c if c.is_control() => {
+                w.write_all(&s.as_bytes()[start..i])?;
+                write!(w, "\\u{:04x}", c as u32)?;
+                start = i + c.len_utf8();
+                continue;
+            }
+            _ => continue,
+        };
+        w.write_all(&s.as_bytes()[start..i])?;
+        w.write_all(escaped.as_bytes())?;
+        start = i + c.len_utf8();
+    }
+    w.write_all(&s.as_bytes()[start..])?;
+    w.write_all(b"\"")
+}
+
+fn write_opt<W: Write + ?Sized>(
+    w: &mut W,
+    value: Option<impl std::fmt::Display>,
+) -> io::Result<()> {
+    match value {
+        Some(value) => write!(w, "{value}"),
+        None => w.write_all(b"null"),
+    }
+}
+
+/// 操作を1つのJSONオブジェクトとして書き出す
+///
+/// アドレスは整数で、存在しなければ`null`になる。
+pub(super) fn write_action<W: Write + ?Sized>(w: &mut W, action: &Action) -> io::Result<()> {
+    let old_layout = action.kind.old_layout();
+    write!(w, "{{\"seq\":{},\"kind\":", action.seq)?;
+    write_str(w, action.kind.name())?;
+    write!(
+        w,
+        ",\"size\":{},\"align\":{},\"old_size\":",
+        action.layout.size(),
+        action.layout.align()
+    )?;
+    write_opt(w, old_layout.map(|layout| layout.size()))?;
+    write!(w, ",\"old_align\":")?;
+    write_opt(w, old_layout.map(|layout| layout.align()))?;
+    write!(w, ",\"addr\":")?;
+    write_opt(w, action.addr.map(|addr| addr.as_ptr() as usize))?;
+    write!(w, ",\"old_addr\":")?;
+    write_opt(w, action.old_addr.map(|addr| addr.as_ptr() as usize))?;
+    write!(w, ",\"len\":{},\"denied\":", action.len)?;
+    match action.denied {
+        Some(denial) => write_str(w, &denial.to_string())?,
+        None => w.write_all(b"null")?,
+    }
+    write!(
+        w,
+        ",\"timestamp_ns\":{},\"epoch\":{},\"thread_id\":{},\"thread_name\":",
+        action.timestamp.as_nanos(),
+        action.epoch,
+        action.thread_id
+    )?;
+    match action.thread_name {
+        Some(name) => write_str(w, name.as_str())?,
+        None => w.write_all(b"null")?,
+    }
+    #[cfg(feature = "backtrace")]
+    {
+        w.write_all(b",\"backtrace\":")?;
+        match &action.backtrace {
+            Some(backtrace) => {
+                w.write_all(b"[")?;
+                for (i, frame) in backtrace.frames().iter().enumerate() {
+                    if i != 0 {
+                        w.write_all(b",")?;
+                    }
+                    write_str(w, frame)?;
+                }
+                w.write_all(b"]")?;
+            }
+            None => w.write_all(b"null")?,
+        }
+    }
+    w.write_all(b"}")
+}
+
+/// 操作の列をJSONの配列として書き出す
+pub(super) fn write_actions<'a, W: Write + ?Sized>(
+    w: &mut W,
+    actions: impl IntoIterator<Item = &'a Action>,
+) -> io::Result<()> {
+    w.write_all(b"[")?;
+    for (i, action) in actions.into_iter().enumerate() {
+        if i != 0 {
+            w.write_all(b",")?;
+        }
+        w.write_all(b"\n")?;
+        write_action(w, action)?;
+    }
+    w.write_all(b"\n]")
+}
+
+impl<A> DebugAlloc<A> {
+    /// 履歴全体を古い順にJSONの配列として書き出す
+    ///
+    /// 各要素は次のキーを持つオブジェクト:
+    ///
+    /// | キー | 型 | 内容 |
+    /// |---|---|---|
+    /// | `seq` | 整数 | 通し番号 |
+    /// | `kind` | 文字列 | `allocate`、`deallocate`、`allocate_zeroed`、`grow`、`grow_zeroed`、`shrink` |
+    /// | `size`, `align` | 整数 | レイアウト(grow/shrinkでは新しいレイアウト) |
+    /// | `old_size`, `old_align` | 整数または`null` | grow/shrinkの変更前のレイアウト |
+    /// | `addr` | 整数または`null` | 返したアドレス(失敗なら`null`) |
+    /// | `old_addr` | 整数または`null` | grow/shrinkの変更前のアドレス |
+    /// | `len` | 整数 | 内部の割り当て器が返したスライスの長さ |
+    /// | `denied` | 文字列または`null` | 内部の割り当て器を呼ばずに失敗させた理由 |
+    /// | `timestamp_ns` | 整数 | 経過時間[ns] |
+    /// | `epoch` | 整数 | エポック |
+    /// | `thread_id` | 整数 | スレッドID |
+    /// | `thread_name` | 文字列または`null` | スレッド名 |
+    /// | `backtrace` | 文字列の配列または`null` | 呼び出し履歴(`backtrace`機能が有効なときだけ) |
+    pub fn export_json<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_actions(w, self.history().iter())
+    }
+}
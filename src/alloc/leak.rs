@@ -0,0 +1,70 @@
+use std::{fmt::Write, thread};
+
+use super::{Action, DebugAlloc, Tracker};
+
+/// 最後のハンドルを破棄したときに、生存中の確保が残っていた場合の扱い
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum LeakCheck {
+    /// 何もしない
+    #[default]
+    Off,
+    /// 残っている確保を標準エラー出力に表示する
+    Log,
+    /// 残っている確保を表示してpanicする
+    Panic,
+}
+
+impl<A> DebugAlloc<A> {
+    /// まだ解放されていない確保(確保した順)
+    ///
+    /// grow/shrinkで移動したブロックは移動先のアドレスとレイアウトで返す。
+    /// 各要素はそのブロックを最後に確保またはサイズ変更した操作
+    pub fn live_allocations(&self) -> Vec<Action> {
+        self.live_since(0)
+    }
+
+    /// まだ解放されていない確保の合計バイト数
+    ///
+    /// すべて解放したはずの時点で呼ぶと、リークしたバイト数になる。
+    pub fn leaked_bytes(&self) -> u64 {
+        self.live_bytes()
+    }
+
+    /// 有効にすると、最後のハンドルを破棄したときに生存中の確保が残っていればpanicする
+    ///
+    /// 既にpanic中のスレッドで破棄された場合は、二重panicを避けて表示だけにする。
+    pub fn assert_all_freed_on_drop(&self, enabled: bool) {
+        self.set_leak_check(if enabled {
+            LeakCheck::Panic
+        } else {
+            LeakCheck::Off
+        });
+    }
+
+    /// 最後のハンドルを破棄したときに、生存中の確保が残っていた場合の扱いを設定する
+    pub fn set_leak_check(&self, check: LeakCheck) {
+        self.shared.tracker.write().unwrap().leak_check = check;
+    }
+}
+
+impl Drop for Tracker {
+    fn drop(&mut self) {
+        if self.leak_check == LeakCheck::Off || self.live.is_empty() {
+            return;
+        }
+        let mut leaked = self.live.values().collect::<Vec<_>>();
+        leaked.sort_unstable_by_key(|action| action.seq);
+        let mut message = format!(
+            "{} allocation(s) ({} bytes) still live when the allocator was dropped:\n",
+            leaked.len(),
+            self.live_bytes
+        );
+        for action in leaked {
+            let _ = writeln!(message, "{action}");
+        }
+        if self.leak_check == LeakCheck::Panic && !thread::panicking() {
+            panic!("{message}");
+        }
+        eprintln!("{message}");
+    }
+}
@@ -0,0 +1,20 @@
+use std::fs;
+
+use super::DebugAlloc;
+use crate::efence::page_size;
+
+impl<A> DebugAlloc<A> {
+    /// プロセスの常駐メモリのバイト数(`/proc/self/statm`の常駐ページ数×ページサイズ)
+    pub fn process_rss() -> Option<u64> {
+        let statm = fs::read_to_string("/proc/self/statm").ok()?;
+        let resident = statm.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+        Some(resident * page_size() as u64)
+    }
+
+    /// 常駐メモリのうち、追跡している生存中の確保では説明できないバイト数
+    ///
+    /// スタックやmmap、内部の割り当て器のオーバーヘッドなど、追跡していないメモリの目安。
+    pub fn tracking_gap(&self) -> Option<i64> {
+        Some(Self::process_rss()? as i64 - self.live_bytes() as i64)
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+
+#[cfg(feature = "json")]
+use super::json;
+use super::{Action, DebugAlloc, Kind};
+
+const PAGE_SIZE: usize = 4096;
+
+/// 生存中の確保が4KiBページにどれだけ密に詰まっているか
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ClusteringStats {
+    /// 生存中の確保が触れている異なるページの数
+    pub pages: usize,
+    /// 生存中の確保の数
+    pub allocations: usize,
+    /// 1ページあたりの確保の数の平均
+    pub allocations_per_page: f64,
+}
+
+/// [`DebugAlloc::reconcile`]の結果
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ReconcileReport {
+    /// 修正前の生存バイト数のカウンタ
+    pub counted_bytes: u64,
+    /// 生存中の確保の一覧から計算し直したバイト数
+    pub actual_bytes: u64,
+    /// 生存中の確保の数
+    pub live_allocations: usize,
+    /// カウンタのずれ(`counted_bytes - actual_bytes`)
+    pub drift: i64,
+}
+
+impl ReconcileReport {
+    /// ずれがなかったかどうか
+    pub fn is_consistent(&self) -> bool {
+        self.drift == 0
+    }
+}
+
+impl<A> DebugAlloc<A> {
+    /// 生存中の確保のアドレスからページの使われ方を集計する
+    ///
+    /// 割り当て器の空間的局所性の目安になる。
+    pub fn address_clustering(&self) -> ClusteringStats {
+        let tracker = self.shared.tracker.read().unwrap();
+        let live = &tracker.live;
+        let mut pages = live
+            .iter()
+            .flat_map(|(&addr, action)| {
+                let last = addr + action.layout.size() - 1;
+                addr / PAGE_SIZE..=last / PAGE_SIZE
+            })
+            .collect::<Vec<_>>();
+        pages.sort_unstable();
+        pages.dedup();
+        let allocations = live.len();
+        ClusteringStats {
+            pages: pages.len(),
+            allocations,
+            allocations_per_page: if pages.is_empty() {
+                0.0
+            } else {
+                allocations as f64 / pages.len() as f64
+            },
+        }
+    }
+
+    /// 今すべてを解放したときに回収されるバイト数
+    ///
+    /// 生存中の確保の一覧から計算し直すので、[`DebugAlloc::live_bytes`]の検算になる。
+    pub fn reclaimable_bytes(&self) -> u64 {
+        let tracker = self.shared.tracker.read().unwrap();
+        let bytes = tracker
+            .live
+            .values()
+            .map(|action| action.layout.size() as u64)
+            .sum();
+        debug_assert_eq!(bytes, tracker.live_bytes, "live_bytes is out of sync");
+        bytes
+    }
+
+    /// 生存バイト数のカウンタを生存中の確保の一覧と突き合わせ、ずれていれば一覧に合わせて直す
+    pub fn reconcile(&self) -> ReconcileReport {
+        let mut tracker = self.shared.tracker.write().unwrap();
+        let actual_bytes = tracker
+            .live
+            .values()
+            .map(|action| action.layout.size() as u64)
+            .sum::<u64>();
+        let report = ReconcileReport {
+            counted_bytes: tracker.live_bytes,
+            actual_bytes,
+            live_allocations: tracker.live.len(),
+            drift: tracker.live_bytes as i64 - actual_bytes as i64,
+        };
+        tracker.live_bytes = actual_bytes;
+        report
+    }
+
+    /// 生存中の確保について、アライメントを満たすための詰め物のバイト数を見積もる
+    ///
+    /// サイズがアライメントの倍数でない確保ごとに`align - size % align`を足し合わせる。
+    /// 内部の割り当て器が実際にどれだけ余分に確保するかは実装次第なので、
+    /// あくまでサイズに対して過大なアライメントを要求している確保を見つけるための目安で、
+    /// 実際の無駄とは一致しない。
+    pub fn alignment_waste_estimate(&self) -> u64 {
+        self.shared
+            .tracker
+            .read()
+            .unwrap()
+            .live
+            .values()
+            .map(|action| {
+                let (size, align) = (action.layout.size(), action.layout.align());
+                match size % align {
+                    0 => 0,
+                    rem => (align - rem) as u64,
+                }
+            })
+            .sum()
+    }
+
+    /// 先頭のアドレスが`addr`以下で最も近い生存中のブロックを返す
+    ///
+    /// 不正なアクセスで落ちたアドレスから、はみ出したブロックを探すのに使う。
+    pub fn live_allocation_at_or_before<T: ?Sized>(&self, addr: *const T) -> Option<Action> {
+        let tracker = self.shared.tracker.read().unwrap();
+        tracker
+            .live
+            .range(..=addr as *const () as usize)
+            .next_back()
+            .map(|(_, action)| action.clone())
+    }
+
+    /// アドレス範囲が重なっている生存中の確保の組を返す
+    ///
+    /// 範囲は内部の割り当て器が返したスライスの長さ(要求したサイズより短ければ要求したサイズ)で
+    /// 判定する。正しい割り当て器なら常に空になる。
+    pub fn overlapping_allocations(&self) -> Vec<(Action, Action)> {
+        let mut ranges = self
+            .shared
+            .tracker
+            .read()
+            .unwrap()
+            .live
+            .iter()
+            .map(|(&addr, action)| {
+                (
+                    addr,
+                    addr + action.len.max(action.layout.size()),
+                    action.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        ranges.sort_unstable_by_key(|&(start, end, _)| (start, end));
+
+        let mut overlaps = Vec::new();
+        let mut active: Vec<&(usize, usize, Action)> = Vec::new();
+        for range in &ranges {
+            active.retain(|&&(_, end, _)| end > range.0);
+            for &(_, _, other) in &active {
+                overlaps.push((other.clone(), range.2.clone()));
+            }
+            active.push(range);
+        }
+        overlaps
+    }
+
+    /// 通し番号`seq`の操作より前に確保されて、`seq`の時点でまだ解放されていないブロックの
+    /// 確保操作を返す(通し番号順)
+    ///
+    /// 履歴を先頭から再生して求めるので、削除や間引きで欠けた操作は反映されない。
+    pub fn survivors_across(&self, seq: u64) -> Vec<Action> {
+        // 現在のアドレス → 最初の確保操作
+        let mut live = HashMap::<usize, Action>::new();
+        for action in self.history().iter().take_while(|action| action.seq <= seq) {
+            let Some(addr) = action.addr else {
+                continue;
+            };
+            let addr = addr.as_ptr() as usize;
+            match action.kind {
+                Kind::Allocate | Kind::AllocateZeroed => {
+                    if action.seq < seq && action.layout.size() != 0 {
+                        live.insert(addr, action.clone());
+                    }
+                }
+                Kind::Deallocate => {
+                    live.remove(&addr);
+                }
+                Kind::Grow(_) | Kind::GrowZeroed(_) | Kind::Shrink(_) => {
+                    let old_addr = action.old_addr.map(|ptr| ptr.as_ptr() as usize);
+                    if let Some(origin) = old_addr.and_then(|old_addr| live.remove(&old_addr)) {
+                        live.insert(addr, origin);
+                    }
+                }
+            }
+        }
+        let mut survivors = live.into_values().collect::<Vec<_>>();
+        survivors.sort_unstable_by_key(|action| action.seq);
+        survivors
+    }
+
+    /// 生存中の確保だけをJSONの配列にする(通し番号順)
+    ///
+    /// 各要素はそのブロックを最後に返した操作(確保またはgrow/shrink)で、
+    /// [`DebugAlloc::export_json`]と同じキーを持つ。
+    /// 全履歴よりずっと小さく、リークの解析にはこれで足りることが多い。
+    #[cfg(feature = "json")]
+    pub fn live_to_json(&self) -> String {
+        let live = self.live_since(0);
+        let mut buf = Vec::new();
+        json::write_actions(&mut buf, &live).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use std::alloc::{Allocator, Layout, System};
+
+    use crate::DebugAlloc;
+
+    #[test]
+    fn live_to_json_has_only_live_blocks() {
+        let alloc = DebugAlloc::new(System);
+        let layout = Layout::new::<u64>();
+        let freed = alloc.allocate(layout).unwrap();
+        let live = alloc.allocate(layout).unwrap();
+        unsafe { alloc.deallocate(freed.cast(), layout) };
+        let json = alloc.live_to_json();
+        assert_eq!(json.matches("\"seq\":").count(), 1);
+        assert!(json.contains("{\"seq\":1,\"kind\":\"allocate\",\"size\":8,"));
+        assert!(json.contains(&format!(
+            "\"addr\":{},",
+            live.cast::<u8>().as_ptr() as usize
+        )));
+        unsafe { alloc.deallocate(live.cast(), layout) };
+    }
+}
@@ -0,0 +1,130 @@
+use std::{
+    collections::HashMap,
+    env,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use super::{Action, DebugAlloc, Kind};
+
+/// 書き出すスナップショットの最大数(最大値のスナップショットを除く)
+const MAX_SNAPSHOTS: usize = 100;
+
+/// 確保した場所の名前(呼び出し履歴がなければ`[unknown]`)
+fn site_of(action: &Action) -> String {
+    #[cfg(feature = "backtrace")]
+    if let Some(frame) = action
+        .backtrace
+        .as_ref()
+        .and_then(|backtrace| backtrace.frames().first())
+    {
+        return frame.clone();
+    }
+    let _ = action;
+    "[unknown]".to_string()
+}
+
+impl<A> DebugAlloc<A> {
+    /// 履歴をValgrindのmassifの形式で`path`に書き出す
+    ///
+    /// 書き出したファイルは`ms_print`やmassif-visualizerで読める。詳しくは
+    /// [`DebugAlloc::write_massif`]を参照。
+    pub fn export_massif(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write_massif(&mut w)?;
+        w.flush()
+    }
+
+    /// 履歴の生存バイト数の推移をmassifのスナップショットとして`w`に書き出す
+    ///
+    /// 時間の単位はms。履歴から等間隔に最大100個のスナップショットを取り、
+    /// 生存バイト数が最大になった時点を`peak`として加える。`peak`のスナップショットだけは
+    /// 生存中のブロックを確保した場所(呼び出し履歴の先頭のフレーム)ごとに分けて出す。
+    /// 生存バイト数は履歴の先頭を0として積み上げる。
+    pub fn write_massif<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let history = self.history();
+        let mut live = 0u64;
+        let timeline = history
+            .iter()
+            .map(|action| {
+                live = live.saturating_add_signed(action.net_bytes());
+                live
+            })
+            .collect::<Vec<_>>();
+        let peak = timeline
+            .iter()
+            .enumerate()
+            .max_by_key(|&(i, &bytes)| (bytes, std::cmp::Reverse(i)))
+            .map(|(i, _)| i);
+
+        // 最大値の時点で生存していたブロックを場所ごとに集計する
+        let mut sites = Vec::<(String, u64)>::new();
+        if let Some(peak) = peak {
+            let mut blocks = HashMap::<usize, &Action>::new();
+            for action in history.range(..=peak) {
+                let Some(addr) = action.addr else {
+                    continue;
+                };
+                match action.kind {
+                    Kind::Deallocate => {
+                        blocks.remove(&(addr.as_ptr() as usize));
+                        continue;
+                    }
+                    _ => {
+                        if let Some(old_addr) = action.old_addr {
+                            blocks.remove(&(old_addr.as_ptr() as usize));
+                        }
+                    }
+                }
+                if action.layout.size() != 0 {
+                    blocks.insert(addr.as_ptr() as usize, action);
+                }
+            }
+            let mut by_site = HashMap::<String, u64>::new();
+            for action in blocks.values() {
+                *by_site.entry(site_of(action)).or_default() += action.layout.size() as u64;
+            }
+            sites.extend(by_site);
+            sites.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        }
+
+        writeln!(w, "desc: (none)")?;
+        writeln!(w, "cmd: {}", env::args().collect::<Vec<_>>().join(" "))?;
+        writeln!(w, "time_unit: ms")?;
+
+        let step = timeline.len().div_ceil(MAX_SNAPSHOTS).max(1);
+        let mut indices = (0..timeline.len()).step_by(step).collect::<Vec<_>>();
+        if let Some(last) = timeline.len().checked_sub(1) {
+            if indices.last() != Some(&last) {
+                indices.push(last);
+            }
+        }
+        if let Some(peak) = peak {
+            if let Err(i) = indices.binary_search(&peak) {
+                indices.insert(i, peak);
+            }
+        }
+        for (n, &i) in indices.iter().enumerate() {
+            writeln!(w, "#-----------\nsnapshot={n}\n#-----------")?;
+            writeln!(w, "time={}", history[i].timestamp.as_millis())?;
+            writeln!(w, "mem_heap_B={}", timeline[i])?;
+            writeln!(w, "mem_heap_extra_B=0\nmem_stacks_B=0")?;
+            if Some(i) != peak {
+                writeln!(w, "heap_tree=empty")?;
+                continue;
+            }
+            writeln!(w, "heap_tree=peak")?;
+            writeln!(
+                w,
+                "n{}: {} (heap allocation functions) malloc/new/new[], --alloc-fns, etc.",
+                sites.len(),
+                timeline[i]
+            )?;
+            for (site, bytes) in &sites {
+                writeln!(w, " n0: {bytes} 0x0: {site}")?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+use super::{AllocStats, DebugAlloc};
+
+/// [`DebugAlloc::measure`]で実行した処理の間の確保の集計
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct MeasureReport {
+    /// 処理の間の操作の回数とバイト数(`live_*`は0)
+    pub stats: AllocStats,
+    /// 処理の前後での生存バイト数の増減
+    pub net_live_bytes: i64,
+    /// 処理の間に生存バイト数が開始時より最大でどれだけ増えたか
+    pub peak_delta: u64,
+}
+
+/// panicしても計測中の最大値の記録を外す
+struct PeakGuard<'a, A> {
+    alloc: &'a DebugAlloc<A>,
+    id: u64,
+}
+
+impl<A> PeakGuard<'_, A> {
+    fn take(&self) -> u64 {
+        let mut tracker = self.alloc.shared.tracker.write().unwrap();
+        let i = tracker
+            .measure_peaks
+            .iter()
+            .position(|&(id, _)| id == self.id)
+            .unwrap();
+        tracker.measure_peaks.swap_remove(i).1
+    }
+}
+
+impl<A> Drop for PeakGuard<'_, A> {
+    fn drop(&mut self) {
+        if let Ok(mut tracker) = self.alloc.shared.tracker.write() {
+            tracker.measure_peaks.retain(|&(id, _)| id != self.id);
+        }
+    }
+}
+
+impl<A> DebugAlloc<A> {
+    /// `f`を実行し、その結果と実行中の確保の集計を返す
+    ///
+    /// 集計は実行の前後の差なので、`f`の実行中に他のスレッドがこの割り当て器で行った
+    /// 操作も含まれる。`f`だけの値を得るには、他のスレッドが確保しない状態で呼ぶこと。
+    pub fn measure<R>(&self, f: impl FnOnce() -> R) -> (R, MeasureReport) {
+        let (before, live_before, guard) = {
+            let mut tracker = self.shared.tracker.write().unwrap();
+            let id = tracker.next_measure_id;
+            tracker.next_measure_id += 1;
+            let live_bytes = tracker.live_bytes;
+            tracker.measure_peaks.push((id, live_bytes));
+            (tracker.stats, live_bytes, PeakGuard { alloc: self, id })
+        };
+        let result = f();
+        let peak = guard.take();
+        let tracker = self.shared.tracker.read().unwrap();
+        let report = MeasureReport {
+            stats: tracker.stats.since(&before),
+            net_live_bytes: tracker.live_bytes as i64 - live_before as i64,
+            peak_delta: peak.saturating_sub(live_before),
+        };
+        (result, report)
+    }
+}
@@ -0,0 +1,82 @@
+use std::{alloc::Layout, collections::HashMap};
+
+use super::{Action, DebugAlloc, Kind};
+
+/// [`DebugAlloc::alloc_free_pingpong`]で「すぐに解放された」とみなす間に挟まる操作の数
+pub const DEFAULT_PINGPONG_GAP: usize = 2;
+
+impl<A> DebugAlloc<A> {
+    /// 確保してすぐに同じレイアウトで解放する、という組が`min_occurrences`回以上あった
+    /// レイアウトと回数を返す
+    ///
+    /// 間に挟まる操作が[`DEFAULT_PINGPONG_GAP`]個以下のものを数える。
+    /// バッファをループの外に出して使い回せる箇所の目印になる。
+    pub fn alloc_free_pingpong(&self, min_occurrences: usize) -> Vec<(Layout, usize)> {
+        self.alloc_free_pingpong_within(min_occurrences, DEFAULT_PINGPONG_GAP)
+    }
+
+    /// 間に挟まる操作が`max_gap`個以下のものを数える[`DebugAlloc::alloc_free_pingpong`]
+    pub fn alloc_free_pingpong_within(
+        &self,
+        min_occurrences: usize,
+        max_gap: usize,
+    ) -> Vec<(Layout, usize)> {
+        let history = self.history();
+        let mut counts = HashMap::<Layout, usize>::new();
+        for (i, action) in history.iter().enumerate() {
+            let (Kind::Allocate | Kind::AllocateZeroed, Some(addr)) = (action.kind, action.addr)
+            else {
+                continue;
+            };
+            let freed = history
+                .range(i + 1..)
+                .take(max_gap + 1)
+                .find(|other| other.addr == Some(addr));
+            if let Some(free) = freed {
+                if free.kind == Kind::Deallocate && free.layout == action.layout {
+                    *counts.entry(action.layout).or_insert(0) += 1;
+                }
+            }
+        }
+        let mut result = counts
+            .into_iter()
+            .filter(|&(_, count)| count >= min_occurrences)
+            .collect::<Vec<_>>();
+        result.sort_unstable_by_key(|&(layout, count)| {
+            (usize::MAX - count, layout.size(), layout.align())
+        });
+        result
+    }
+
+    /// 確保した後、使われないまま解放されたと思われるブロックの確保操作を返す
+    ///
+    /// メモリへのアクセスは観測できないので、次の条件を満たすものを候補とする。
+    ///
+    /// - 確保したスレッドが次に行った操作が、そのブロックの解放である
+    /// - 解放したときのレイアウトが確保したときと同じ(間にgrow/shrinkがない)
+    ///
+    /// 投機的に確保して捨てているコードの目印になる。
+    pub fn likely_unused_allocations(&self) -> Vec<Action> {
+        let history = self.history();
+        history
+            .iter()
+            .enumerate()
+            .filter(|(i, action)| {
+                let (Kind::Allocate | Kind::AllocateZeroed, Some(addr)) =
+                    (action.kind, action.addr)
+                else {
+                    return false;
+                };
+                history
+                    .range(i + 1..)
+                    .find(|other| other.thread_id == action.thread_id)
+                    .is_some_and(|next| {
+                        next.kind == Kind::Deallocate
+                            && next.addr == Some(addr)
+                            && next.layout == action.layout
+                    })
+            })
+            .map(|(_, action)| action.clone())
+            .collect()
+    }
+}
@@ -0,0 +1,46 @@
+use std::sync::atomic::Ordering;
+
+use super::DebugAlloc;
+
+/// [`DebugAlloc::recording_paused`]が返すガード
+///
+/// 破棄されると、ガードを作る前の記録の設定に戻す。
+#[must_use = "dropping the guard resumes recording immediately"]
+#[derive(Debug)]
+pub struct RecordingPaused<'a, A> {
+    alloc: &'a DebugAlloc<A>,
+    was_recording: bool,
+}
+
+impl<A> Drop for RecordingPaused<'_, A> {
+    fn drop(&mut self) {
+        self.alloc.set_recording(self.was_recording);
+    }
+}
+
+impl<A> DebugAlloc<A> {
+    /// 操作を履歴に入れるかどうかを設定する
+    ///
+    /// `false`にしている間の操作は履歴に入らないが、集計と生存中の確保の一覧は更新されるので、
+    /// 再開した後も解放の追跡やリークの検出は正しく続く。既にある履歴はそのまま残る。
+    pub fn set_recording(&self, recording: bool) {
+        self.shared.recording.store(recording, Ordering::Relaxed);
+    }
+
+    /// 操作を履歴に入れているかどうか
+    pub fn is_recording(&self) -> bool {
+        self.shared.recording.load(Ordering::Relaxed)
+    }
+
+    /// 返したガードが生きている間、操作を履歴に入れない
+    ///
+    /// 準備や後片付けの確保を履歴から外すのに使う。ガードを破棄すると元の設定に戻る。
+    /// 詳しくは[`DebugAlloc::set_recording`]を参照。
+    pub fn recording_paused(&self) -> RecordingPaused<'_, A> {
+        let was_recording = self.shared.recording.swap(false, Ordering::Relaxed);
+        RecordingPaused {
+            alloc: self,
+            was_recording,
+        }
+    }
+}
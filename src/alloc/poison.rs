@@ -0,0 +1,97 @@
+use std::{alloc::System, ptr::NonNull, sync::atomic::Ordering};
+
+use super::DebugAlloc;
+
+/// 解放されたメモリを埋める値
+pub const POISON_BYTE: u8 = 0xDE;
+
+impl<A> DebugAlloc<A> {
+    /// 解放するメモリを内部の割り当て器に返す前に[`POISON_BYTE`]で埋めるかどうかを設定する
+    ///
+    /// `deallocate`ではブロック全体を、`shrink`では切り捨てる末尾を埋める。
+    /// 解放後の読み出しが黙って成功せず、見分けやすいごみを読むようになる。
+    pub fn set_poison_freed(&self, poison: bool) {
+        self.shared.poison_freed.store(poison, Ordering::Relaxed);
+    }
+
+    /// 設定されていれば`ptr`から`len`バイトを[`POISON_BYTE`]で埋める
+    ///
+    /// # Safety
+    /// `ptr`から`len`バイトが書き込み可能であること
+    pub(super) unsafe fn poison(&self, ptr: NonNull<u8>, len: usize) {
+        if len != 0 && self.shared.poison_freed.load(Ordering::Relaxed) {
+            ptr.as_ptr().write_bytes(POISON_BYTE, len);
+        }
+    }
+
+    /// [`DebugAlloc::poison`]と同じだが、埋める前の内容を控えて返す
+    ///
+    /// # Safety
+    /// `ptr`から`len`バイトが読み書き可能であること
+    pub(super) unsafe fn poison_saving(&self, ptr: NonNull<u8>, len: usize) -> Vec<u8, System> {
+        let mut saved = Vec::new_in(System);
+        if len != 0 && self.shared.poison_freed.load(Ordering::Relaxed) {
+            saved.extend_from_slice(std::slice::from_raw_parts(ptr.as_ptr(), len));
+            ptr.as_ptr().write_bytes(POISON_BYTE, len);
+        }
+        saved
+    }
+
+    /// [`DebugAlloc::poison_saving`]で埋めた`ptr`からの内容を`saved`に戻す
+    ///
+    /// # Safety
+    /// `ptr`から`saved.len()`バイトが書き込み可能であること
+    pub(super) unsafe fn unpoison(ptr: NonNull<u8>, saved: &[u8]) {
+        ptr.as_ptr()
+            .copy_from_nonoverlapping(saved.as_ptr(), saved.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        alloc::{Allocator, Layout},
+        ptr::NonNull,
+    };
+
+    use crate::{DebugAlloc, MockAlloc, MockResult, POISON_BYTE};
+
+    #[test]
+    fn failed_shrink_keeps_the_block() {
+        let mut block = [0u8; 64];
+        let at = NonNull::from(&mut block).cast::<u8>();
+        let alloc = DebugAlloc::new(MockAlloc::new([MockResult::At(at), MockResult::Fail]));
+        alloc.set_poison_freed(true);
+        let old = Layout::new::<[u8; 64]>();
+        let ptr = alloc.allocate(old).unwrap().cast::<u8>();
+        unsafe {
+            ptr.as_ptr().write_bytes(0x5A, old.size());
+            let new = Layout::new::<[u8; 16]>();
+            assert!(alloc.shrink(ptr, old, new).is_err());
+            let data = std::slice::from_raw_parts(ptr.as_ptr(), old.size());
+            assert!(data.iter().all(|&b| b == 0x5A));
+        }
+    }
+
+    #[test]
+    fn shrink_poisons_the_tail() {
+        let mut block = [0u8; 64];
+        let mut moved = [0u8; 16];
+        let at = NonNull::from(&mut block).cast::<u8>();
+        let to = NonNull::from(&mut moved).cast::<u8>();
+        let alloc = DebugAlloc::new(MockAlloc::new([MockResult::At(at), MockResult::At(to)]));
+        alloc.set_poison_freed(true);
+        let old = Layout::new::<[u8; 64]>();
+        let new = Layout::new::<[u8; 16]>();
+        unsafe {
+            let ptr = alloc.allocate(old).unwrap().cast::<u8>();
+            ptr.as_ptr().write_bytes(0x5A, old.size());
+            let shrunk = alloc.shrink(ptr, old, new).unwrap().cast::<u8>();
+            assert_eq!(shrunk, to);
+            alloc.deallocate(shrunk, new);
+        }
+        assert!(block[..16].iter().all(|&b| b == 0x5A));
+        assert!(block[16..].iter().all(|&b| b == POISON_BYTE));
+        assert!(moved.iter().all(|&b| b == POISON_BYTE));
+    }
+}
@@ -0,0 +1,262 @@
+use std::{
+    alloc::Layout,
+    collections::{HashMap, HashSet},
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+use super::{fmt_layout, Action, DebugAlloc, Kind};
+
+/// 操作の種類とレイアウトの組
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Signature {
+    pub kind: Kind,
+    pub layout: Layout,
+}
+
+impl Signature {
+    fn sort_key(&self) -> (usize, usize, &'static str, usize, usize) {
+        let (old_size, old_align) = self
+            .kind
+            .old_layout()
+            .map_or((0, 0), |l| (l.size(), l.align()));
+        (
+            self.layout.size(),
+            self.layout.align(),
+            self.kind.name(),
+            old_size,
+            old_align,
+        )
+    }
+}
+
+impl From<&Action> for Signature {
+    fn from(action: &Action) -> Self {
+        Self {
+            kind: action.kind,
+            layout: action.layout,
+        }
+    }
+}
+
+impl Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ", self.kind.name())?;
+        if let Some(old_layout) = self.kind.old_layout() {
+            fmt_layout(f, old_layout)?;
+            write!(f, " -> ")?;
+        }
+        fmt_layout(f, self.layout)
+    }
+}
+
+/// 履歴に現れた[`Signature`]ごとの出現回数
+///
+/// `Display`で出力した文字列は`parse`で読み戻せるので、基準となるプロファイルを
+/// ファイルに保存しておき、[`AllocationProfile::diff`]で比較することができる。
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AllocationProfile {
+    counts: HashMap<Signature, usize>,
+}
+
+impl AllocationProfile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn count(&self, signature: &Signature) -> usize {
+        self.counts.get(signature).copied().unwrap_or(0)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Signature, usize)> {
+        self.counts.iter().map(|(sig, &count)| (sig, count))
+    }
+
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    fn add(&mut self, signature: Signature, count: usize) {
+        *self.counts.entry(signature).or_insert(0) += count;
+    }
+
+    fn sorted(&self) -> Vec<(&Signature, usize)> {
+        let mut entries = self.iter().collect::<Vec<_>>();
+        entries.sort_by_key(|(sig, _)| sig.sort_key());
+        entries
+    }
+
+    /// `other`を新しいプロファイルとみなして差分を取る
+    pub fn diff(&self, other: &AllocationProfile) -> ProfileDiff {
+        self.diff_with_threshold(other, 0)
+    }
+
+    /// 出現回数の差が`threshold`を超えたものだけを変更として扱う
+    pub fn diff_with_threshold(&self, other: &AllocationProfile, threshold: usize) -> ProfileDiff {
+        let mut diff = ProfileDiff::default();
+        let signatures = self
+            .counts
+            .keys()
+            .chain(other.counts.keys())
+            .collect::<HashSet<_>>();
+        for &sig in signatures {
+            match (self.counts.get(&sig), other.counts.get(&sig)) {
+                (None, Some(&new)) => diff.added.push((sig, new)),
+                (Some(&old), None) => diff.removed.push((sig, old)),
+                (Some(&old), Some(&new)) if old.abs_diff(new) > threshold => {
+                    diff.changed.push((sig, old, new))
+                }
+                _ => {}
+            }
+        }
+        diff.added.sort_by_key(|(sig, _)| sig.sort_key());
+        diff.removed.sort_by_key(|(sig, _)| sig.sort_key());
+        diff.changed.sort_by_key(|(sig, _, _)| sig.sort_key());
+        diff
+    }
+}
+
+impl<'a> FromIterator<&'a Action> for AllocationProfile {
+    fn from_iter<T: IntoIterator<Item = &'a Action>>(iter: T) -> Self {
+        let mut profile = Self::new();
+        for action in iter {
+            profile.add(action.into(), 1);
+        }
+        profile
+    }
+}
+
+/// 1行に1つ`<回数> <種類> <size> <align> [<old_size> <old_align>]`の形式で出力する
+impl Display for AllocationProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (sig, count) in self.sorted() {
+            write!(
+                f,
+                "{count} {} {} {}",
+                sig.kind.name(),
+                sig.layout.size(),
+                sig.layout.align()
+            )?;
+            if let Some(old_layout) = sig.kind.old_layout() {
+                write!(f, " {} {}", old_layout.size(), old_layout.align())?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseProfileError {
+    line: usize,
+}
+
+impl Display for ParseProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid allocation profile at line {}", self.line)
+    }
+}
+
+impl std::error::Error for ParseProfileError {}
+
+impl FromStr for AllocationProfile {
+    type Err = ParseProfileError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut profile = Self::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let err = ParseProfileError { line: i + 1 };
+            let mut fields = line.split_whitespace();
+            let count = fields
+                .next()
+                .and_then(|s| s.parse().ok())
+                .ok_or(err.clone())?;
+            let name = fields.next().ok_or(err.clone())?;
+            let nums = fields
+                .map(|s| s.parse::<usize>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| err.clone())?;
+            let layout =
+                |size, align| Layout::from_size_align(size, align).map_err(|_| err.clone());
+            let (kind, layout) = match (name, &nums[..]) {
+                ("allocate", &[size, align]) => (Kind::Allocate, layout(size, align)?),
+                ("deallocate", &[size, align]) => (Kind::Deallocate, layout(size, align)?),
+                ("allocate_zeroed", &[size, align]) => (Kind::AllocateZeroed, layout(size, align)?),
+                ("grow", &[size, align, old_size, old_align]) => (
+                    Kind::Grow(layout(old_size, old_align)?),
+                    layout(size, align)?,
+                ),
+                ("grow_zeroed", &[size, align, old_size, old_align]) => (
+                    Kind::GrowZeroed(layout(old_size, old_align)?),
+                    layout(size, align)?,
+                ),
+                ("shrink", &[size, align, old_size, old_align]) => (
+                    Kind::Shrink(layout(old_size, old_align)?),
+                    layout(size, align)?,
+                ),
+                _ => return Err(err),
+            };
+            profile.add(Signature { kind, layout }, count);
+        }
+        Ok(profile)
+    }
+}
+
+/// 2つの[`AllocationProfile`]の差分
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProfileDiff {
+    /// 新しいプロファイルにだけ現れたもの
+    pub added: Vec<(Signature, usize)>,
+    /// 古いプロファイルにだけ現れたもの
+    pub removed: Vec<(Signature, usize)>,
+    /// 出現回数が変化したもの(古い回数, 新しい回数)
+    pub changed: Vec<(Signature, usize, usize)>,
+}
+
+impl ProfileDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl Display for ProfileDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No changes");
+        }
+        if !self.added.is_empty() {
+            writeln!(f, "Added:")?;
+            for (sig, count) in &self.added {
+                writeln!(f, "\t+ {sig} x{count}")?;
+            }
+        }
+        if !self.removed.is_empty() {
+            writeln!(f, "Removed:")?;
+            for (sig, count) in &self.removed {
+                writeln!(f, "\t- {sig} x{count}")?;
+            }
+        }
+        if !self.changed.is_empty() {
+            writeln!(f, "Changed:")?;
+            for (sig, old, new) in &self.changed {
+                writeln!(f, "\t~ {sig} x{old} -> x{new}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<A> DebugAlloc<A> {
+    /// 履歴から[`AllocationProfile`]を作る
+    pub fn allocation_profile(&self) -> AllocationProfile {
+        self.history().iter().collect()
+    }
+}
@@ -0,0 +1,130 @@
+use std::{
+    io::{self, Read, Write},
+    net::{TcpListener, ToSocketAddrs},
+    thread::{self, JoinHandle},
+};
+
+use super::DebugAlloc;
+
+/// 1つの値だけを持つ指標を書き出す
+fn write_metric<W: Write>(
+    w: &mut W,
+    name: &str,
+    ty: &str,
+    help: &str,
+    value: u64,
+) -> io::Result<()> {
+    writeln!(w, "# HELP debug_allocator_{name} {help}")?;
+    writeln!(w, "# TYPE debug_allocator_{name} {ty}")?;
+    writeln!(w, "debug_allocator_{name} {value}")
+}
+
+impl<A> DebugAlloc<A> {
+    /// 集計をPrometheusのテキスト形式で書き出す
+    ///
+    /// 指標の名前には`debug_allocator_`が付く。累計は`*_total`のcounter、
+    /// 生存中の値と最大値はgaugeになる。種類ごとの成功回数は
+    /// `debug_allocator_operations_total{kind="..."}`(`allocate`は`allocate_zeroed`を、
+    /// `grow`は`grow_zeroed`を含む)。
+    pub fn export_prometheus<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let stats = self.stats();
+        let peak_bytes = self.peak_usage();
+        write_metric(
+            w,
+            "allocations_total",
+            "counter",
+            "Successful allocate and allocate_zeroed calls.",
+            stats.allocations as u64,
+        )?;
+        write_metric(
+            w,
+            "failures_total",
+            "counter",
+            "Failed or denied calls.",
+            stats.failures as u64,
+        )?;
+        writeln!(
+            w,
+            "# HELP debug_allocator_operations_total Successful calls by kind."
+        )?;
+        writeln!(w, "# TYPE debug_allocator_operations_total counter")?;
+        for (kind, count) in [
+            ("allocate", stats.allocations),
+            ("deallocate", stats.deallocations),
+            ("grow", stats.grows),
+            ("shrink", stats.shrinks),
+        ] {
+            writeln!(
+                w,
+                "debug_allocator_operations_total{{kind=\"{kind}\"}} {count}"
+            )?;
+        }
+        write_metric(
+            w,
+            "bytes_allocated_total",
+            "counter",
+            "Bytes added by allocations and grows.",
+            stats.allocated_bytes,
+        )?;
+        write_metric(
+            w,
+            "bytes_freed_total",
+            "counter",
+            "Bytes removed by deallocations and shrinks.",
+            stats.freed_bytes,
+        )?;
+        write_metric(
+            w,
+            "allocations_live",
+            "gauge",
+            "Blocks currently allocated.",
+            stats.live_allocations as u64,
+        )?;
+        write_metric(
+            w,
+            "bytes_live",
+            "gauge",
+            "Bytes currently allocated.",
+            stats.live_bytes,
+        )?;
+        write_metric(
+            w,
+            "bytes_peak",
+            "gauge",
+            "Maximum of bytes_live.",
+            peak_bytes,
+        )
+    }
+}
+
+impl<A: Clone + Send + Sync + 'static> DebugAlloc<A> {
+    /// `addr`で待ち受け、[`DebugAlloc::export_prometheus`]の内容を返すスレッドを起動する
+    ///
+    /// リクエストのパスやメソッドは見ずに、どの接続にも今の集計を返す。
+    /// スレッドはプロセスが終わるまで動き続ける。このスレッドの確保も、
+    /// この割り当て器をグローバルアロケータにしていれば記録される。
+    pub fn serve_prometheus(&self, addr: impl ToSocketAddrs) -> io::Result<JoinHandle<()>> {
+        let listener = TcpListener::bind(addr)?;
+        let alloc = self.clone();
+        thread::Builder::new()
+            .name("debug-allocator-metrics".into())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    let Ok(mut stream) = stream else {
+                        continue;
+                    };
+                    // リクエストは読み捨てる
+                    let _ = stream.read(&mut [0; 1024]);
+                    let mut body = Vec::new();
+                    let _ = alloc.export_prometheus(&mut body);
+                    let _ = write!(
+                        stream,
+                        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+                         Content-Length: {}\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    )
+                    .and_then(|()| stream.write_all(&body));
+                }
+            })
+    }
+}
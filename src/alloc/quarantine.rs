@@ -0,0 +1,220 @@
+use std::{
+    alloc::{Allocator, Layout, System},
+    collections::VecDeque,
+    ptr::NonNull,
+    sync::atomic::Ordering,
+    thread,
+};
+
+use super::{AllocAnomaly, DebugAlloc, POISON_BYTE};
+
+/// 内部の割り当て器に返さずに保留している解放済みのブロック
+#[derive(Debug)]
+struct Quarantined {
+    addr: usize,
+    layout: Layout,
+    /// 解放した操作の通し番号
+    free_seq: u64,
+}
+
+#[derive(Debug)]
+pub(super) struct Quarantine {
+    /// 保留するブロックの最大数(0なら保留しない)
+    capacity: usize,
+    blocks: VecDeque<Quarantined, System>,
+    /// 型を消した[`DebugAlloc::flush_quarantine`](最後の複製を破棄するときに呼ぶ)
+    flush: Option<unsafe fn(*const ())>,
+}
+
+impl Default for Quarantine {
+    fn default() -> Self {
+        Self {
+            capacity: 0,
+            blocks: VecDeque::new_in(System),
+            flush: None,
+        }
+    }
+}
+
+impl<A> Drop for DebugAlloc<A> {
+    fn drop(&mut self) {
+        if self.shared.handles.fetch_sub(1, Ordering::AcqRel) != 1 {
+            return;
+        }
+        let flush = self.shared.quarantine.lock().ok().and_then(|q| q.flush);
+        if let Some(flush) = flush {
+            // 巻き戻し中に報告でpanicすると中断してしまう
+            if thread::panicking() {
+                self.set_panic_on_anomaly(false);
+            }
+            unsafe { flush((self as *const Self).cast()) };
+        }
+    }
+}
+
+impl<A> DebugAlloc<A> {
+    /// 保留中のブロックの数
+    pub fn quarantined(&self) -> usize {
+        self.shared.quarantine.lock().unwrap().blocks.len()
+    }
+
+    /// 保留するなら`ptr`のブロックを[`POISON_BYTE`]で埋めて`true`を返す
+    pub(super) unsafe fn hold_freed(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        let held = self
+            .shared
+            .quarantine
+            .lock()
+            .is_ok_and(|quarantine| quarantine.capacity != 0);
+        if held {
+            ptr.as_ptr().write_bytes(POISON_BYTE, layout.size());
+        }
+        held
+    }
+}
+
+impl<A: Allocator> DebugAlloc<A> {
+    /// 解放されたブロックを最大`n`個まで内部の割り当て器に返さずに保留する
+    ///
+    /// 保留中のブロックは[`POISON_BYTE`]で埋めておき、保留から外して内部の割り当て器に
+    /// 返すときに書き換えられていないか確かめる。書き換えられていれば
+    /// [`AllocAnomaly::UseAfterFree`]を報告する。解放後の書き込みを検出できるうえ、
+    /// 保留中はアドレスが再利用されないので二重解放も見つけやすくなる。
+    ///
+    /// 保留が`n`個を超えると古いものから返す。`0`を渡すと保留中のブロックをすべて返す。
+    /// 最後の複製を破棄したときにも、保留中のブロックをすべて確かめて返す。
+    pub fn set_quarantine(&self, n: usize) {
+        let evicted = {
+            let mut quarantine = self.shared.quarantine.lock().unwrap();
+            quarantine.capacity = n;
+            quarantine.flush = Some(Self::flush_erased);
+            let excess = quarantine.blocks.len().saturating_sub(n);
+            let kept = quarantine.blocks.split_off(excess);
+            std::mem::replace(&mut quarantine.blocks, kept)
+        };
+        for block in evicted {
+            unsafe { self.release(block) };
+        }
+    }
+
+    /// 保留中のブロックをすべて確かめて内部の割り当て器に返す
+    pub fn flush_quarantine(&self) {
+        let blocks = std::mem::replace(
+            &mut self.shared.quarantine.lock().unwrap().blocks,
+            VecDeque::new_in(System),
+        );
+        for block in blocks {
+            unsafe { self.release(block) };
+        }
+    }
+
+    /// `this`の指す`DebugAlloc<A>`で[`DebugAlloc::flush_quarantine`]を呼ぶ
+    unsafe fn flush_erased(this: *const ()) {
+        (*this.cast::<Self>()).flush_quarantine();
+    }
+
+    /// 通し番号`free_seq`で解放された`ptr`のブロックを保留に加え、あふれた分を返す
+    pub(super) unsafe fn enqueue_quarantine(
+        &self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        free_seq: u64,
+    ) {
+        let block = Quarantined {
+            addr: ptr.as_ptr() as usize,
+            layout,
+            free_seq,
+        };
+        let evicted = match self.shared.quarantine.lock() {
+            Ok(mut quarantine) => {
+                quarantine.blocks.push_back(block);
+                if quarantine.blocks.len() > quarantine.capacity {
+                    quarantine.blocks.pop_front()
+                } else {
+                    None
+                }
+            }
+            Err(_) => Some(block),
+        };
+        if let Some(block) = evicted {
+            self.release(block);
+        }
+    }
+
+    /// 保留していたブロックを確かめて内部の割り当て器に返す
+    unsafe fn release(&self, block: Quarantined) {
+        let ptr = NonNull::new_unchecked(block.addr as *mut u8);
+        let data = std::slice::from_raw_parts(ptr.as_ptr(), block.layout.size());
+        if let Some(offset) = data.iter().position(|&b| b != POISON_BYTE) {
+            self.report_anomaly(AllocAnomaly::UseAfterFree {
+                addr: ptr.cast(),
+                layout: block.layout,
+                offset,
+                free_seq: block.free_seq,
+            });
+        }
+        self.inner_deallocate(ptr, block.layout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        alloc::{Allocator, Layout},
+        ptr::NonNull,
+    };
+
+    use crate::{AllocAnomaly, DebugAlloc, Kind, MockAlloc, MockResult};
+
+    fn deallocations(mock: &MockAlloc) -> usize {
+        mock.calls()
+            .iter()
+            .filter(|call| call.kind == Kind::Deallocate)
+            .count()
+    }
+
+    #[test]
+    fn write_after_free_is_reported() {
+        let mut block = [0u8; 32];
+        let at = NonNull::from(&mut block).cast::<u8>();
+        let mock = MockAlloc::new([MockResult::At(at)]);
+        let alloc = DebugAlloc::new(mock.clone());
+        alloc.set_quarantine(4);
+        let layout = Layout::new::<[u8; 32]>();
+        let ptr = alloc.allocate(layout).unwrap().cast::<u8>();
+        unsafe {
+            alloc.deallocate(ptr, layout);
+            ptr.add(5).write(1);
+        }
+        assert_eq!(deallocations(&mock), 0);
+        alloc.flush_quarantine();
+        assert_eq!(deallocations(&mock), 1);
+        assert_eq!(
+            alloc.anomalies(),
+            [AllocAnomaly::UseAfterFree {
+                addr: at.cast(),
+                layout,
+                offset: 5,
+                free_seq: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn dropping_the_last_handle_releases_the_quarantine() {
+        let mock = MockAlloc::new([MockResult::Succeed { extra: 0 }; 3]);
+        let alloc = DebugAlloc::new(mock.clone());
+        alloc.set_quarantine(2);
+        let clone = alloc.clone();
+        let layout = Layout::new::<u64>();
+        for _ in 0..3 {
+            let ptr = alloc.allocate(layout).unwrap();
+            unsafe { alloc.deallocate(ptr.cast(), layout) };
+        }
+        // あふれた1つだけが返されている
+        assert_eq!(deallocations(&mock), 1);
+        drop(alloc);
+        assert_eq!(deallocations(&mock), 1);
+        drop(clone);
+        assert_eq!(deallocations(&mock), 3);
+    }
+}
@@ -0,0 +1,72 @@
+use std::{alloc::Layout, sync::atomic::Ordering};
+
+use super::{DebugAlloc, Kind};
+
+impl<A> DebugAlloc<A> {
+    /// 要求されたバイト数の累計の上限を設定し、累計を0に戻す
+    ///
+    /// 確保したサイズと拡張で増えたサイズを累計し、累計が`max_bytes`を超える要求が来ると、
+    /// それ以降の確保と拡張をすべて内部の割り当て器を呼ばずに`Err(AllocError)`で失敗させ、
+    /// [`Denial::Quota`](super::Denial::Quota)の付いた失敗として記録する。解放しても
+    /// 累計は減らないので、実行の途中で使えるメモリが尽きた状況を再現できる。縮小と解放は
+    /// 常に許可する。`None`を渡すと上限をなくす。
+    ///
+    /// 生存バイト数を制限する[`DebugAlloc::set_byte_budget`]と違い、一度尽きると
+    /// [`DebugAlloc::reset_byte_quota`]を呼ぶまで失敗し続ける。
+    pub fn set_byte_quota(&self, max_bytes: Option<u64>) {
+        self.shared
+            .byte_quota
+            .store(max_bytes.unwrap_or(u64::MAX), Ordering::Relaxed);
+        self.reset_byte_quota();
+    }
+
+    /// [`DebugAlloc::set_byte_quota`]で設定した上限
+    pub fn byte_quota(&self) -> Option<u64> {
+        match self.shared.byte_quota.load(Ordering::Relaxed) {
+            u64::MAX => None,
+            quota => Some(quota),
+        }
+    }
+
+    /// 要求されたバイト数の累計を0に戻し、上限に達した状態から復帰する
+    pub fn reset_byte_quota(&self) {
+        self.shared.quota_used.store(0, Ordering::Relaxed);
+        self.shared.quota_exhausted.store(false, Ordering::Relaxed);
+    }
+
+    /// 上限に数えた、要求されたバイト数の累計
+    pub fn quota_used(&self) -> u64 {
+        self.shared.quota_used.load(Ordering::Relaxed)
+    }
+
+    /// 上限に達して確保を失敗させている状態なら`true`
+    pub fn quota_exhausted(&self) -> bool {
+        self.shared.quota_exhausted.load(Ordering::Relaxed)
+    }
+
+    /// 上限に達している確保なら`true`、そうでなければ累計に加える
+    pub(super) fn over_quota(&self, kind: Kind, layout: Layout) -> bool {
+        let Some(quota) = self.byte_quota() else {
+            return false;
+        };
+        let old_size = match kind {
+            Kind::Allocate | Kind::AllocateZeroed => 0,
+            Kind::Grow(old_layout) | Kind::GrowZeroed(old_layout) => old_layout.size(),
+            Kind::Deallocate | Kind::Shrink(_) => return false,
+        };
+        if self.quota_exhausted() {
+            return true;
+        }
+        let growth = layout.size().saturating_sub(old_size) as u64;
+        let counted =
+            self.shared
+                .quota_used
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                    used.checked_add(growth).filter(|&total| total <= quota)
+                });
+        if counted.is_err() {
+            self.shared.quota_exhausted.store(true, Ordering::Relaxed);
+        }
+        counted.is_err()
+    }
+}
@@ -0,0 +1,116 @@
+//! 操作を固定長のバイナリで書き出す形式
+//!
+//! ファイルの先頭に[`MAGIC`]があり、その後に[`RECORD_LEN`]バイトのレコードが並ぶ。
+//! 整数はすべてリトルエンディアンで、存在しないアドレスやレイアウトは0で表す。
+//!
+//! | オフセット | 型 | 内容 |
+//! |---|---|---|
+//! | 0 | u64 | 通し番号 |
+//! | 8 | u8 | 種類(`Kind`の宣言順) |
+//! | 9 | u8 | 拒否の理由(0: なし、1: フック、2: 故障注入、3: 上限、4: 事前条件の違反、5: 累計の上限) |
+//! | 10 | u64 | サイズ |
+//! | 18 | u64 | アライメント |
+//! | 26 | u64 | 変更前のサイズ |
+//! | 34 | u64 | 変更前のアライメント |
+//! | 42 | u64 | アドレス |
+//! | 50 | u64 | 変更前のアドレス |
+//! | 58 | u64 | 長さ |
+//! | 66 | u64 | 時刻[ns] |
+//! | 74 | u64 | スレッドID |
+//! | 82 | u64 | エポック |
+
+use std::{alloc::Layout, ptr::NonNull, time::Duration};
+
+use super::{Action, Denial, Kind};
+
+pub(super) const MAGIC: &[u8; 8] = b"DALLOC01";
+pub(super) const RECORD_LEN: usize = 90;
+
+fn kind_tag(kind: Kind) -> u8 {
+    match kind {
+        Kind::Allocate => 0,
+        Kind::Deallocate => 1,
+        Kind::AllocateZeroed => 2,
+        Kind::Grow(_) => 3,
+        Kind::GrowZeroed(_) => 4,
+        Kind::Shrink(_) => 5,
+    }
+}
+
+fn denial_tag(denial: Option<Denial>) -> u8 {
+    match denial {
+        None => 0,
+        Some(Denial::Hook) => 1,
+        Some(Denial::Fault) => 2,
+        Some(Denial::Budget) => 3,
+        Some(Denial::Contract) => 4,
+        Some(Denial::Quota) => 5,
+    }
+}
+
+/// `action`を1つのレコードにする
+pub(super) fn encode(action: &Action) -> [u8; RECORD_LEN] {
+    let old_layout = action.kind.old_layout();
+    let addr = |addr: Option<std::ptr::NonNull<()>>| addr.map_or(0, |addr| addr.as_ptr() as u64);
+    let mut buf = [0; RECORD_LEN];
+    buf[0..8].copy_from_slice(&action.seq.to_le_bytes());
+    buf[8] = kind_tag(action.kind);
+    buf[9] = denial_tag(action.denied);
+    let words = [
+        action.layout.size() as u64,
+        action.layout.align() as u64,
+        old_layout.map_or(0, |layout| layout.size() as u64),
+        old_layout.map_or(0, |layout| layout.align() as u64),
+        addr(action.addr),
+        addr(action.old_addr),
+        action.len as u64,
+        action.timestamp.as_nanos() as u64,
+        action.thread_id,
+        action.epoch,
+    ];
+    for (chunk, word) in buf[10..].chunks_exact_mut(8).zip(words) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    buf
+}
+
+/// [`encode`]したレコードを読み戻す。不正なレコードなら`None`を返す
+pub(super) fn decode(buf: &[u8; RECORD_LEN]) -> Option<Action> {
+    let word = |i: usize| u64::from_le_bytes(buf[10 + i * 8..18 + i * 8].try_into().unwrap());
+    let addr = |addr: u64| NonNull::new(addr as usize as *mut ());
+    let layout = Layout::from_size_align(word(0) as usize, word(1) as usize).ok()?;
+    let old_layout = || Layout::from_size_align(word(2) as usize, word(3) as usize).ok();
+    let kind = match buf[8] {
+        0 => Kind::Allocate,
+        1 => Kind::Deallocate,
+        2 => Kind::AllocateZeroed,
+        3 => Kind::Grow(old_layout()?),
+        4 => Kind::GrowZeroed(old_layout()?),
+        5 => Kind::Shrink(old_layout()?),
+        _ => return None,
+    };
+    let denied = match buf[9] {
+        0 => None,
+        1 => Some(Denial::Hook),
+        2 => Some(Denial::Fault),
+        3 => Some(Denial::Budget),
+        4 => Some(Denial::Contract),
+        5 => Some(Denial::Quota),
+        _ => return None,
+    };
+    Some(Action {
+        seq: u64::from_le_bytes(buf[0..8].try_into().unwrap()),
+        addr: addr(word(4)),
+        old_addr: addr(word(5)),
+        len: word(6) as usize,
+        denied,
+        timestamp: Duration::from_nanos(word(7)),
+        epoch: word(9),
+        thread_id: word(8),
+        thread_name: None,
+        #[cfg(feature = "backtrace")]
+        backtrace: None,
+        layout,
+        kind,
+    })
+}
@@ -0,0 +1,230 @@
+use std::{
+    alloc::{AllocError, Allocator, Layout, System},
+    ptr::{self, NonNull},
+    sync::Arc,
+};
+
+use super::{AllocAnomaly, DebugAlloc, Shared};
+
+/// レッドゾーンを埋める値
+pub const RED_ZONE_BYTE: u8 = 0xFD;
+
+impl<A> DebugAlloc<A> {
+    /// 各ブロックの前後に`bytes`バイトのレッドゾーンを置いて、はみ出した書き込みを検出する
+    ///
+    /// 内部の割り当て器からはレッドゾーンの分だけ大きく確保し、レッドゾーンを
+    /// [`RED_ZONE_BYTE`]で埋める。解放とgrow/shrinkのときにレッドゾーンが書き換えられて
+    /// いれば[`AllocAnomaly::RedZoneCorrupted`]を報告する
+    /// ([`DebugAlloc::set_panic_on_anomaly`]が有効ならpanicする)。
+    /// 履歴や集計には要求されたレイアウトと、利用者に返したアドレスが記録される。
+    pub fn with_red_zone(alloc: A, bytes: usize) -> Self {
+        Self {
+            alloc,
+            shared: Arc::new_in(
+                Shared {
+                    red_zone: bytes,
+                    ..Default::default()
+                },
+                System,
+            ),
+        }
+    }
+
+    /// ブロックの前のレッドゾーンの大きさ(アラインメントを保つために切り上げる)
+    fn front(&self, layout: Layout) -> usize {
+        self.shared.red_zone.next_multiple_of(layout.align())
+    }
+
+    /// 内部の割り当て器に渡すレイアウト
+    fn outer(&self, layout: Layout) -> Result<Layout, AllocError> {
+        self.front(layout)
+            .checked_add(layout.size())
+            .and_then(|size| size.checked_add(self.shared.red_zone))
+            .and_then(|size| Layout::from_size_align(size, layout.align()).ok())
+            .ok_or(AllocError)
+    }
+
+    /// `ptr`の前後のレッドゾーンを埋める
+    unsafe fn fill_red_zones(&self, ptr: NonNull<u8>, layout: Layout) {
+        let front = self.front(layout);
+        ptr.as_ptr().sub(front).write_bytes(RED_ZONE_BYTE, front);
+        ptr.as_ptr()
+            .add(layout.size())
+            .write_bytes(RED_ZONE_BYTE, self.shared.red_zone);
+    }
+
+    /// `ptr`の前後のレッドゾーンが書き換えられていれば報告する
+    unsafe fn verify_red_zones(&self, ptr: NonNull<u8>, layout: Layout) {
+        let front = self.front(layout);
+        let before = std::slice::from_raw_parts(ptr.as_ptr().sub(front), front);
+        let after =
+            std::slice::from_raw_parts(ptr.as_ptr().add(layout.size()), self.shared.red_zone);
+        let offset = match before.iter().position(|&b| b != RED_ZONE_BYTE) {
+            Some(i) => i as isize - front as isize,
+            None => match after.iter().position(|&b| b != RED_ZONE_BYTE) {
+                Some(i) => (layout.size() + i) as isize,
+                None => return,
+            },
+        };
+        let alloc_seq = self.shared.tracker.read().ok().and_then(|tracker| {
+            tracker
+                .live
+                .get(&(ptr.as_ptr() as usize))
+                .map(|action| action.seq)
+        });
+        self.report_anomaly(AllocAnomaly::RedZoneCorrupted {
+            addr: ptr.cast(),
+            layout,
+            offset,
+            alloc_seq,
+        });
+    }
+}
+
+impl<A: Allocator> DebugAlloc<A> {
+    /// レッドゾーンを考慮して内部の割り当て器で確保する
+    pub(super) fn inner_allocate(
+        &self,
+        layout: Layout,
+        zeroed: bool,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if self.shared.red_zone == 0 {
+            return if zeroed {
+                self.alloc.allocate_zeroed(layout)
+            } else {
+                self.alloc.allocate(layout)
+            };
+        }
+        let outer = self.outer(layout)?;
+        let base = if zeroed {
+            self.alloc.allocate_zeroed(outer)
+        } else {
+            self.alloc.allocate(outer)
+        }?;
+        unsafe {
+            let ptr = base.cast::<u8>().add(self.front(layout));
+            self.fill_red_zones(ptr, layout);
+            Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+        }
+    }
+
+    /// レッドゾーンを確かめてから内部の割り当て器で解放する
+    pub(super) unsafe fn inner_deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if self.shared.red_zone == 0 {
+            return self.alloc.deallocate(ptr, layout);
+        }
+        self.verify_red_zones(ptr, layout);
+        // `outer`は確保できた時点で成功している
+        let outer = self.outer(layout).unwrap();
+        self.alloc.deallocate(ptr.sub(self.front(layout)), outer);
+    }
+
+    /// レッドゾーンを考慮して内部の割り当て器でgrow/grow_zeroed/shrinkする
+    pub(super) unsafe fn inner_resize(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        zeroed: bool,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let growing = new_layout.size() >= old_layout.size();
+        if self.shared.red_zone == 0 {
+            return match (growing, zeroed) {
+                (true, false) => self.alloc.grow(ptr, old_layout, new_layout),
+                (true, true) => self.alloc.grow_zeroed(ptr, old_layout, new_layout),
+                (false, _) => self.alloc.shrink(ptr, old_layout, new_layout),
+            };
+        }
+        self.verify_red_zones(ptr, old_layout);
+        let front = self.front(old_layout);
+        if front != self.front(new_layout) {
+            // 前のレッドゾーンの大きさが変わるので、確保し直して移す
+            let new = self.inner_allocate(new_layout, zeroed)?;
+            ptr::copy_nonoverlapping(
+                ptr.as_ptr(),
+                new.cast::<u8>().as_ptr(),
+                old_layout.size().min(new_layout.size()),
+            );
+            self.alloc
+                .deallocate(ptr.sub(front), self.outer(old_layout).unwrap());
+            return Ok(new);
+        }
+        let base = ptr.sub(front);
+        let (old_outer, new_outer) = (self.outer(old_layout)?, self.outer(new_layout)?);
+        let base = if growing {
+            self.alloc.grow(base, old_outer, new_outer)?
+        } else {
+            self.alloc.shrink(base, old_outer, new_outer)?
+        };
+        let ptr = base.cast::<u8>().add(front);
+        if zeroed && growing {
+            // 元の後ろのレッドゾーンも含めて、増えた部分を0にする
+            ptr.as_ptr()
+                .add(old_layout.size())
+                .write_bytes(0, new_layout.size() - old_layout.size());
+        }
+        self.fill_red_zones(ptr, new_layout);
+        Ok(NonNull::slice_from_raw_parts(ptr, new_layout.size()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{Allocator, Layout, System};
+
+    use crate::{AllocAnomaly, DebugAlloc};
+
+    #[test]
+    fn intact_red_zones_across_resizes() {
+        let alloc = DebugAlloc::with_red_zone(System, 16);
+        let small = Layout::from_size_align(24, 8).unwrap();
+        let large = Layout::from_size_align(100, 8).unwrap();
+        unsafe {
+            let ptr = alloc.allocate(small).unwrap().cast::<u8>();
+            ptr.as_ptr().write_bytes(7, small.size());
+            let ptr = alloc.grow(ptr, small, large).unwrap().cast::<u8>();
+            let data = std::slice::from_raw_parts(ptr.as_ptr(), small.size());
+            assert!(data.iter().all(|&b| b == 7));
+            ptr.as_ptr().write_bytes(9, large.size());
+            let ptr = alloc.shrink(ptr, large, small).unwrap().cast::<u8>();
+            alloc.deallocate(ptr, small);
+        }
+        assert!(alloc.anomalies().is_empty());
+        assert_eq!(alloc.live_bytes(), 0);
+    }
+
+    #[test]
+    fn overflow_is_reported() {
+        let alloc = DebugAlloc::with_red_zone(System, 16);
+        let layout = Layout::from_size_align(32, 8).unwrap();
+        let ptr = alloc.allocate(layout).unwrap().cast::<u8>();
+        unsafe {
+            ptr.add(layout.size()).write(0);
+            alloc.deallocate(ptr, layout);
+        }
+        assert_eq!(
+            alloc.anomalies(),
+            [AllocAnomaly::RedZoneCorrupted {
+                addr: ptr.cast(),
+                layout,
+                offset: 32,
+                alloc_seq: Some(0),
+            }]
+        );
+    }
+
+    #[test]
+    fn underflow_is_reported() {
+        let alloc = DebugAlloc::with_red_zone(System, 16);
+        let layout = Layout::from_size_align(32, 8).unwrap();
+        let ptr = alloc.allocate(layout).unwrap().cast::<u8>();
+        unsafe {
+            ptr.sub(1).write(0);
+            alloc.deallocate(ptr, layout);
+        }
+        assert!(matches!(
+            alloc.anomalies()[..],
+            [AllocAnomaly::RedZoneCorrupted { offset: -1, .. }]
+        ));
+    }
+}
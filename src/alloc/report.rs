@@ -0,0 +1,91 @@
+use std::{
+    alloc::Allocator,
+    fmt::{self, Display},
+    time::{Duration, Instant},
+};
+
+use super::{AllocStats, ByteSize, DebugAlloc};
+
+/// 確保の集計
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Report {
+    pub stats: AllocStats,
+    /// 生存バイト数の最大値
+    pub peak_bytes: u64,
+    /// 処理にかかった時間
+    pub elapsed: Duration,
+    /// バイト数に読みやすい単位を添える([`DebugAlloc::set_human_sizes`])
+    pub human_sizes: bool,
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stats = &self.stats;
+        let bytes = |n| Bytes(n, self.human_sizes);
+        writeln!(f, "allocations:   {}", stats.allocations)?;
+        writeln!(f, "deallocations: {}", stats.deallocations)?;
+        writeln!(f, "grows:         {}", stats.grows)?;
+        writeln!(f, "shrinks:       {}", stats.shrinks)?;
+        writeln!(f, "failures:      {}", stats.failures)?;
+        writeln!(f, "allocated:     {}", bytes(stats.allocated_bytes))?;
+        writeln!(f, "freed:         {}", bytes(stats.freed_bytes))?;
+        writeln!(
+            f,
+            "outstanding:   {} in {} allocations",
+            bytes(stats.live_bytes),
+            stats.live_allocations
+        )?;
+        writeln!(f, "peak:          {}", bytes(self.peak_bytes))?;
+        writeln!(f, "elapsed:       {:?}", self.elapsed)
+    }
+}
+
+/// 正確な値を表示する(`human_sizes`なら読みやすい単位と並べる)
+struct Bytes(u64, bool);
+
+impl Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.1 || self.0 < 1024 {
+            write!(f, "{} bytes", self.0)
+        } else {
+            write!(f, "{} ({} bytes)", ByteSize(self.0), self.0)
+        }
+    }
+}
+
+impl<A> DebugAlloc<A> {
+    /// 生成されてからの集計をまとめて返す
+    ///
+    /// [`Display`]で読みやすい要約として表示できる。`elapsed`は生成されてからの時間。
+    pub fn report(&self) -> Report {
+        Report {
+            stats: self.stats(),
+            peak_bytes: self.peak_usage(),
+            elapsed: self.shared.created.elapsed(),
+            human_sizes: self.human_sizes(),
+        }
+    }
+}
+
+fn run(alloc: &dyn Allocator, workload: &impl Fn(&DebugAlloc<&dyn Allocator>)) -> Report {
+    let alloc = DebugAlloc::new(alloc);
+    let start = Instant::now();
+    workload(&alloc);
+    let elapsed = start.elapsed();
+    Report {
+        elapsed,
+        ..alloc.report()
+    }
+}
+
+/// 同じ処理を`a`と`b`のそれぞれで実行し、両方の集計を返す
+///
+/// `workload`は渡された割り当て器を通して確保しなければならない。
+/// 公平に比べるには、`workload`は毎回同じ操作をする決定的な処理である必要がある。
+pub fn compare<A: Allocator, B: Allocator>(
+    a: A,
+    b: B,
+    workload: impl Fn(&DebugAlloc<&dyn Allocator>),
+) -> (Report, Report) {
+    (run(&a, &workload), run(&b, &workload))
+}
@@ -0,0 +1,61 @@
+use std::io::{self, Read, Write};
+
+use super::{record, Action, DebugAlloc, Signature};
+
+pub(super) const MAGIC: &[u8; 8] = b"DALLORLE";
+
+impl<A> DebugAlloc<A> {
+    /// 履歴を連長圧縮したバイナリ形式で書き出す
+    ///
+    /// 種類とレイアウトが同じ操作が続いたら、最初の操作のレコードと繰り返しの回数にまとめる。
+    /// 先頭に8バイトの識別子があり、その後に`u64`の回数(リトルエンディアン)と
+    /// [`DebugAlloc::attach_rotating_stream`]と同じ形式のレコードの組が並ぶ。
+    /// [`read_rle_binary`]で読み戻せる。
+    pub fn write_rle_binary<W: Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        let history = self.history();
+        let mut iter = history.iter().peekable();
+        while let Some(first) = iter.next() {
+            let signature = Signature::from(first);
+            let mut count = 1u64;
+            while iter.next_if(|a| Signature::from(*a) == signature).is_some() {
+                count += 1;
+            }
+            w.write_all(&count.to_le_bytes())?;
+            w.write_all(&record::encode(first))?;
+        }
+        Ok(())
+    }
+}
+
+/// [`DebugAlloc::write_rle_binary`]で書き出した履歴を読み込み、まとめた操作を展開する
+///
+/// まとめた操作はアドレスや時刻を残していないので、最初の操作の複製になる
+/// (通し番号だけは1つずつ増やす)。
+pub fn read_rle_binary<R: Read>(mut r: R) -> io::Result<Vec<Action>> {
+    let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let mut magic = [0; 8];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not a run-length encoded history"));
+    }
+    let mut actions = Vec::new();
+    loop {
+        let mut count = [0; 8];
+        match r.read_exact(&mut count) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        let mut buf = [0; record::RECORD_LEN];
+        r.read_exact(&mut buf)?;
+        let first = record::decode(&buf).ok_or_else(|| invalid("invalid record"))?;
+        for i in 0..u64::from_le_bytes(count) {
+            actions.push(Action {
+                seq: first.seq + i,
+                ..first.clone()
+            });
+        }
+    }
+    Ok(actions)
+}
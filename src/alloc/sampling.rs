@@ -0,0 +1,161 @@
+use std::{
+    sync::atomic::{AtomicU32, Ordering},
+    time::Instant,
+};
+
+use super::DebugAlloc;
+
+/// 記録数が1秒あたり`target`件程度になるように間引くトークンバケット
+#[derive(Debug)]
+pub(super) struct AdaptiveSampler {
+    target: u32,
+    tokens: f64,
+    last_refill: Instant,
+    window_start: Instant,
+    seen: u64,
+    stored: u64,
+    /// 直前の1秒間の割合
+    last_window_rate: Option<f64>,
+}
+
+impl AdaptiveSampler {
+    fn new(target: u32) -> Self {
+        let now = Instant::now();
+        Self {
+            target,
+            tokens: target as f64,
+            last_refill: now,
+            window_start: now,
+            seen: 0,
+            stored: 0,
+            last_window_rate: None,
+        }
+    }
+
+    /// 今回の操作を履歴に入れるかどうか
+    pub(super) fn sample(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.target as f64).min(self.target as f64);
+        self.last_refill = now;
+
+        if now.duration_since(self.window_start).as_secs() >= 1 {
+            self.last_window_rate = Some(self.current_rate());
+            self.window_start = now;
+            self.seen = 0;
+            self.stored = 0;
+        }
+
+        self.seen += 1;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            self.stored += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn current_rate(&self) -> f64 {
+        if self.seen == 0 {
+            1.0
+        } else {
+            self.stored as f64 / self.seen as f64
+        }
+    }
+}
+
+/// 生存バイト数が`step`の倍数をまたいだときだけ記録する
+#[derive(Debug)]
+pub(super) struct RecordStep {
+    step: u64,
+    last_bucket: u64,
+}
+
+impl RecordStep {
+    pub(super) fn crossed(&mut self, live_bytes: u64) -> bool {
+        let bucket = live_bytes / self.step;
+        let crossed = bucket != self.last_bucket;
+        self.last_bucket = bucket;
+        crossed
+    }
+}
+
+impl<A> DebugAlloc<A> {
+    /// 履歴に入る操作が1秒あたり`target_per_sec`件程度になるように自動で間引く
+    ///
+    /// 集計(`live_bytes`など)はすべての操作で更新される。`0`を渡すと間引きをやめる。
+    pub fn set_adaptive_sampling(&self, target_per_sec: u32) {
+        *self.shared.sampler.lock().unwrap() =
+            (target_per_sec != 0).then(|| AdaptiveSampler::new(target_per_sec));
+    }
+
+    /// 生存バイト数が前回記録したときと違う`step_bytes`の倍数の区間に入ったときだけ
+    /// 履歴に入れる
+    ///
+    /// 長時間の実行でもメモリ使用量の大きな変化だけが残る。集計はすべての操作で更新される。
+    /// `0`を渡すとすべての操作を記録する設定に戻す。
+    pub fn set_record_on_step(&self, step_bytes: u64) {
+        let mut tracker = self.shared.tracker.write().unwrap();
+        tracker.record_step = (step_bytes != 0).then(|| RecordStep {
+            step: step_bytes,
+            last_bucket: tracker.live_bytes / step_bytes,
+        });
+    }
+
+    /// `n`件に1件だけ履歴に入れる
+    ///
+    /// 「どれだけ記録するか」を決める設定で、[`DebugAlloc::watch`]などの通知には影響しない。
+    /// 通知の頻度は[`DebugAlloc::set_notify_sample`]で別に決める。
+    /// 集計はすべての操作で更新される。`0`か`1`を渡すとすべて記録する。
+    pub fn set_sample_rate(&self, n: u32) {
+        self.shared.sample_every.store(n, Ordering::Relaxed);
+    }
+
+    /// `n`件に1件だけ[`DebugAlloc::watch`]などのコールバックを呼ぶ
+    ///
+    /// 「どれだけ反応するか」を決める設定で、履歴に入るかどうか
+    /// ([`DebugAlloc::set_sample_rate`])とは独立している。
+    /// 履歴はすべて残しつつ、重い通知処理だけを間引ける。`0`か`1`を渡すと毎回呼ぶ。
+    /// [`DebugAlloc::subscribe`]などの受信側や[`DebugAlloc::attach_rotating_stream`]も
+    /// 同じように間引く。間引かない監視は[`DebugAlloc::set_watch_unsampled`]で選ぶ。
+    pub fn set_notify_sample(&self, n: u32) {
+        self.shared.notify_every.store(n, Ordering::Relaxed);
+    }
+
+    /// 直近1秒間に履歴に入った操作の割合(`0.0`〜`1.0`)
+    ///
+    /// 間引きをしていなければ常に`1.0`
+    pub fn effective_sample_rate(&self) -> f64 {
+        match &*self.shared.sampler.lock().unwrap() {
+            Some(sampler) if sampler.window_start.elapsed().as_secs() >= 1 => {
+                sampler.current_rate()
+            }
+            Some(sampler) => sampler
+                .last_window_rate
+                .unwrap_or_else(|| sampler.current_rate()),
+            None => 1.0,
+        }
+    }
+
+    pub(super) fn should_notify(&self, seq: u64) -> bool {
+        every_nth(&self.shared.notify_every, seq)
+    }
+
+    pub(super) fn sample(&self, seq: u64) -> bool {
+        if !every_nth(&self.shared.sample_every, seq) {
+            return false;
+        }
+        match self.shared.sampler.lock() {
+            Ok(mut sampler) => sampler.as_mut().is_none_or(|sampler| sampler.sample()),
+            Err(_) => true,
+        }
+    }
+}
+
+fn every_nth(n: &AtomicU32, seq: u64) -> bool {
+    match n.load(Ordering::Relaxed) {
+        0 | 1 => true,
+        n => seq.is_multiple_of(n as u64),
+    }
+}
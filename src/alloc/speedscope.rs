@@ -0,0 +1,130 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use super::{json, DebugAlloc, Kind};
+
+/// `関数名 at ファイル:行:列`の形式のフレームをspeedscopeのフレームとして書き出す
+fn write_frame<W: Write>(w: &mut W, frame: &str) -> io::Result<()> {
+    let (name, location) = frame.split_once(" at ").unwrap_or((frame, ""));
+    w.write_all(b"{\"name\":")?;
+    json::write_str(w, name)?;
+    let mut parts = location.rsplitn(3, ':');
+    let (col, line, file) = (parts.next(), parts.next(), parts.next());
+    if let (Some(file), Some(Ok(line)), Some(Ok(col))) = (
+        file,
+        line.map(str::parse::<u32>),
+        col.map(str::parse::<u32>),
+    ) {
+        w.write_all(b",\"file\":")?;
+        json::write_str(w, file)?;
+        write!(w, ",\"line\":{line},\"col\":{col}")?;
+    }
+    w.write_all(b"}")
+}
+
+impl<A> DebugAlloc<A> {
+    /// 確保したバイト数を呼び出し履歴ごとに集計し、speedscopeの形式で`path`に書き出す
+    ///
+    /// 詳しくは[`DebugAlloc::write_speedscope`]を参照。
+    pub fn export_speedscope(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write_speedscope(&mut w)?;
+        w.flush()
+    }
+
+    /// 確保したバイト数を呼び出し履歴ごとに集計し、speedscopeのJSONとして`w`に書き出す
+    ///
+    /// 書き出したファイルを<https://www.speedscope.app>で開くと、確保したバイト数の
+    /// フレームグラフになる。allocate/allocate_zeroedはサイズを、grow/grow_zeroedは
+    /// 増えた分を重みにする。呼び出し履歴を記録していない操作は`[unknown]`にまとめる。
+    pub fn write_speedscope<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let history = self.history();
+        let mut frames = Vec::<&str>::new();
+        let mut frame_index = HashMap::<&str, usize>::new();
+        // 根から順のフレーム番号の列 → 確保したバイト数
+        let mut stacks = HashMap::<Vec<usize>, u64>::new();
+        let mut order = Vec::<Vec<usize>>::new();
+        for action in history.iter() {
+            if action.addr.is_none() {
+                continue;
+            }
+            let bytes = match action.kind {
+                Kind::Allocate | Kind::AllocateZeroed => action.layout.size(),
+                Kind::Grow(old_layout) | Kind::GrowZeroed(old_layout) => {
+                    action.layout.size().saturating_sub(old_layout.size())
+                }
+                Kind::Deallocate | Kind::Shrink(_) => 0,
+            };
+            if bytes == 0 {
+                continue;
+            }
+            let mut stack = action
+                .backtrace
+                .iter()
+                .flat_map(|backtrace| backtrace.frames().iter().rev())
+                .map(String::as_str)
+                .collect::<Vec<_>>();
+            if stack.is_empty() {
+                stack.push("[unknown]");
+            }
+            let stack = stack
+                .into_iter()
+                .map(|name| {
+                    *frame_index.entry(name).or_insert_with(|| {
+                        frames.push(name);
+                        frames.len() - 1
+                    })
+                })
+                .collect::<Vec<_>>();
+            let total = stacks.entry(stack).or_insert_with_key(|stack| {
+                order.push(stack.clone());
+                0
+            });
+            *total += bytes as u64;
+        }
+
+        w.write_all(
+            b"{\"$schema\":\"https://www.speedscope.app/file-format-schema.json\",\
+              \"exporter\":\"debug-allocator\",\"name\":\"allocated bytes\",\
+              \"activeProfileIndex\":0,\"shared\":{\"frames\":[",
+        )?;
+        for (i, frame) in frames.iter().enumerate() {
+            if i != 0 {
+                w.write_all(b",")?;
+            }
+            w.write_all(b"\n")?;
+            write_frame(w, frame)?;
+        }
+        let total = stacks.values().sum::<u64>();
+        write!(
+            w,
+            "\n]}},\"profiles\":[{{\"type\":\"sampled\",\"name\":\"allocated bytes\",\
+             \"unit\":\"bytes\",\"startValue\":0,\"endValue\":{total},\"samples\":["
+        )?;
+        for (i, stack) in order.iter().enumerate() {
+            if i != 0 {
+                w.write_all(b",")?;
+            }
+            w.write_all(b"\n[")?;
+            for (j, frame) in stack.iter().enumerate() {
+                if j != 0 {
+                    w.write_all(b",")?;
+                }
+                write!(w, "{frame}")?;
+            }
+            w.write_all(b"]")?;
+        }
+        w.write_all(b"\n],\"weights\":[")?;
+        for (i, stack) in order.iter().enumerate() {
+            if i != 0 {
+                w.write_all(b",")?;
+            }
+            write!(w, "{}", stacks[stack])?;
+        }
+        w.write_all(b"]}]}\n")
+    }
+}
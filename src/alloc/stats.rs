@@ -0,0 +1,256 @@
+use std::collections::BTreeMap;
+
+use super::{Action, DebugAlloc, Kind};
+
+/// 操作の回数とバイト数の集計
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct AllocStats {
+    /// 成功した`allocate`/`allocate_zeroed`の回数
+    pub allocations: usize,
+    pub deallocations: usize,
+    /// 成功した`grow`/`grow_zeroed`の回数
+    pub grows: usize,
+    /// 成功した`shrink`の回数
+    pub shrinks: usize,
+    /// 失敗した操作の回数
+    pub failures: usize,
+    /// 確保と拡張で増えたバイト数の合計
+    pub allocated_bytes: u64,
+    /// 解放と縮小で減ったバイト数の合計
+    pub freed_bytes: u64,
+    /// 生存中の確保の数
+    pub live_allocations: usize,
+    /// 生存中の確保の合計バイト数
+    pub live_bytes: u64,
+}
+
+impl AllocStats {
+    /// 累計の項目に`action`を加える(`live_*`は変更しない)
+    pub(super) fn count(&mut self, action: &Action) {
+        if action.addr.is_none() {
+            self.failures += 1;
+            return;
+        }
+        let size = action.layout.size() as u64;
+        match action.kind {
+            Kind::Allocate | Kind::AllocateZeroed => {
+                self.allocations += 1;
+                self.allocated_bytes += size;
+            }
+            Kind::Deallocate => {
+                self.deallocations += 1;
+                self.freed_bytes += size;
+            }
+            Kind::Grow(old_layout) | Kind::GrowZeroed(old_layout) => {
+                self.grows += 1;
+                self.allocated_bytes += size.saturating_sub(old_layout.size() as u64);
+            }
+            Kind::Shrink(old_layout) => {
+                self.shrinks += 1;
+                self.freed_bytes += (old_layout.size() as u64).saturating_sub(size);
+            }
+        }
+    }
+
+    /// `earlier`からの累計の増分(`live_*`は0になる)
+    pub(super) fn since(&self, earlier: &Self) -> Self {
+        Self {
+            allocations: self.allocations.saturating_sub(earlier.allocations),
+            deallocations: self.deallocations.saturating_sub(earlier.deallocations),
+            grows: self.grows.saturating_sub(earlier.grows),
+            shrinks: self.shrinks.saturating_sub(earlier.shrinks),
+            failures: self.failures.saturating_sub(earlier.failures),
+            allocated_bytes: self.allocated_bytes.saturating_sub(earlier.allocated_bytes),
+            freed_bytes: self.freed_bytes.saturating_sub(earlier.freed_bytes),
+            live_allocations: 0,
+            live_bytes: 0,
+        }
+    }
+
+    fn with_live<'a>(mut self, live: impl IntoIterator<Item = &'a Action>) -> Self {
+        for action in live {
+            self.live_allocations += 1;
+            self.live_bytes += action.layout.size() as u64;
+        }
+        self
+    }
+}
+
+impl<'a> FromIterator<&'a Action> for AllocStats {
+    fn from_iter<T: IntoIterator<Item = &'a Action>>(iter: T) -> Self {
+        let mut stats = Self::default();
+        for action in iter {
+            stats.count(action);
+        }
+        stats
+    }
+}
+
+impl<A> DebugAlloc<A> {
+    /// 生成されてからの集計を返す
+    ///
+    /// 履歴を削除しても値は変わらない。
+    pub fn stats(&self) -> AllocStats {
+        let tracker = self.shared.tracker.read().unwrap();
+        AllocStats {
+            live_allocations: tracker.live.len(),
+            live_bytes: tracker.live_bytes,
+            ..tracker.stats
+        }
+    }
+
+    /// 今確保されているバイト数
+    ///
+    /// [`DebugAlloc::live_bytes`]と同じで、履歴を削除しても正しい値を返す。
+    pub fn current_usage(&self) -> u64 {
+        self.live_bytes()
+    }
+
+    /// 確保されていたバイト数の最大値
+    ///
+    /// 操作のたびに更新するので、履歴を削除しても失われない。
+    pub fn peak_usage(&self) -> u64 {
+        self.shared.tracker.read().unwrap().peak_bytes
+    }
+
+    /// 累計の回数とバイト数を0に戻す
+    ///
+    /// 生存中の確保の一覧と履歴はそのまま残るので、リークの追跡を続けたまま
+    /// 新しい区間の累計を取れる。
+    ///
+    /// リセットの種類と影響する状態:
+    ///
+    /// | メソッド | 累計 | 生存中の確保 | 履歴 |
+    /// |---|---|---|---|
+    /// | [`DebugAlloc::reset_counters`] | 0に戻す | 残す | 残す |
+    /// | [`DebugAlloc::reset_stats`] | 0に戻す | 忘れる | 残す |
+    /// | [`DebugAlloc::clear_history`] | 残す | 残す | 削除する |
+    ///
+    /// [`DebugAlloc::peak_usage`]は累計と同じように扱い、今の値から測り直す。
+    pub fn reset_counters(&self) {
+        let mut tracker = self.shared.tracker.write().unwrap();
+        tracker.stats = AllocStats::default();
+        tracker.peak_bytes = tracker.live_bytes;
+    }
+
+    /// 累計を0に戻し、今生存中の確保を忘れる
+    ///
+    /// 今の状態を基準にして数え直す。それ以前に確保されたブロックの解放は
+    /// 生存中の確保の一覧に影響しない。履歴は残る。
+    /// 他のリセットとの違いは[`DebugAlloc::reset_counters`]を参照。
+    pub fn reset_stats(&self) {
+        let mut tracker = self.shared.tracker.write().unwrap();
+        tracker.stats = AllocStats::default();
+        tracker.live.clear();
+        tracker.live_bytes = 0;
+        tracker.peak_bytes = 0;
+    }
+
+    /// 計測を開始する
+    ///
+    /// これ以降の操作だけが`measured_*`の対象になる。履歴はそのまま残る。
+    /// 再度呼ぶと開始位置を今に移す。
+    pub fn begin_measurement(&self) {
+        let mut tracker = self.shared.tracker.write().unwrap();
+        tracker.measurement_start = Some(tracker.next_seq);
+    }
+
+    /// 計測の開始位置の通し番号(計測していなければ`None`)
+    pub fn measurement_start(&self) -> Option<u64> {
+        self.shared.tracker.read().unwrap().measurement_start
+    }
+
+    /// 計測を開始してから履歴に記録された操作の集計
+    ///
+    /// `live_*`は計測開始後に確保またはサイズ変更されて、まだ生存中のブロックを数える。
+    /// 計測していなければ履歴全体を対象にする。
+    pub fn measured_stats(&self) -> AllocStats {
+        let start = self.measurement_start().unwrap_or(0);
+        let stats = self
+            .history()
+            .iter()
+            .filter(|action| action.seq >= start)
+            .collect::<AllocStats>();
+        stats.with_live(&self.measured_outstanding())
+    }
+
+    /// 計測を開始してから確保またはサイズ変更されて、まだ生存中のブロック(通し番号順)
+    pub fn measured_outstanding(&self) -> Vec<Action> {
+        self.live_since(self.measurement_start().unwrap_or(0))
+    }
+
+    /// 通し番号が`seq`以降の操作で確保またはサイズ変更された生存中のブロック(通し番号順)
+    pub(super) fn live_since(&self, seq: u64) -> Vec<Action> {
+        let tracker = self.shared.tracker.read().unwrap();
+        let mut live = tracker
+            .live
+            .values()
+            .filter(|action| action.seq >= seq)
+            .cloned()
+            .collect::<Vec<_>>();
+        live.sort_unstable_by_key(|action| action.seq);
+        live
+    }
+
+    /// `op`を繰り返しても生存バイト数が増えないことを確かめる
+    ///
+    /// 一度`op`を空回ししてから`reps`回実行し、最後の実行後の生存バイト数が
+    /// 最初の実行後より増えていればpanicする。
+    pub fn assert_steady_state(&self, reps: usize, op: impl FnMut()) {
+        self.assert_steady_state_with_tolerance(reps, 0, op);
+    }
+
+    /// `tolerance`バイトまでの増加を許す[`DebugAlloc::assert_steady_state`]
+    pub fn assert_steady_state_with_tolerance(
+        &self,
+        reps: usize,
+        tolerance: u64,
+        mut op: impl FnMut(),
+    ) {
+        op();
+        if reps == 0 {
+            return;
+        }
+        op();
+        let (baseline, since) = {
+            let tracker = self.shared.tracker.read().unwrap();
+            (tracker.live_bytes, tracker.next_seq)
+        };
+        for _ in 1..reps {
+            op();
+        }
+        let last = self.live_bytes();
+        if last > baseline + tolerance {
+            let leaked = self.live_since(since);
+            let mut msg = format!(
+                "live bytes grew from {baseline} to {last} over {reps} repetitions \
+                 (tolerance: {tolerance}), {} allocations still live:\n",
+                leaked.len()
+            );
+            for action in &leaked {
+                msg += &action.to_string();
+            }
+            panic!("{msg}");
+        }
+    }
+
+    /// アラインメントごとの集計
+    ///
+    /// 回数と累計のバイト数は履歴から、`live_*`は生存中の確保から数える。
+    pub fn stats_by_alignment(&self) -> BTreeMap<usize, AllocStats> {
+        let mut groups = BTreeMap::<usize, AllocStats>::new();
+        for action in self.history().iter() {
+            groups
+                .entry(action.layout.align())
+                .or_default()
+                .count(action);
+        }
+        let tracker = self.shared.tracker.read().unwrap();
+        for action in tracker.live.values() {
+            let stats = groups.entry(action.layout.align()).or_default();
+            stats.live_allocations += 1;
+            stats.live_bytes += action.layout.size() as u64;
+        }
+        groups
+    }
+}
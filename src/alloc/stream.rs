@@ -0,0 +1,137 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use super::{record, Action, DebugAlloc, WatchHandle};
+
+const PREFIX: &str = "debug-allocator.";
+const EXTENSION: &str = ".bin";
+
+/// 一定の大きさごとに新しいファイルに切り替えて書き出す先
+struct RotatingFiles {
+    dir: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    /// 書き出し中のファイルとその大きさ
+    file: File,
+    written: u64,
+    next_index: u64,
+    /// 残しているファイル(古い順、書き出し中のものを含む)
+    files: VecDeque<PathBuf>,
+}
+
+impl RotatingFiles {
+    fn new(dir: &Path, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        // 以前の書き出しを上書きしないように、既存のファイルより後の番号から始める
+        let mut next_index = 0;
+        for entry in fs::read_dir(dir)? {
+            let name = entry?.file_name();
+            let index = name
+                .to_str()
+                .and_then(|name| name.strip_prefix(PREFIX)?.strip_suffix(EXTENSION))
+                .and_then(|index| index.parse::<u64>().ok());
+            if let Some(index) = index {
+                next_index = next_index.max(index + 1);
+            }
+        }
+        let (file, path) = Self::create(dir, next_index)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            max_bytes,
+            max_files: max_files.max(1),
+            file,
+            written: record::MAGIC.len() as u64,
+            next_index: next_index + 1,
+            files: VecDeque::from([path]),
+        })
+    }
+
+    fn create(dir: &Path, index: u64) -> io::Result<(File, PathBuf)> {
+        let path = dir.join(format!("{PREFIX}{index:08}{EXTENSION}"));
+        let mut file = File::create(&path)?;
+        file.write_all(record::MAGIC)?;
+        Ok((file, path))
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let (file, path) = Self::create(&self.dir, self.next_index)?;
+        self.next_index += 1;
+        self.file = file;
+        self.written = record::MAGIC.len() as u64;
+        self.files.push_back(path);
+        while self.files.len() > self.max_files {
+            if let Some(oldest) = self.files.pop_front() {
+                fs::remove_file(oldest)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, record: &[u8]) -> io::Result<()> {
+        let len = record.len() as u64;
+        if self.written > record::MAGIC.len() as u64 && self.written + len > self.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(record)?;
+        self.written += len;
+        Ok(())
+    }
+}
+
+impl<A> DebugAlloc<A> {
+    /// 記録した操作を`dir`の中のファイルにバイナリ形式で書き出し続ける
+    ///
+    /// 1つのファイルが`max_bytes`に達すると新しいファイルに切り替え、
+    /// `max_files`個を超えた古いファイルは削除する。
+    /// レコードはバッファせずにすぐ書き込むので、プロセスが落ちても直前までの操作が残る。
+    /// 書き込みに失敗するとそれ以降は書き出さない。
+    /// 履歴に入らない操作も書き出すが、[`DebugAlloc::set_notify_sample`]で間引いた操作は
+    /// 書き出さない(すべて書き出すには[`DebugAlloc::set_watch_unsampled`]を使う)。
+    /// 返したハンドルを[`DebugAlloc::unwatch`]に渡すと書き出しを止める。
+    pub fn attach_rotating_stream(
+        &self,
+        dir: impl AsRef<Path>,
+        max_bytes: u64,
+        max_files: usize,
+    ) -> io::Result<WatchHandle> {
+        let files = Mutex::new(Some(RotatingFiles::new(
+            dir.as_ref(),
+            max_bytes,
+            max_files,
+        )?));
+        Ok(self.on_action(move |action| {
+            let Ok(mut files) = files.lock() else {
+                return;
+            };
+            if let Some(sink) = files.as_mut() {
+                if sink.write(&record::encode(action)).is_err() {
+                    *files = None;
+                }
+            }
+        }))
+    }
+}
+
+/// [`DebugAlloc::attach_rotating_stream`]で書き出した1つのファイルを読み込む
+///
+/// 書き込みの途中でプロセスが落ちて最後のレコードが欠けていれば、そのレコードは無視する。
+/// 複数のファイルに分かれていれば、番号の順に読み込んでつなげる。
+pub fn read_stream<R: Read>(mut r: R) -> io::Result<Vec<Action>> {
+    let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let mut buf = Vec::new();
+    r.read_to_end(&mut buf)?;
+    let records = buf
+        .strip_prefix(record::MAGIC)
+        .ok_or_else(|| invalid("not a debug-allocator stream"))?;
+    records
+        .chunks_exact(record::RECORD_LEN)
+        .map(|chunk| {
+            record::decode(chunk.try_into().unwrap()).ok_or_else(|| invalid("invalid record"))
+        })
+        .collect()
+}
@@ -0,0 +1,46 @@
+use std::{alloc::Layout, ptr::NonNull, sync::atomic::Ordering};
+
+use super::{AllocAnomaly, DebugAlloc, Kind};
+
+impl<A> DebugAlloc<A> {
+    /// 内部の割り当て器が返したメモリを読み直して確かめるかどうかを設定する
+    ///
+    /// 有効にすると、`allocate_zeroed`と`grow_zeroed`が返した領域のうち0であるべき範囲を
+    /// 読み、0でないバイトがあれば[`AllocAnomaly::NotZeroed`]を報告する。
+    /// また、返されたスライスの長さが要求したサイズより短ければ
+    /// [`AllocAnomaly::ShortLength`]を報告する。アラインメントは厳密モードでなくても常に
+    /// 確かめる([`AllocAnomaly::UnderAligned`])。
+    /// 自作の割り当て器を検証するのに使う。返した領域を全部読むので遅くなる。
+    pub fn set_strict(&self, strict: bool) {
+        self.shared.strict.store(strict, Ordering::Relaxed);
+    }
+
+    /// 厳密モードなら、内部の割り当て器が`kind`の操作で返した`ptr`を確かめる
+    pub(super) fn audit(&self, kind: Kind, layout: Layout, ptr: NonNull<[u8]>) {
+        if !self.shared.strict.load(Ordering::Relaxed) {
+            return;
+        }
+        if ptr.len() < layout.size() {
+            self.report_anomaly(AllocAnomaly::ShortLength {
+                addr: ptr.cast(),
+                layout,
+                len: ptr.len(),
+            });
+        }
+        let zeroed = match kind {
+            Kind::AllocateZeroed => 0..layout.size(),
+            Kind::GrowZeroed(old_layout) => old_layout.size()..layout.size(),
+            _ => return,
+        };
+        let data = unsafe {
+            std::slice::from_raw_parts(ptr.cast::<u8>().as_ptr().add(zeroed.start), zeroed.len())
+        };
+        if let Some(i) = data.iter().position(|&b| b != 0) {
+            self.report_anomaly(AllocAnomaly::NotZeroed {
+                addr: ptr.cast(),
+                layout,
+                offset: zeroed.start + i,
+            });
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+
+use super::{DebugAlloc, Kind};
+
+/// 描画領域の幅[px]
+const WIDTH: usize = 1000;
+/// 1つのブロックの行の高さ[px]
+const ROW: usize = 4;
+const MARGIN: usize = 20;
+
+/// 描画する1つのブロック
+struct Bar {
+    seq: u64,
+    addr: usize,
+    size: usize,
+    /// 置かれたときと外れたときの履歴の位置(外れていなければ`None`)
+    start: usize,
+    end: Option<usize>,
+}
+
+/// サイズから色を決める(小さいほど青く、大きいほど赤い)
+fn color(size: usize) -> String {
+    let class = (usize::BITS - size.leading_zeros()).min(32);
+    let hue = 240 - class * 240 / 32;
+    format!("hsl({hue},70%,50%)")
+}
+
+impl<A> DebugAlloc<A> {
+    /// 各ブロックの生存期間を横棒にしたSVGを書き出す
+    ///
+    /// 横軸は履歴の中の位置で、1行に1つのブロックを置かれた順に並べる。棒の色はサイズで、
+    /// 小さいものは青、大きいものは赤になる。grow/shrinkは元のブロックの終わりと
+    /// 新しいブロックの始まりとして扱い、最後まで解放されなかったブロックは右端まで
+    /// 伸ばして黒い枠を付ける。各棒にマウスを乗せると通し番号、アドレス、サイズが出る。
+    pub fn export_svg<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let history = self.history();
+        let mut bars = Vec::<Bar>::new();
+        // アドレス → `bars`の添字
+        let mut live = HashMap::<usize, usize>::new();
+        for (i, action) in history.iter().enumerate() {
+            let Some(addr) = action.addr else {
+                continue;
+            };
+            let addr = addr.as_ptr() as usize;
+            let released = match action.kind {
+                Kind::Deallocate => live.remove(&addr),
+                _ => action
+                    .old_addr
+                    .and_then(|old_addr| live.remove(&(old_addr.as_ptr() as usize))),
+            };
+            if let Some(bar) = released {
+                bars[bar].end = Some(i);
+            }
+            if action.kind == Kind::Deallocate || action.layout.size() == 0 {
+                continue;
+            }
+            live.insert(addr, bars.len());
+            bars.push(Bar {
+                seq: action.seq,
+                addr,
+                size: action.layout.size(),
+                start: i,
+                end: None,
+            });
+        }
+
+        let len = history.len().max(1);
+        let x = |i: usize| MARGIN as f64 + (i * WIDTH) as f64 / len as f64;
+        let height = bars.len() * ROW + 2 * MARGIN;
+        writeln!(
+            w,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{height}\" \
+             font-family=\"monospace\" font-size=\"10\">",
+            WIDTH + 2 * MARGIN
+        )?;
+        writeln!(
+            w,
+            "<text x=\"{MARGIN}\" y=\"{}\">{} blocks, {} actions</text>",
+            MARGIN - 6,
+            bars.len(),
+            history.len()
+        )?;
+        for (row, bar) in bars.iter().enumerate() {
+            let start = x(bar.start);
+            let end = x(bar.end.unwrap_or(len));
+            let stroke = if bar.end.is_none() {
+                " stroke=\"black\" stroke-width=\"0.5\""
+            } else {
+                ""
+            };
+            writeln!(
+                w,
+                "<rect x=\"{start:.1}\" y=\"{}\" width=\"{:.1}\" height=\"{}\" fill=\"{}\"{stroke}>\
+                 <title>#{} {:#x} {} bytes</title></rect>",
+                MARGIN + row * ROW,
+                (end - start).max(1.0),
+                ROW - 1,
+                color(bar.size),
+                bar.seq,
+                bar.addr,
+                bar.size
+            )?;
+        }
+        writeln!(w, "</svg>")
+    }
+}
@@ -0,0 +1,202 @@
+use std::{alloc::System, io, os::unix::net::UnixDatagram, process, sync::Arc};
+
+use super::{Action, AllocAnomaly, DebugAlloc, Shared};
+
+/// syslogの重大度
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Emergency = 0,
+    Alert = 1,
+    Critical = 2,
+    Error = 3,
+    Warning = 4,
+    Notice = 5,
+    Informational = 6,
+    Debug = 7,
+}
+
+/// facility: user
+const FACILITY_USER: u8 = 1;
+
+#[derive(Debug)]
+pub(super) struct SyslogSink {
+    socket: UnixDatagram,
+    level: Severity,
+}
+
+impl SyslogSink {
+    fn connect(level: Severity) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect("/dev/log")?;
+        Ok(Self { socket, level })
+    }
+
+    fn send(&self, severity: Severity, msg: &str) {
+        if severity > self.level {
+            return;
+        }
+        let line = format!(
+            "<{}>debug-allocator[{}]: {msg}",
+            FACILITY_USER * 8 + severity as u8,
+            process::id()
+        );
+        // ログが送れなくても割り当て自体は続ける
+        let _ = self.socket.send(line.as_bytes());
+    }
+
+    pub(super) fn failure(&self, action: &Action) {
+        let mut msg = format!(
+            "event=failure kind={} size={} align={}",
+            action.kind.name(),
+            action.layout.size(),
+            action.layout.align()
+        );
+        if let Some(old_layout) = action.kind.old_layout() {
+            msg += &format!(
+                " old_size={} old_align={}",
+                old_layout.size(),
+                old_layout.align()
+            );
+        }
+        self.send(Severity::Error, &msg);
+    }
+
+    pub(super) fn anomaly(&self, anomaly: &AllocAnomaly) {
+        match anomaly {
+            AllocAnomaly::UnderAligned { addr, layout } => self.send(
+                Severity::Warning,
+                &format!(
+                    "event=under_aligned size={} align={} addr={:p}",
+                    layout.size(),
+                    layout.align(),
+                    *addr
+                ),
+            ),
+            AllocAnomaly::StaleGeneration {
+                addr,
+                expected,
+                actual,
+            } => self.send(
+                Severity::Warning,
+                &format!(
+                    "event=stale_generation expected={expected} actual={actual} addr={:p}",
+                    *addr
+                ),
+            ),
+            AllocAnomaly::CrossThreadFree {
+                alloc_thread,
+                free_thread,
+                addr,
+            } => self.send(
+                Severity::Warning,
+                &format!(
+                    "event=cross_thread_free alloc_thread={alloc_thread} free_thread={free_thread} addr={:p}",
+                    *addr
+                ),
+            ),
+            AllocAnomaly::DoubleFree { addr, prior_seq } => self.send(
+                Severity::Error,
+                &format!(
+                    "event=double_free prior_seq={prior_seq} addr={:p}",
+                    *addr
+                ),
+            ),
+            AllocAnomaly::LayoutMismatch {
+                addr,
+                expected,
+                actual,
+            } => self.send(
+                Severity::Error,
+                &format!(
+                    "event=layout_mismatch expected_size={} expected_align={} size={} align={} addr={:p}",
+                    expected.size(),
+                    expected.align(),
+                    actual.size(),
+                    actual.align(),
+                    *addr
+                ),
+            ),
+            AllocAnomaly::RedZoneCorrupted {
+                addr,
+                layout,
+                offset,
+                alloc_seq,
+            } => self.send(
+                Severity::Error,
+                &format!(
+                    "event=red_zone_corrupted size={} align={} offset={offset} alloc_seq={} addr={:p}",
+                    layout.size(),
+                    layout.align(),
+                    alloc_seq.map_or(-1, |seq| seq as i64),
+                    *addr
+                ),
+            ),
+            AllocAnomaly::UseAfterFree {
+                addr,
+                layout,
+                offset,
+                free_seq,
+            } => self.send(
+                Severity::Error,
+                &format!(
+                    "event=use_after_free size={} align={} offset={offset} free_seq={free_seq} addr={:p}",
+                    layout.size(),
+                    layout.align(),
+                    *addr
+                ),
+            ),
+            AllocAnomaly::NotZeroed {
+                addr,
+                layout,
+                offset,
+            } => self.send(
+                Severity::Error,
+                &format!(
+                    "event=not_zeroed size={} align={} offset={offset} addr={:p}",
+                    layout.size(),
+                    layout.align(),
+                    *addr
+                ),
+            ),
+            AllocAnomaly::ShortLength { addr, layout, len } => self.send(
+                Severity::Error,
+                &format!(
+                    "event=short_length size={} align={} len={len} addr={:p}",
+                    layout.size(),
+                    layout.align(),
+                    *addr
+                ),
+            ),
+            AllocAnomaly::BrokenContract { violation, action } => self.send(
+                Severity::Error,
+                &format!(
+                    "event=broken_contract violation=\"{violation}\" seq={} kind={} size={} align={} addr={:#x}",
+                    action.seq,
+                    action.kind.name(),
+                    action.layout.size(),
+                    action.layout.align(),
+                    action.old_addr.map_or(0, |addr| addr.as_ptr() as usize)
+                ),
+            ),
+        }
+    }
+}
+
+impl<A> DebugAlloc<A> {
+    /// 割り当ての失敗と異常を`/dev/log`に送る
+    ///
+    /// すべての操作ではなく、失敗(`Error`)と異常(`Warning`など)だけを送る。
+    /// `level`より重大度の低いものは送らない。
+    pub fn with_syslog(alloc: A, level: Severity) -> io::Result<Self> {
+        Ok(Self {
+            alloc,
+            shared: Arc::new_in(
+                Shared {
+                    syslog: Some(SyslogSink::connect(level)?),
+                    ..Default::default()
+                },
+                System,
+            ),
+        })
+    }
+}
@@ -0,0 +1,99 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    io::{self, IsTerminal, Write},
+    thread,
+};
+
+use super::{write_action, DebugAlloc};
+
+/// 記録のたびに確保しないように、スレッド名を固定長で持つ
+///
+/// [`ThreadName::CAPACITY`]バイトを超える名前は文字の境界で切り詰める。
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ThreadName {
+    bytes: [u8; Self::CAPACITY],
+    len: u8,
+}
+
+impl ThreadName {
+    pub const CAPACITY: usize = 32;
+
+    pub fn new(name: &str) -> Self {
+        let mut len = name.len().min(Self::CAPACITY);
+        while !name.is_char_boundary(len) {
+            len -= 1;
+        }
+        let mut bytes = [0; Self::CAPACITY];
+        bytes[..len].copy_from_slice(&name.as_bytes()[..len]);
+        Self {
+            bytes,
+            len: len as u8,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // `new`で文字の境界で切っているので常に正しいUTF-8
+        std::str::from_utf8(&self.bytes[..self.len as usize]).unwrap()
+    }
+}
+
+impl Display for ThreadName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for ThreadName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+/// 今のスレッドの`(ThreadId::as_u64, 名前)`
+pub(super) fn current_thread() -> (u64, Option<ThreadName>) {
+    let thread = thread::current();
+    (
+        thread.id().as_u64().get(),
+        thread.name().map(ThreadName::new),
+    )
+}
+
+impl<A> DebugAlloc<A> {
+    /// 履歴をスレッドごとにまとめて表示する
+    ///
+    /// スレッドは最初に操作した順ではなくIDの順に並ぶ。各スレッドの操作は記録した順
+    pub fn dump_by_thread(&self) {
+        let color = self.use_color(io::stdout().is_terminal());
+        self.dump_by_thread_with(&mut io::stdout(), color)
+            .expect("failed printing to stdout");
+    }
+
+    /// 履歴を[`DebugAlloc::dump_by_thread`]と同じ形式で`w`に書き出す
+    pub fn dump_by_thread_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.dump_by_thread_with(w, self.use_color(false))
+    }
+
+    fn dump_by_thread_with<W: Write>(&self, w: &mut W, color: bool) -> io::Result<()> {
+        let formatter = self.formatter();
+        let history = self.history();
+        let mut threads = BTreeMap::new();
+        for action in history.iter() {
+            threads
+                .entry(action.thread_id)
+                .or_insert_with(|| (action.thread_name, Vec::new()))
+                .1
+                .push(action);
+        }
+        for (id, (name, actions)) in threads {
+            match name {
+                Some(name) => writeln!(w, "thread {id} ({name}): {} actions", actions.len())?,
+                None => writeln!(w, "thread {id}: {} actions", actions.len())?,
+            }
+            for action in actions {
+                write_action(w, action, &formatter, color)?;
+            }
+        }
+        Ok(())
+    }
+}
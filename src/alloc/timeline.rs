@@ -0,0 +1,202 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{self, Write},
+    time::Duration,
+};
+
+use super::{DebugAlloc, Kind};
+
+const SPARK_LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// [`DebugAlloc::usage_timeline`]の1つの点
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct UsageSample {
+    /// 操作の通し番号
+    pub seq: u64,
+    /// 操作の経過時間
+    pub timestamp: Duration,
+    /// 操作の直後に生存していたバイト数
+    pub live_bytes: u64,
+}
+
+impl<A> DebugAlloc<A> {
+    /// 履歴の各操作の直後に生存していたバイト数を`(通し番号, バイト数)`で返す
+    ///
+    /// 履歴の先頭を0として積み上げるので、履歴を削除した後は実際の値とずれる。
+    pub fn live_bytes_timeline(&self) -> Vec<(u64, u64)> {
+        let mut live = 0u64;
+        self.history()
+            .iter()
+            .map(|action| {
+                live = live.saturating_add_signed(action.net_bytes());
+                (action.seq, live)
+            })
+            .collect()
+    }
+
+    /// 履歴の各操作の直後に生存していたバイト数を、通し番号と経過時間とともに返す
+    ///
+    /// [`DebugAlloc::live_bytes_timeline`]と同じく、履歴の先頭を0として積み上げる。
+    pub fn usage_timeline(&self) -> Vec<UsageSample> {
+        let mut live = 0u64;
+        self.history()
+            .iter()
+            .map(|action| {
+                live = live.saturating_add_signed(action.net_bytes());
+                UsageSample {
+                    seq: action.seq,
+                    timestamp: action.timestamp,
+                    live_bytes: live,
+                }
+            })
+            .collect()
+    }
+
+    /// [`DebugAlloc::usage_timeline`]を`seq,timestamp_ns,live_bytes`の列のCSVで書き出す
+    ///
+    /// 1行目は見出し。
+    pub fn export_usage_csv<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, "seq,timestamp_ns,live_bytes")?;
+        for sample in self.usage_timeline() {
+            writeln!(
+                w,
+                "{},{},{}",
+                sample.seq,
+                sample.timestamp.as_nanos(),
+                sample.live_bytes
+            )?;
+        }
+        Ok(())
+    }
+
+    /// [`DebugAlloc::usage_timeline`]をgnuplotのデータファイルとして書き出す
+    ///
+    /// 列は空白区切りの`seq timestamp_s live_bytes`で、見出しは`#`のコメントにする。
+    /// 例えば`plot "usage.dat" using 2:3 with steps`で時間に対する推移を描ける。
+    pub fn export_usage_gnuplot<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, "# seq timestamp_s live_bytes")?;
+        for sample in self.usage_timeline() {
+            writeln!(
+                w,
+                "{} {:.9} {}",
+                sample.seq,
+                sample.timestamp.as_secs_f64(),
+                sample.live_bytes
+            )?;
+        }
+        Ok(())
+    }
+
+    /// 履歴の各操作の`(経過時間[ns], 生存バイト数の増減)`
+    ///
+    /// 増減を足し合わせると生存バイト数になる。外部のグラフ描画などに使う。
+    pub fn byte_deltas(&self) -> Vec<(u64, i64)> {
+        self.history()
+            .iter()
+            .map(|action| (action.timestamp.as_nanos() as u64, action.net_bytes()))
+            .collect()
+    }
+
+    /// 履歴を再生し、生存中のブロックが置かれるたびに`f(サイズ, true)`、
+    /// 解放されるたびに`f(サイズ, false)`を呼ぶ(サイズ0は除く)
+    fn replay_live_sizes(&self, mut f: impl FnMut(usize, bool)) {
+        // アドレス → サイズ
+        let mut live = HashMap::<usize, usize>::new();
+        for action in self.history().iter() {
+            let Some(addr) = action.addr else {
+                continue;
+            };
+            let released = match action.kind {
+                Kind::Deallocate => live.remove(&(addr.as_ptr() as usize)),
+                _ => action
+                    .old_addr
+                    .and_then(|old_addr| live.remove(&(old_addr.as_ptr() as usize))),
+            };
+            if let Some(size) = released {
+                f(size, false);
+            }
+            let size = action.layout.size();
+            if action.kind == Kind::Deallocate || size == 0 {
+                continue;
+            }
+            live.insert(addr.as_ptr() as usize, size);
+            f(size, true);
+        }
+    }
+
+    /// サイズごとに、同時に生存していた確保の数の最大値を返す
+    ///
+    /// 履歴を1回再生して求める。固定サイズのオブジェクトプールの大きさの目安になる。
+    pub fn peak_concurrency_by_size(&self) -> BTreeMap<usize, usize> {
+        // サイズ → (今の数, 最大の数)
+        let mut counts = BTreeMap::<usize, (usize, usize)>::new();
+        self.replay_live_sizes(|size, added| {
+            let (count, peak) = counts.entry(size).or_default();
+            if added {
+                *count += 1;
+                *peak = (*peak).max(*count);
+            } else {
+                *count -= 1;
+            }
+        });
+        counts
+            .into_iter()
+            .map(|(size, (_, peak))| (size, peak))
+            .collect()
+    }
+
+    /// 同時に生存していた確保の、異なるサイズの種類の数の最大値
+    ///
+    /// 大きいほどさまざまなサイズの確保が混ざっていて、割り当て器の断片化を招きやすい。
+    pub fn max_live_distinct_sizes(&self) -> usize {
+        // サイズ → 生存中の数
+        let mut sizes = HashMap::<usize, usize>::new();
+        let mut peak = 0;
+        self.replay_live_sizes(|size, added| {
+            if added {
+                *sizes.entry(size).or_default() += 1;
+                peak = peak.max(sizes.len());
+            } else if let Some(count) = sizes.get_mut(&size) {
+                *count -= 1;
+                if *count == 0 {
+                    sizes.remove(&size);
+                }
+            }
+        });
+        peak
+    }
+
+    /// 生存バイト数の推移を幅`width`文字のスパークラインにする
+    ///
+    /// 各列にはその区間の最大値を使う。履歴が空なら空文字列を返す。
+    pub fn sparkline(&self, width: usize) -> String {
+        let timeline = self.live_bytes_timeline();
+        if timeline.is_empty() || width == 0 {
+            return String::new();
+        }
+        let width = width.min(timeline.len());
+        let columns = (0..width)
+            .map(|i| {
+                let start = i * timeline.len() / width;
+                let end = (i + 1) * timeline.len() / width;
+                timeline[start..end]
+                    .iter()
+                    .map(|&(_, bytes)| bytes)
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect::<Vec<_>>();
+        let max = columns.iter().copied().max().unwrap_or(0);
+        columns
+            .into_iter()
+            .map(|bytes| {
+                let level = if max == 0 {
+                    0
+                } else {
+                    (bytes as u128 * (SPARK_LEVELS.len() - 1) as u128 / max as u128) as usize
+                };
+                SPARK_LEVELS[level]
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,104 @@
+use std::{
+    alloc::System,
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+    sync::{atomic::AtomicBool, Arc, Mutex},
+};
+
+use super::{Action, DebugAlloc, Kind, Shared};
+
+/// サイズ(同じなら通し番号)で比較する
+#[derive(Debug)]
+struct BySize(Action);
+
+impl BySize {
+    fn key(&self) -> (usize, u64) {
+        (self.0.layout.size(), self.0.seq)
+    }
+}
+
+impl PartialEq for BySize {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for BySize {}
+
+impl PartialOrd for BySize {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BySize {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+/// サイズの大きいものから`n`個の操作を保持する
+#[derive(Debug)]
+pub(super) struct TopN {
+    n: usize,
+    heap: BinaryHeap<Reverse<BySize>, System>,
+}
+
+impl TopN {
+    pub(super) fn push(&mut self, action: &Action) {
+        if action.addr.is_none() || action.kind == Kind::Deallocate || self.n == 0 {
+            return;
+        }
+        if self.heap.len() < self.n {
+            self.heap.push(Reverse(BySize(action.clone())));
+        } else if let Some(mut min) = self.heap.peek_mut() {
+            if action.layout.size() > min.0 .0.layout.size() {
+                *min = Reverse(BySize(action.clone()));
+            }
+        }
+    }
+}
+
+impl<A> DebugAlloc<A> {
+    /// 通常の履歴に加えて、サイズの大きい確保を`n`個まで保持する
+    ///
+    /// 古い履歴を削除しても、最も大きい確保は[`DebugAlloc::top_n`]で見られる。
+    pub fn with_top_n_by_size(alloc: A, n: usize) -> Self {
+        Self::with_top_n(alloc, n, false)
+    }
+
+    /// 通常の履歴を残さず、サイズの大きい確保を`n`個だけ保持する
+    pub fn top_n_only(alloc: A, n: usize) -> Self {
+        Self::with_top_n(alloc, n, true)
+    }
+
+    fn with_top_n(alloc: A, n: usize, history_disabled: bool) -> Self {
+        Self {
+            alloc,
+            shared: Arc::new_in(
+                Shared {
+                    top_n: Mutex::new(Some(TopN {
+                        n,
+                        heap: BinaryHeap::with_capacity_in(n, System),
+                    })),
+                    history_disabled: AtomicBool::new(history_disabled),
+                    ..Default::default()
+                },
+                System,
+            ),
+        }
+    }
+
+    /// これまでで最も大きい確保(grow/shrinkを含む)をサイズの大きい順に返す
+    ///
+    /// [`DebugAlloc::with_top_n_by_size`]などで作っていなければ空
+    pub fn top_n(&self) -> Vec<Action> {
+        let top_n = self.shared.top_n.lock().unwrap();
+        let mut actions = top_n
+            .iter()
+            .flat_map(|top_n| top_n.heap.iter().map(|entry| entry.0 .0.clone()))
+            .collect::<Vec<_>>();
+        actions.sort_unstable_by_key(|action| Reverse((action.layout.size(), action.seq)));
+        actions
+    }
+}
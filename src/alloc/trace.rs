@@ -0,0 +1,253 @@
+//! 操作のすべての情報を保存する、版付きのトレースファイルの形式
+//!
+//! ファイルの先頭に8バイトの識別子`DALLOCTR`と`u16`の版([`Trace::VERSION`])がある。
+//! その後に操作ごとに、[`DebugAlloc::attach_rotating_stream`]と同じ90バイトの固定長の
+//! レコードと、次の可変長の部分が続く。整数はすべてリトルエンディアン。
+//!
+//! | 型 | 内容 |
+//! |---|---|
+//! | u8 | スレッド名のバイト数(0xFFなら名前なし) |
+//! | \[u8\] | スレッド名(UTF-8) |
+//! | u32 | 呼び出し履歴のフレーム数(0xFFFF_FFFFなら記録なし) |
+//! | (u32, \[u8\])の列 | 各フレームのバイト数とUTF-8の文字列 |
+//!
+//! 呼び出し履歴は`backtrace`機能が無効でも同じ形で書き出し、読み込むときは捨てる。
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use super::{read_rle_binary, read_stream, record, rle, Action, DebugAlloc, ThreadName};
+
+const MAGIC: &[u8; 8] = b"DALLOCTR";
+/// スレッド名や呼び出し履歴がないことを表す長さ
+const NO_NAME: u8 = 0xFF;
+const NO_BACKTRACE: u32 = u32::MAX;
+
+/// 保存した操作の列
+///
+/// [`DebugAlloc::save_trace`]で書き出したファイルを[`Trace::load`]で読み込む。
+/// 通し番号、時刻、スレッド名、呼び出し履歴を含めて操作をそのまま読み戻せるので、
+/// 別のマシンや実行で記録したトレースを[`DebugAlloc::from_actions`]で調べたり、
+/// [`replay_history`](crate::replay_history)で実行し直したりできる。
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Trace {
+    pub actions: Vec<Action>,
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(feature = "backtrace")]
+fn write_bytes<W: Write>(w: &mut W, bytes: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(bytes.len()).map_err(|_| invalid("string too long"))?;
+    w.write_all(&len.to_le_bytes())?;
+    w.write_all(bytes)
+}
+
+fn write_action<W: Write>(w: &mut W, action: &Action) -> io::Result<()> {
+    w.write_all(&record::encode(action))?;
+    match action.thread_name {
+        Some(name) => {
+            w.write_all(&[name.as_str().len() as u8])?;
+            w.write_all(name.as_str().as_bytes())?;
+        }
+        None => w.write_all(&[NO_NAME])?,
+    }
+    #[cfg(feature = "backtrace")]
+    if let Some(backtrace) = &action.backtrace {
+        w.write_all(&(backtrace.frames().len() as u32).to_le_bytes())?;
+        for frame in backtrace.frames() {
+            write_bytes(w, frame.as_bytes())?;
+        }
+        return Ok(());
+    }
+    w.write_all(&NO_BACKTRACE.to_le_bytes())
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+/// `len`バイトの文字列を読む
+///
+/// `len`はファイルから読んだ値なので、先に確保せずに読めた分だけ伸ばす。
+fn read_string<R: Read>(r: &mut R, len: usize) -> io::Result<String> {
+    let mut buf = Vec::new();
+    r.take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() != len {
+        return Err(invalid("truncated string"));
+    }
+    String::from_utf8(buf).map_err(|_| invalid("invalid UTF-8"))
+}
+
+/// 次の操作を読み込む。ファイルがレコードの境界で終わっていれば`None`を返す
+fn read_action<R: Read>(r: &mut R) -> io::Result<Option<Action>> {
+    let mut buf = [0; record::RECORD_LEN];
+    let mut filled = 0;
+    while filled < buf.len() {
+        match r.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    let mut action = record::decode(&buf).ok_or_else(|| invalid("invalid record"))?;
+    let mut len = [0];
+    r.read_exact(&mut len)?;
+    if len[0] != NO_NAME {
+        action.thread_name = Some(ThreadName::new(&read_string(r, len[0] as usize)?));
+    }
+    let frames = read_u32(r)?;
+    if frames != NO_BACKTRACE {
+        let frames = (0..frames)
+            .map(|_| {
+                let len = read_u32(r)?;
+                read_string(r, len as usize)
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        #[cfg(feature = "backtrace")]
+        {
+            action.backtrace = Some(super::AllocBacktrace::from_frames(frames));
+        }
+        #[cfg(not(feature = "backtrace"))]
+        let _ = frames;
+    }
+    Ok(Some(action))
+}
+
+fn write_header<W: Write>(w: &mut W) -> io::Result<()> {
+    w.write_all(MAGIC)?;
+    w.write_all(&Trace::VERSION.to_le_bytes())
+}
+
+impl Trace {
+    /// 書き出すファイルの形式の版
+    pub const VERSION: u16 = 1;
+
+    /// [`DebugAlloc::save_trace`]で書き出したファイルを読み込む
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    /// 先頭の識別子で形式を判別して、`path`の操作の列を読み込む
+    ///
+    /// [`DebugAlloc::save_trace`]、[`DebugAlloc::write_rle_binary`]で書き出したファイルと、
+    /// 識別子がなければ[`DebugAlloc::attach_rotating_stream`]のストリームとして読む。
+    pub fn load_any(path: impl AsRef<Path>) -> io::Result<Vec<Action>> {
+        let data = fs::read(path)?;
+        if data.starts_with(MAGIC) {
+            Ok(Self::read(&data[..])?.actions)
+        } else if data.starts_with(rle::MAGIC) {
+            read_rle_binary(&data[..])
+        } else {
+            read_stream(&data[..])
+        }
+    }
+
+    /// [`DebugAlloc::write_trace`]で書き出したトレースを読み込む
+    ///
+    /// 識別子が違うか、読み込めない版なら[`io::ErrorKind::InvalidData`]のエラーを返す。
+    pub fn read<R: Read>(mut r: R) -> io::Result<Self> {
+        let mut header = [0; 10];
+        r.read_exact(&mut header)?;
+        if &header[..8] != MAGIC {
+            return Err(invalid("not a debug-allocator trace"));
+        }
+        let version = u16::from_le_bytes([header[8], header[9]]);
+        if version != Self::VERSION {
+            return Err(invalid("unsupported trace version"));
+        }
+        let mut actions = Vec::new();
+        while let Some(action) = read_action(&mut r)? {
+            actions.push(action);
+        }
+        Ok(Self { actions })
+    }
+
+    /// トレースを`path`に書き出す
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write(&mut w)?;
+        w.flush()
+    }
+
+    /// トレースを`w`に書き出す
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_header(w)?;
+        for action in &self.actions {
+            write_action(w, action)?;
+        }
+        Ok(())
+    }
+}
+
+impl<A> DebugAlloc<A> {
+    /// 履歴全体を古い順にトレースファイルとして`path`に書き出す
+    ///
+    /// 操作のすべての情報を含み、[`Trace::load`]で読み戻せる。形式は[`Trace`]を参照。
+    pub fn save_trace(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write_trace(&mut w)?;
+        w.flush()
+    }
+
+    /// 履歴全体を古い順にトレースとして`w`に書き出す
+    pub fn write_trace<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_header(w)?;
+        for action in self.history().iter() {
+            write_action(w, action)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        alloc::{Allocator, Layout, System},
+        io,
+    };
+
+    use super::{record, Trace, MAGIC, NO_NAME};
+    use crate::DebugAlloc;
+
+    #[test]
+    fn round_trip() {
+        let alloc = DebugAlloc::new(System);
+        let layout = Layout::new::<[u64; 4]>();
+        let ptr = alloc.allocate(layout).unwrap();
+        let grown = unsafe { alloc.grow(ptr.cast(), layout, Layout::new::<[u64; 8]>()) }.unwrap();
+        unsafe { alloc.deallocate(grown.cast(), Layout::new::<[u64; 8]>()) };
+        let mut buf = Vec::new();
+        alloc.write_trace(&mut buf).unwrap();
+        let trace = Trace::read(&buf[..]).unwrap();
+        assert_eq!(trace.actions, alloc.snapshot());
+    }
+
+    #[test]
+    fn huge_string_length_is_invalid_data() {
+        let alloc = DebugAlloc::new(System);
+        let layout = Layout::new::<u64>();
+        let ptr = alloc.allocate(layout).unwrap();
+        unsafe { alloc.deallocate(ptr.cast(), layout) };
+        let mut buf = Vec::new();
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&Trace::VERSION.to_le_bytes());
+        buf.extend_from_slice(&record::encode(&alloc.snapshot()[0]));
+        buf.push(NO_NAME);
+        // 1フレームで、4GiB近い長さを名乗るのに数バイトしかない
+        buf.extend_from_slice(&1u32.to_le_bytes());
+        buf.extend_from_slice(&0xFFFF_FFF0u32.to_le_bytes());
+        buf.extend_from_slice(b"main");
+        let err = Trace::read(&buf[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,236 @@
+//! グローバルアロケータを使わずに呼び出し履歴を取る
+//!
+//! 記録するときは`_Unwind_Backtrace`で戻りアドレスだけを集め、名前への変換は
+//! フレームを参照したときに行う。名前は読み込まれたオブジェクトファイルのシンボル表
+//! (`.symtab`、なければ`.dynsym`)から引くので、ファイル名と行番号は分からない。
+
+use std::{
+    alloc::System,
+    collections::BTreeMap,
+    ffi::{c_char, c_int, c_void, CStr},
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use super::demangle::demangle;
+
+const URC_NO_REASON: c_int = 0;
+const URC_FAILURE: c_int = 9;
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u64 = 2;
+const SHT_DYNSYM: u64 = 11;
+const STT_FUNC: u8 = 2;
+
+type TraceFn = extern "C" fn(ctx: *mut c_void, arg: *mut c_void) -> c_int;
+
+#[repr(C)]
+struct DlPhdrInfo {
+    addr: usize,
+    name: *const c_char,
+    phdr: *const Phdr,
+    phnum: u16,
+}
+
+#[cfg(target_pointer_width = "64")]
+#[allow(dead_code)]
+#[repr(C)]
+struct Phdr {
+    p_type: u32,
+    p_flags: u32,
+    p_offset: u64,
+    p_vaddr: u64,
+    p_paddr: u64,
+    p_filesz: u64,
+    p_memsz: u64,
+    p_align: u64,
+}
+
+#[cfg(target_pointer_width = "32")]
+#[allow(dead_code)]
+#[repr(C)]
+struct Phdr {
+    p_type: u32,
+    p_offset: u32,
+    p_vaddr: u32,
+    p_paddr: u32,
+    p_filesz: u32,
+    p_memsz: u32,
+    p_flags: u32,
+    p_align: u32,
+}
+
+type PhdrFn = extern "C" fn(info: *mut DlPhdrInfo, size: usize, data: *mut c_void) -> c_int;
+
+extern "C" {
+    fn _Unwind_Backtrace(trace: TraceFn, arg: *mut c_void) -> c_int;
+    fn _Unwind_GetIP(ctx: *mut c_void) -> usize;
+    fn dl_iterate_phdr(callback: PhdrFn, data: *mut c_void) -> c_int;
+}
+
+/// 戻りアドレスを呼び出し元に近い順に`addrs`の容量いっぱいまで積む
+pub(super) fn trace(addrs: &mut Vec<usize, System>) {
+    extern "C" fn push(ctx: *mut c_void, arg: *mut c_void) -> c_int {
+        let addrs = unsafe { &mut *arg.cast::<Vec<usize, System>>() };
+        let ip = unsafe { _Unwind_GetIP(ctx) };
+        if ip == 0 || addrs.len() == addrs.capacity() {
+            return URC_FAILURE;
+        }
+        addrs.push(ip);
+        URC_NO_REASON
+    }
+    unsafe { _Unwind_Backtrace(push, (addrs as *mut Vec<usize, System>).cast()) };
+}
+
+/// 戻りアドレス`addr`を含む関数の名前(分からなければアドレスそのもの)
+pub(super) fn symbolize(addr: usize) -> String {
+    // 戻りアドレスは呼び出し命令の次を指すので、1戻して呼び出し元の関数に入れる
+    let pc = addr.wrapping_sub(1);
+    find_object(pc)
+        .and_then(|(path, base)| {
+            let table = symbol_table(path)?;
+            let name = table.lookup((pc - base) as u64)?;
+            Some(demangle(name).unwrap_or_else(|| name.to_string()))
+        })
+        .unwrap_or_else(|| format!("{addr:#x}"))
+}
+
+/// `pc`を含むオブジェクトファイルのパスと読み込み先の基準アドレス
+fn find_object(pc: usize) -> Option<(PathBuf, usize)> {
+    struct Search {
+        pc: usize,
+        found: Option<(PathBuf, usize)>,
+    }
+
+    extern "C" fn visit(info: *mut DlPhdrInfo, _size: usize, data: *mut c_void) -> c_int {
+        let info = unsafe { &*info };
+        let search = unsafe { &mut *data.cast::<Search>() };
+        let phdrs = unsafe { std::slice::from_raw_parts(info.phdr, info.phnum as usize) };
+        let contains = phdrs.iter().any(|phdr| {
+            let start = info.addr.wrapping_add(phdr.p_vaddr as usize);
+            phdr.p_type == PT_LOAD && (start..start + phdr.p_memsz as usize).contains(&search.pc)
+        });
+        if !contains {
+            return 0;
+        }
+        let name = if info.name.is_null() {
+            c""
+        } else {
+            unsafe { CStr::from_ptr(info.name) }
+        };
+        // 名前が空なのは実行ファイル自身
+        let path = match name.to_str() {
+            Ok("") | Err(_) => PathBuf::from("/proc/self/exe"),
+            Ok(name) => PathBuf::from(name),
+        };
+        search.found = Some((path, info.addr));
+        1
+    }
+
+    let mut search = Search { pc, found: None };
+    unsafe { dl_iterate_phdr(visit, (&mut search as *mut Search).cast()) };
+    search.found
+}
+
+/// 関数のシンボルを開始アドレスの順に並べたもの
+struct SymbolTable {
+    /// (開始アドレス, 大きさ, マングルされた名前)
+    symbols: Vec<(u64, u64, String)>,
+}
+
+impl SymbolTable {
+    fn lookup(&self, offset: u64) -> Option<&str> {
+        let i = self
+            .symbols
+            .partition_point(|&(start, _, _)| start <= offset)
+            .checked_sub(1)?;
+        let (start, size, name) = &self.symbols[i];
+        (*size == 0 || offset < start + size).then_some(name)
+    }
+}
+
+/// オブジェクトファイルのシンボル表(読めなければ`None`)。一度読んだものは覚えておく
+fn symbol_table(path: PathBuf) -> Option<Arc<SymbolTable>> {
+    static TABLES: Mutex<BTreeMap<PathBuf, Option<Arc<SymbolTable>>>> = Mutex::new(BTreeMap::new());
+    let mut tables = TABLES.lock().unwrap_or_else(|e| e.into_inner());
+    tables
+        .entry(path)
+        .or_insert_with_key(|path| {
+            let data = fs::read(path).ok()?;
+            let mut symbols = read_symbols(&data)?;
+            symbols.sort_unstable_by_key(|&(start, _, _)| start);
+            Some(Arc::new(SymbolTable { symbols }))
+        })
+        .clone()
+}
+
+/// ELFファイルから関数のシンボルを読む
+fn read_symbols(data: &[u8]) -> Option<Vec<(u64, u64, String)>> {
+    let native = if cfg!(target_endian = "little") { 1 } else { 2 };
+    if data.get(..4)? != b"\x7fELF" || *data.get(5)? != native {
+        return None;
+    }
+    let wide = match *data.get(4)? {
+        1 => false,
+        2 => true,
+        _ => return None,
+    };
+    let read = |offset: u64, len: usize| -> Option<u64> {
+        let start = usize::try_from(offset).ok()?;
+        let bytes = data.get(start..start.checked_add(len)?)?;
+        Some(match len {
+            1 => bytes[0] as u64,
+            2 => u16::from_ne_bytes(bytes.try_into().ok()?) as u64,
+            4 => u32::from_ne_bytes(bytes.try_into().ok()?) as u64,
+            _ => u64::from_ne_bytes(bytes.try_into().ok()?),
+        })
+    };
+    // 32ビットと64ビットで位置と大きさが異なる欄を読む
+    let field = |offset: u64, narrow: u64, wide_offset: u64| {
+        if wide {
+            read(offset + wide_offset, 8)
+        } else {
+            read(offset + narrow, 4)
+        }
+    };
+    let shoff = field(0, 0x20, 0x28)?;
+    let (shentsize, shnum) = if wide {
+        (read(0x3a, 2)?, read(0x3c, 2)?)
+    } else {
+        (read(0x2e, 2)?, read(0x30, 2)?)
+    };
+    let section = |i: u64| {
+        let header = shoff + i * shentsize;
+        let kind = read(header + 4, 4)?;
+        let offset = field(header, 16, 24)?;
+        let size = field(header, 20, 32)?;
+        let link = read(header + if wide { 40 } else { 24 }, 4)?;
+        let entsize = field(header, 36, 56)?;
+        Some((kind, offset, size, link, entsize))
+    };
+    let sections = (0..shnum).map(section).collect::<Option<Vec<_>>>()?;
+    let (_, offset, size, link, entsize) = *sections
+        .iter()
+        .find(|section| section.0 == SHT_SYMTAB)
+        .or_else(|| sections.iter().find(|section| section.0 == SHT_DYNSYM))?;
+    let (_, strtab, _, _, _) = *sections.get(link as usize)?;
+    if entsize == 0 {
+        return None;
+    }
+    let mut symbols = Vec::new();
+    for i in 0..size / entsize {
+        let sym = offset + i * entsize;
+        let (info, value, len) = if wide {
+            (read(sym + 4, 1)?, read(sym + 8, 8)?, read(sym + 16, 8)?)
+        } else {
+            (read(sym + 12, 1)?, read(sym + 4, 4)?, read(sym + 8, 4)?)
+        };
+        if info as u8 & 0xf != STT_FUNC || value == 0 {
+            continue;
+        }
+        let name_start = usize::try_from(strtab + read(sym, 4)?).ok()?;
+        let name = CStr::from_bytes_until_nul(data.get(name_start..)?).ok()?;
+        symbols.push((value, len, name.to_string_lossy().into_owned()));
+    }
+    Some(symbols)
+}
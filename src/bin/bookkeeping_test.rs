@@ -0,0 +1,66 @@
+#![feature(allocator_api)]
+
+use std::{
+    alloc::{Allocator, GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use debug_allocator::alloc::DebugAlloc;
+
+/// グローバルアロケータを通った確保の回数を数える
+struct Counting;
+
+static GLOBAL_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        GLOBAL_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        GLOBAL_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// 記録のための内部のデータ構造がグローバルアロケータを通らないことを確かめる
+fn main() {
+    let allocator = DebugAlloc::new(System);
+    let layout = Layout::from_size_align(24, 8).unwrap();
+    // スレッド情報などの初回だけの初期化を済ませておく
+    let ptr = allocator.allocate(layout).unwrap();
+    unsafe { allocator.deallocate(ptr.cast(), layout) };
+
+    let before = GLOBAL_ALLOCATIONS.load(Ordering::Relaxed);
+    let mut v = Vec::new_in(&allocator);
+    for i in 0..10000u32 {
+        v.push(i);
+        if i % 100 == 0 {
+            let ptr = allocator.allocate(layout).unwrap();
+            unsafe { allocator.deallocate(ptr.cast(), layout) };
+        }
+    }
+    drop(v);
+    let after = GLOBAL_ALLOCATIONS.load(Ordering::Relaxed);
+
+    assert_eq!(
+        before, after,
+        "bookkeeping allocated through the global allocator"
+    );
+    let history = allocator.history();
+    assert!(
+        history
+            .iter()
+            .all(|action| action.layout == layout || action.layout.align() == 4),
+        "history contains an action not made by the workload"
+    );
+    println!("ok: {} actions, no bookkeeping allocations", history.len());
+}
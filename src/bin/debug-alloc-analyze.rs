@@ -0,0 +1,117 @@
+//! 保存した履歴の要約を表示する
+//!
+//! ```text
+//! debug-alloc-analyze [--top N] [--fail-on-leak] <ファイル>...
+//! ```
+//!
+//! [`DebugAlloc::save_trace`]、[`DebugAlloc::attach_rotating_stream`]、
+//! [`DebugAlloc::write_rle_binary`]で書き出したファイルを読み込み、集計、よく現れるサイズ、
+//! 解放されなかった確保、grow/shrinkの連鎖を表示する。複数のファイルを渡すと順につなげる。
+//! `--fail-on-leak`を付けると、解放されなかった確保があれば終了コード1で終わる。
+
+use std::{alloc::System, cmp::Reverse, env, process};
+
+use debug_allocator::alloc::{DebugAlloc, Kind, Report, Trace};
+
+const USAGE: &str = "usage: debug-alloc-analyze [--top N] [--fail-on-leak] <trace>...";
+
+fn usage() -> ! {
+    eprintln!("{USAGE}");
+    process::exit(2);
+}
+
+fn main() {
+    let mut top = 10;
+    let mut fail_on_leak = false;
+    let mut paths = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--top" => {
+                top = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or_else(|| usage())
+            }
+            "--fail-on-leak" => fail_on_leak = true,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return;
+            }
+            _ => paths.push(arg),
+        }
+    }
+    if paths.is_empty() {
+        usage();
+    }
+    let mut actions = Vec::new();
+    for path in &paths {
+        match Trace::load_any(path) {
+            Ok(loaded) => actions.extend(loaded),
+            Err(e) => {
+                eprintln!("{path}: {e}");
+                process::exit(1);
+            }
+        }
+    }
+    let span = match (actions.first(), actions.last()) {
+        (Some(first), Some(last)) => last.timestamp.saturating_sub(first.timestamp),
+        _ => Default::default(),
+    };
+    let alloc = DebugAlloc::from_actions(System, actions);
+    alloc.set_human_sizes(true);
+
+    println!("== summary ==");
+    print!(
+        "{}",
+        Report {
+            elapsed: span,
+            ..alloc.report()
+        }
+    );
+
+    println!("\n== most frequent operations ==");
+    let profile = alloc.allocation_profile();
+    let mut signatures = profile.iter().collect::<Vec<_>>();
+    signatures.sort_by_key(|&(signature, count)| (Reverse(count), signature.to_string()));
+    for (signature, count) in signatures.iter().take(top) {
+        println!("{count:>10}  {signature}");
+    }
+
+    println!("\n== most allocated sizes (bytes) ==");
+    let mut sizes = signatures
+        .iter()
+        .filter(|(signature, _)| matches!(signature.kind, Kind::Allocate | Kind::AllocateZeroed))
+        .map(|(signature, count)| (signature.layout.size() as u64 * *count as u64, *signature))
+        .collect::<Vec<_>>();
+    sizes.sort_by_key(|&(bytes, _)| Reverse(bytes));
+    for (bytes, signature) in sizes.iter().take(top) {
+        println!("{bytes:>10}  {signature}");
+    }
+
+    println!("\n== leak candidates ==");
+    let mut live = alloc.live_allocations();
+    live.sort_by_key(|action| Reverse(action.layout.size()));
+    for action in live.iter().take(top) {
+        println!("{action:#}");
+    }
+    println!(
+        "{} allocations, {} bytes not freed",
+        live.len(),
+        alloc.leaked_bytes()
+    );
+
+    println!("\n== growth chains ==");
+    let grown = alloc.ideal_initial_sizes();
+    println!("{} blocks were grown", grown.len());
+    let mut unbounded = alloc.unbounded_growth_candidates();
+    unbounded.sort_by_key(|&(_, _, grows)| Reverse(grows));
+    println!("{} blocks only grew and are still live:", unbounded.len());
+    for (seq, size, grows) in unbounded.iter().take(top) {
+        println!("  #{seq} {size} bytes after {grows} grows");
+    }
+
+    if fail_on_leak && !live.is_empty() {
+        process::exit(1);
+    }
+}
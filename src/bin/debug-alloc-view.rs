@@ -0,0 +1,221 @@
+//! 保存した履歴を対話的に眺めるビューア
+//!
+//! ```text
+//! debug-alloc-view <ファイル>...
+//! ```
+//!
+//! [`DebugAlloc::save_trace`]、[`DebugAlloc::attach_rotating_stream`]、
+//! [`DebugAlloc::write_rle_binary`]で書き出したファイルを読み込む。複数のファイルを渡すと
+//! 順につなげる。起動したら`h`でコマンドの一覧を出す。
+
+use std::{
+    alloc::System,
+    env,
+    io::{self, BufRead, Write},
+    process,
+};
+
+use debug_allocator::alloc::{Action, DebugAlloc, HistoryFilter, Trace};
+
+const HELP: &str = "\
+commands:
+  <Enter>, n     next page
+  p              previous page
+  g <seq>        go to the action with the sequence number
+  /<text>        search forward for text
+  ?<text>        search backward for text
+  k <kind>       show only actions of the kind (no argument: all kinds)
+  m <bytes>      show only actions of at least the size (no argument: any size)
+  l              list live allocations at the end of the trace
+  s              live-bytes sparkline
+  r              summary
+  h              this help
+  q              quit";
+
+/// 端末の大きさ(環境変数がなければ80x24)
+fn term_size() -> (usize, usize) {
+    let var = |name, default| {
+        env::var(name)
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(default)
+    };
+    (var("COLUMNS", 80), var("LINES", 24))
+}
+
+struct Viewer {
+    alloc: DebugAlloc<System>,
+    /// 絞り込んだ後の操作
+    shown: Vec<Action>,
+    kind: Option<String>,
+    min_size: usize,
+    /// 表示している先頭の`shown`の添字
+    top: usize,
+}
+
+impl Viewer {
+    fn refilter(&mut self) {
+        let mut filter = HistoryFilter::new().min_size(self.min_size);
+        if let Some(kind) = &self.kind {
+            filter = filter.kind(kind);
+        }
+        self.shown = self.alloc.filtered(&filter);
+        self.top = 0;
+    }
+
+    fn page_len(&self) -> usize {
+        term_size().1.saturating_sub(2).max(1)
+    }
+
+    fn show_page(&self, out: &mut impl Write) -> io::Result<()> {
+        let end = (self.top + self.page_len()).min(self.shown.len());
+        for action in &self.shown[self.top..end] {
+            writeln!(out, "{action:#}")?;
+        }
+        write!(
+            out,
+            "-- {}-{} of {}",
+            (self.top + 1).min(end),
+            end,
+            self.shown.len()
+        )?;
+        if let Some(kind) = &self.kind {
+            write!(out, " | kind: {kind}")?;
+        }
+        if self.min_size != 0 {
+            write!(out, " | size >= {}", self.min_size)?;
+        }
+        write!(out, " | h: help -- ")
+    }
+
+    fn search(&mut self, text: &str, forward: bool) -> bool {
+        let found = if forward {
+            (self.top + 1..self.shown.len()).find(|&i| self.matches(i, text))
+        } else {
+            (0..self.top).rev().find(|&i| self.matches(i, text))
+        };
+        if let Some(i) = found {
+            self.top = i;
+        }
+        found.is_some()
+    }
+
+    fn matches(&self, i: usize, text: &str) -> bool {
+        self.shown[i].to_string().contains(text)
+    }
+
+    /// コマンドを実行する。終了するなら`false`を返す
+    fn run(&mut self, line: &str, out: &mut impl Write) -> io::Result<bool> {
+        let (cmd, arg) = match line.char_indices().nth(1) {
+            Some((i, _)) if line.starts_with(['/', '?']) => line.split_at(i),
+            _ => line.split_once(' ').unwrap_or((line, "")),
+        };
+        let arg = arg.trim();
+        let page = self.page_len();
+        match cmd {
+            "" | "n" => {
+                if self.top + page < self.shown.len() {
+                    self.top += page;
+                }
+            }
+            "p" => self.top = self.top.saturating_sub(page),
+            "g" => match arg.parse::<u64>() {
+                Ok(seq) => self.top = self.shown.partition_point(|action| action.seq < seq),
+                Err(_) => writeln!(out, "usage: g <seq>")?,
+            },
+            "/" | "?" => {
+                if !self.search(arg, cmd == "/") {
+                    writeln!(out, "not found: {arg}")?;
+                }
+            }
+            "k" => {
+                self.kind = (!arg.is_empty()).then(|| arg.to_string());
+                self.refilter();
+            }
+            "m" => match arg.parse::<usize>() {
+                Ok(size) => {
+                    self.min_size = size;
+                    self.refilter();
+                }
+                Err(_) if arg.is_empty() => {
+                    self.min_size = 0;
+                    self.refilter();
+                }
+                Err(_) => writeln!(out, "usage: m <bytes>")?,
+            },
+            "l" => {
+                let live = self.alloc.live_allocations();
+                for action in &live {
+                    writeln!(out, "{action:#}")?;
+                }
+                writeln!(
+                    out,
+                    "{} live allocations, {} bytes",
+                    live.len(),
+                    self.alloc.leaked_bytes()
+                )?;
+                return Ok(true);
+            }
+            "s" => {
+                writeln!(out, "{}", self.alloc.sparkline(term_size().0))?;
+                return Ok(true);
+            }
+            "r" => {
+                write!(out, "{}", self.alloc.report())?;
+                return Ok(true);
+            }
+            "h" => {
+                writeln!(out, "{HELP}")?;
+                return Ok(true);
+            }
+            "q" => return Ok(false),
+            _ => writeln!(out, "unknown command: {line} (h: help)")?,
+        }
+        self.show_page(out)?;
+        Ok(true)
+    }
+}
+
+fn main() {
+    let paths = env::args().skip(1).collect::<Vec<_>>();
+    if paths.is_empty() {
+        eprintln!("usage: debug-alloc-view <trace>...");
+        process::exit(2);
+    }
+    let mut actions = Vec::new();
+    for path in &paths {
+        match Trace::load_any(path) {
+            Ok(loaded) => actions.extend(loaded),
+            Err(e) => {
+                eprintln!("{path}: {e}");
+                process::exit(1);
+            }
+        }
+    }
+    let alloc = DebugAlloc::from_actions(System, actions);
+    alloc.set_human_sizes(true);
+    let mut viewer = Viewer {
+        shown: alloc.snapshot(),
+        alloc,
+        kind: None,
+        min_size: 0,
+        top: 0,
+    };
+
+    let mut out = io::stdout().lock();
+    viewer
+        .show_page(&mut out)
+        .and_then(|()| out.flush())
+        .expect("failed printing to stdout");
+    for line in io::stdin().lock().lines() {
+        let line = line.expect("failed reading stdin");
+        let running = viewer
+            .run(line.trim_end(), &mut out)
+            .and_then(|running| out.flush().map(|()| running))
+            .expect("failed printing to stdout");
+        if !running {
+            break;
+        }
+    }
+    writeln!(out).expect("failed printing to stdout");
+}
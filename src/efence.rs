@@ -0,0 +1,102 @@
+use std::{
+    alloc::{AllocError, Allocator, Layout},
+    ffi::{c_int, c_long, c_void},
+    ptr::{self, NonNull},
+};
+
+const PROT_NONE: c_int = 0;
+const PROT_READ: c_int = 1;
+const PROT_WRITE: c_int = 2;
+const MAP_PRIVATE: c_int = 0x02;
+const MAP_ANONYMOUS: c_int = 0x20;
+const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+const _SC_PAGESIZE: c_int = 30;
+
+extern "C" {
+    fn mmap(
+        addr: *mut c_void,
+        len: usize,
+        prot: c_int,
+        flags: c_int,
+        fd: c_int,
+        offset: i64,
+    ) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    fn mprotect(addr: *mut c_void, len: usize, prot: c_int) -> c_int;
+    fn sysconf(name: c_int) -> c_long;
+}
+
+/// ページサイズ(`sysconf`が失敗したら4096)
+pub(crate) fn page_size() -> usize {
+    match unsafe { sysconf(_SC_PAGESIZE) } {
+        size if size > 0 => size as usize,
+        _ => 4096,
+    }
+}
+
+/// 確保ごとにページを割り当て、ブロックの末尾の直後にアクセスできないガードページを置く割り当て器
+///
+/// ブロックはガードページに接するように置くので、末尾を越えた読み書きはその場で
+/// SIGSEGVになる。解放したブロックはページごと返すので、解放後のアクセスも同様に落ちる。
+/// [`DebugAlloc`](crate::DebugAlloc)で包めば、落ちたアドレスを
+/// [`DebugAlloc::live_allocation_at_or_before`](crate::DebugAlloc::live_allocation_at_or_before)
+/// で調べて、どの確保がはみ出したかが分かる。
+///
+/// アラインメントを保つためにブロックの末尾とガードページの間にアラインメント未満の
+/// すき間ができることがあり、その範囲のはみ出しは検出できない。
+/// ページサイズより大きいアラインメントの確保は失敗する。確保ごとに少なくとも1ページを
+/// 使うので、テストやデバッグ用に使う。
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EFenceAlloc;
+
+impl EFenceAlloc {
+    /// ブロックに使うページのバイト数(ガードページを除く)
+    fn data_len(layout: Layout, page: usize) -> usize {
+        layout.size().next_multiple_of(page)
+    }
+}
+
+unsafe impl Allocator for EFenceAlloc {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let page = page_size();
+        if layout.align() > page {
+            return Err(AllocError);
+        }
+        let data_len = Self::data_len(layout, page);
+        let total = data_len.checked_add(page).ok_or(AllocError)?;
+        unsafe {
+            let base = mmap(
+                ptr::null_mut(),
+                total,
+                PROT_READ | PROT_WRITE,
+                MAP_PRIVATE | MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if base == MAP_FAILED {
+                return Err(AllocError);
+            }
+            let guard = base.cast::<u8>().add(data_len);
+            if mprotect(guard.cast(), page, PROT_NONE) != 0 {
+                munmap(base, total);
+                return Err(AllocError);
+            }
+            // ガードページの直前に、アラインメントを満たすように置く
+            let offset = (data_len - layout.size()) & !(layout.align() - 1);
+            let ptr = NonNull::new_unchecked(base.cast::<u8>().add(offset));
+            Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+        }
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        // 匿名マッピングは0で埋められている
+        self.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let page = page_size();
+        let data_len = Self::data_len(layout, page);
+        let base = (ptr.as_ptr() as usize) & !(page - 1);
+        munmap(base as *mut c_void, data_len + page);
+    }
+}
@@ -0,0 +1,153 @@
+use std::{
+    alloc::{Allocator, GlobalAlloc, Layout, System},
+    cell::Cell,
+    ptr::{self, NonNull},
+    sync::OnceLock,
+};
+
+use crate::alloc::DebugAlloc;
+
+thread_local! {
+    /// このスレッドで記録中、または履歴を参照中かどうか
+    static BUSY: Cell<bool> = const { Cell::new(false) };
+}
+
+/// 記録の処理の中で起きた確保を記録しないための印
+struct Busy;
+
+impl Busy {
+    /// 既に記録中なら`None`を返す
+    fn enter() -> Option<Self> {
+        // 印を作るのは確かめた後(作ってから捨てると`Drop`で解除されてしまう)
+        BUSY.try_with(|busy| {
+            if busy.get() {
+                return None;
+            }
+            busy.set(true);
+            Some(Busy)
+        })
+        .ok()
+        .flatten()
+    }
+}
+
+impl Drop for Busy {
+    fn drop(&mut self) {
+        let _ = BUSY.try_with(|busy| busy.set(false));
+    }
+}
+
+/// `#[global_allocator]`に設定して、プログラム全体の確保を記録する
+///
+/// 内部では`System`を包んだ[`DebugAlloc`]に記録する。記録の処理の中で起きた確保や、
+/// [`DebugGlobalAlloc::with`]の中での確保は記録せず、直接`System`に渡す。
+///
+/// ```ignore
+/// #[global_allocator]
+/// static GLOBAL: DebugGlobalAlloc = DebugGlobalAlloc::new();
+///
+/// GLOBAL.with(|alloc| alloc.dump_n(10));
+/// ```
+#[derive(Debug, Default)]
+pub struct DebugGlobalAlloc {
+    inner: OnceLock<DebugAlloc<System>>,
+}
+
+impl DebugGlobalAlloc {
+    pub const fn new() -> Self {
+        Self {
+            inner: OnceLock::new(),
+        }
+    }
+
+    fn inner(&self) -> &DebugAlloc<System> {
+        self.inner.get_or_init(|| DebugAlloc::new(System))
+    }
+
+    /// 記録せずに確保した`ptr`を、二重解放の判定から外す
+    fn untracked(&self, ptr: *mut u8) -> *mut u8 {
+        // 初期化の中の確保から呼ばれることがあるので、ここでは初期化しない
+        if let (Some(inner), Some(addr)) = (self.inner.get(), NonNull::new(ptr)) {
+            inner.forget_freed(addr.cast());
+        }
+        ptr
+    }
+
+    /// 記録した内容を参照する
+    ///
+    /// 履歴のロックを持ったまま確保すると記録の処理とデッドロックするので、
+    /// `f`の中でこのスレッドが行った確保は記録しない。
+    pub fn with<R>(&self, f: impl FnOnce(&DebugAlloc<System>) -> R) -> R {
+        let _busy = Busy::enter();
+        f(self.inner())
+    }
+}
+
+fn into_raw(result: Result<NonNull<[u8]>, std::alloc::AllocError>) -> *mut u8 {
+    result.map_or(ptr::null_mut(), |ptr| ptr.cast().as_ptr())
+}
+
+unsafe impl GlobalAlloc for DebugGlobalAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match Busy::enter() {
+            Some(_busy) => into_raw(self.inner().allocate(layout)),
+            None => self.untracked(System.alloc(layout)),
+        }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        match Busy::enter() {
+            Some(_busy) => into_raw(self.inner().allocate_zeroed(layout)),
+            None => self.untracked(System.alloc_zeroed(layout)),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        match Busy::enter() {
+            Some(_busy) => self.inner().deallocate(NonNull::new_unchecked(ptr), layout),
+            None => System.dealloc(ptr, layout),
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let Some(_busy) = Busy::enter() else {
+            return self.untracked(System.realloc(ptr, layout, new_size));
+        };
+        let ptr = NonNull::new_unchecked(ptr);
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        into_raw(if new_size >= layout.size() {
+            self.inner().grow(ptr, layout, new_layout)
+        } else {
+            self.inner().shrink(ptr, layout, new_layout)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocate_inside_with_while_holding_history() {
+        static GLOBAL: DebugGlobalAlloc = DebugGlobalAlloc::new();
+        let layout = Layout::from_size_align(240, 8).unwrap();
+        unsafe {
+            let ptr = GLOBAL.alloc(layout);
+            GLOBAL.dealloc(ptr, layout);
+        }
+        let recorded = GLOBAL.with(|alloc| {
+            let history = alloc.history();
+            // 2回目以降も記録の処理を通らなければ、履歴のロックで止まらない
+            for _ in 0..2 {
+                unsafe {
+                    let ptr = GLOBAL.alloc(layout);
+                    assert!(!ptr.is_null());
+                    GLOBAL.dealloc(ptr, layout);
+                }
+            }
+            history.len()
+        });
+        assert_eq!(recorded, 2);
+        assert_eq!(GLOBAL.with(|alloc| alloc.history().len()), 2);
+    }
+}
@@ -1,3 +1,14 @@
-#![feature(allocator_api)]
+#![feature(allocator_api, btreemap_alloc, thread_id_value)]
+#![cfg_attr(feature = "async", feature(async_iterator))]
 pub mod alloc;
+#[cfg(all(feature = "linux", target_os = "linux"))]
+pub mod efence;
+pub mod global;
+pub mod replay;
+pub mod testing;
 pub use alloc::*;
+#[cfg(all(feature = "linux", target_os = "linux"))]
+pub use efence::EFenceAlloc;
+pub use global::*;
+pub use replay::*;
+pub use testing::*;
@@ -5,7 +5,17 @@ use debug_allocator::alloc::{Action, Kind};
 
 fn main() {
     let mut action = Action {
+        seq: 0,
         addr: None,
+        old_addr: None,
+        len: 0,
+        denied: None,
+        timestamp: Default::default(),
+        epoch: 0,
+        thread_id: 0,
+        thread_name: None,
+        #[cfg(feature = "backtrace")]
+        backtrace: None,
         layout: Layout::from_size_align(0, 1).unwrap(),
         kind: Kind::Allocate,
     };
@@ -19,5 +29,6 @@ fn main() {
         Global.grow(ptr.cast(), layout, action.layout).unwrap_or_else(|_| handle_alloc_error(action.layout))
     };
     action.addr = Some(ptr.cast());
+    action.len = ptr.len();
     println!("{action}");
 }
@@ -0,0 +1,277 @@
+use std::{
+    alloc::{Allocator, Layout},
+    collections::HashMap,
+    ptr::{self, NonNull},
+};
+
+use crate::alloc::{Action, Kind};
+
+/// 生成するサイズの上限
+const MAX_SIZE: usize = 1 << 16;
+
+/// ファザーの入力から、確保の操作の列を決定的に作る
+///
+/// 操作は生存中のブロックのスタックに対して行うものとして解釈する。
+/// `Deallocate`、`Grow`、`GrowZeroed`、`Shrink`は一番最後に確保されたブロックを対象にし、
+/// 渡すレイアウトはそのブロックの今のレイアウトと必ず一致する。
+/// [`replay`]でそのまま実行できる。
+pub fn arbitrary_actions(data: &[u8]) -> Vec<(Kind, Layout)> {
+    let mut ops = Vec::new();
+    let mut stack = Vec::<Layout>::new();
+    for chunk in data.chunks_exact(4) {
+        let size = u16::from_le_bytes([chunk[1], chunk[2]]) as usize % MAX_SIZE;
+        let align = 1 << (chunk[3] % 7);
+        let top = stack.last().copied();
+        let op = match top {
+            Some(top) => match chunk[0] % 6 {
+                0 => (
+                    Kind::Allocate,
+                    Layout::from_size_align(size, align).unwrap(),
+                ),
+                1 => (
+                    Kind::AllocateZeroed,
+                    Layout::from_size_align(size, align).unwrap(),
+                ),
+                2 => (Kind::Deallocate, top),
+                3 => (
+                    Kind::Grow(top),
+                    Layout::from_size_align(top.size() + size, top.align()).unwrap(),
+                ),
+                4 => (
+                    Kind::GrowZeroed(top),
+                    Layout::from_size_align(top.size() + size, top.align()).unwrap(),
+                ),
+                _ => (
+                    Kind::Shrink(top),
+                    Layout::from_size_align(top.size() - size % (top.size() + 1), top.align())
+                        .unwrap(),
+                ),
+            },
+            None if chunk[0] % 2 == 0 => (
+                Kind::Allocate,
+                Layout::from_size_align(size, align).unwrap(),
+            ),
+            None => (
+                Kind::AllocateZeroed,
+                Layout::from_size_align(size, align).unwrap(),
+            ),
+        };
+        match op.0 {
+            Kind::Allocate | Kind::AllocateZeroed => stack.push(op.1),
+            Kind::Deallocate => {
+                stack.pop();
+            }
+            Kind::Grow(_) | Kind::GrowZeroed(_) | Kind::Shrink(_) => {
+                *stack.last_mut().unwrap() = op.1;
+            }
+        }
+        ops.push(op);
+    }
+    ops
+}
+
+struct Block {
+    ptr: NonNull<u8>,
+    layout: Layout,
+    fill: u8,
+}
+
+impl Block {
+    /// 先頭`len`バイトが`fill`のままであることを確かめる
+    fn verify(&self, len: usize, what: &str) {
+        let data = unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), len) };
+        if let Some(i) = data.iter().position(|&b| b != self.fill) {
+            panic!(
+                "{what}: byte {i} of block {:p} was {:#04x}, expected {:#04x}",
+                self.ptr, data[i], self.fill
+            );
+        }
+    }
+}
+
+fn verify_zeroed(ptr: NonNull<u8>, range: std::ops::Range<usize>, what: &str) {
+    let data = unsafe { std::slice::from_raw_parts(ptr.as_ptr().add(range.start), range.len()) };
+    if let Some(i) = data.iter().position(|&b| b != 0) {
+        panic!(
+            "{what}: byte {} of block {:p} was not zeroed",
+            range.start + i,
+            ptr
+        );
+    }
+}
+
+/// [`arbitrary_actions`]が作った操作の列を`alloc`で実行し、失敗した操作の数を返す
+///
+/// 確保したブロックは決まった値で埋めておき、grow/shrinkで中身が保たれているか、
+/// `*_zeroed`が0で埋めているかを確かめ、壊れていればpanicする。
+/// 失敗した操作は飛ばし、最後に残ったブロックはすべて解放する。
+pub fn replay<A: Allocator>(alloc: &A, ops: &[(Kind, Layout)]) -> usize {
+    let mut stack = Vec::<Block>::new();
+    let mut failures = 0;
+    for (i, &(kind, layout)) in ops.iter().enumerate() {
+        let fill = (i % 255) as u8 + 1;
+        match kind {
+            Kind::Allocate | Kind::AllocateZeroed => {
+                let result = if kind == Kind::Allocate {
+                    alloc.allocate(layout)
+                } else {
+                    alloc.allocate_zeroed(layout)
+                };
+                let Ok(ptr) = result else {
+                    failures += 1;
+                    // 対象のブロックがずれないように、失敗した確保も空のブロックとして積む
+                    stack.push(Block {
+                        ptr: NonNull::dangling(),
+                        layout: Layout::new::<()>(),
+                        fill: 0,
+                    });
+                    continue;
+                };
+                let ptr = ptr.cast::<u8>();
+                if kind == Kind::AllocateZeroed {
+                    verify_zeroed(ptr, 0..layout.size(), "allocate_zeroed");
+                }
+                unsafe { ptr::write_bytes(ptr.as_ptr(), fill, layout.size()) };
+                stack.push(Block { ptr, layout, fill });
+            }
+            Kind::Deallocate => {
+                if let Some(block) = stack.pop() {
+                    block.verify(block.layout.size(), "deallocate");
+                    if block.fill != 0 {
+                        unsafe { alloc.deallocate(block.ptr, block.layout) };
+                    }
+                }
+            }
+            Kind::Grow(_) | Kind::GrowZeroed(_) | Kind::Shrink(_) => {
+                let Some(block) = stack.last_mut() else {
+                    continue;
+                };
+                if block.fill == 0 {
+                    // 確保に失敗したブロック
+                    continue;
+                }
+                let old_size = block.layout.size();
+                let result = unsafe {
+                    match kind {
+                        Kind::Grow(_) => alloc.grow(block.ptr, block.layout, layout),
+                        Kind::GrowZeroed(_) => alloc.grow_zeroed(block.ptr, block.layout, layout),
+                        _ => alloc.shrink(block.ptr, block.layout, layout),
+                    }
+                };
+                let Ok(ptr) = result else {
+                    failures += 1;
+                    continue;
+                };
+                block.ptr = ptr.cast();
+                block.layout = layout;
+                let kept = old_size.min(layout.size());
+                block.verify(kept, kind.name());
+                if let Kind::GrowZeroed(_) = kind {
+                    verify_zeroed(block.ptr, old_size..layout.size(), "grow_zeroed");
+                }
+                unsafe {
+                    ptr::write_bytes(
+                        block.ptr.as_ptr().add(kept),
+                        block.fill,
+                        layout.size() - kept,
+                    )
+                };
+            }
+        }
+    }
+    for block in stack.into_iter().rev() {
+        if block.fill != 0 {
+            block.verify(block.layout.size(), "deallocate");
+            unsafe { alloc.deallocate(block.ptr, block.layout) };
+        }
+    }
+    failures
+}
+
+/// [`replay_history`]の結果
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ReplaySummary {
+    /// 実行した操作の数
+    pub replayed: usize,
+    /// 実行して失敗した操作の数
+    pub failures: usize,
+    /// 対象のブロックが見つからずに飛ばした解放やgrow/shrinkの数
+    pub unmatched: usize,
+    /// 記録の中で最後まで解放されず、終わりにまとめて解放したブロックの数
+    pub leaked: usize,
+}
+
+/// 記録した履歴の操作を同じ順に`alloc`で実行し直す
+///
+/// 記録されたアドレスは、実行し直して`alloc`が返したブロックに対応させる。
+/// 記録の時点で失敗していた操作は実行しない。履歴の先頭が削除されていて、解放や
+/// grow/shrinkの対象が履歴の中で確保されていなければ飛ばす。実行して失敗した確保の
+/// ブロックは以降の操作でも対象がないものとして扱う。最後まで解放されなかったブロックは、
+/// 終わりにすべて解放する。
+///
+/// 保存したトレースを読み込んで、自作の割り当て器の再現可能な負荷試験やベンチマークに使える。
+pub fn replay_history<'a, A: Allocator>(
+    alloc: &A,
+    history: impl IntoIterator<Item = &'a Action>,
+) -> ReplaySummary {
+    let mut summary = ReplaySummary::default();
+    // 記録されたアドレス → 実行し直したブロック
+    let mut blocks = HashMap::<usize, (NonNull<u8>, Layout)>::new();
+    for action in history {
+        let Some(addr) = action.addr else {
+            continue;
+        };
+        let addr = addr.as_ptr() as usize;
+        let layout = action.layout;
+        let result = match action.kind {
+            Kind::Allocate => alloc.allocate(layout),
+            Kind::AllocateZeroed => alloc.allocate_zeroed(layout),
+            Kind::Deallocate => {
+                match blocks.remove(&addr) {
+                    Some((ptr, layout)) => {
+                        unsafe { alloc.deallocate(ptr, layout) };
+                        summary.replayed += 1;
+                    }
+                    None => summary.unmatched += 1,
+                }
+                continue;
+            }
+            Kind::Grow(_) | Kind::GrowZeroed(_) | Kind::Shrink(_) => {
+                let old_addr = action.old_addr.map(|ptr| ptr.as_ptr() as usize);
+                let Some((ptr, old_layout)) =
+                    old_addr.and_then(|old_addr| blocks.remove(&old_addr))
+                else {
+                    summary.unmatched += 1;
+                    continue;
+                };
+                let result = unsafe {
+                    match action.kind {
+                        Kind::Grow(_) => alloc.grow(ptr, old_layout, layout),
+                        Kind::GrowZeroed(_) => alloc.grow_zeroed(ptr, old_layout, layout),
+                        _ => alloc.shrink(ptr, old_layout, layout),
+                    }
+                };
+                if result.is_err() {
+                    // 失敗しても元のブロックは有効なまま
+                    blocks.insert(old_addr.unwrap(), (ptr, old_layout));
+                }
+                result
+            }
+        };
+        summary.replayed += 1;
+        match result {
+            Ok(ptr) => {
+                if let Some((ptr, layout)) = blocks.insert(addr, (ptr.cast(), layout)) {
+                    // 記録の中で同じアドレスが解放されずに再び返された(サイズ0の確保など)
+                    unsafe { alloc.deallocate(ptr, layout) };
+                }
+            }
+            Err(_) => summary.failures += 1,
+        }
+    }
+    summary.leaked = blocks.len();
+    for (ptr, layout) in blocks.into_values() {
+        unsafe { alloc.deallocate(ptr, layout) };
+    }
+    summary
+}
@@ -0,0 +1,11 @@
+//! `Allocator`に対して汎用なコードのテストに使う割り当て器
+
+mod deterministic;
+mod mock;
+mod never;
+mod shaker;
+
+pub use deterministic::*;
+pub use mock::*;
+pub use never::*;
+pub use shaker::*;
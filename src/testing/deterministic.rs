@@ -0,0 +1,171 @@
+use std::{
+    alloc::{AllocError, Allocator, Layout, System},
+    ptr::NonNull,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::alloc::Action;
+
+/// [`DeterministicAlloc::new`]で確保する領域のバイト数
+const DEFAULT_CAPACITY: usize = 1 << 20;
+/// [`DeterministicAlloc::relocate`]で置き換える先の最初のアドレスの初期値
+const DEFAULT_BASE: usize = 0x1000_0000;
+/// 領域のアラインメント(これより大きいアラインメントの確保は失敗する)
+const ARENA_ALIGN: usize = 4096;
+
+/// 実行や環境によらず、領域の先頭から毎回同じ位置にブロックを置く割り当て器
+///
+/// 作るときに`System`から一つの領域を確保し、その先頭から順にアラインメントを合わせて
+/// ブロックを切り出す。領域の先頭からの位置は毎回同じになるが、領域自体のアドレスは実行ごとに
+/// 変わるので、履歴の出力を比べるときは[`DeterministicAlloc::relocate`]で固定の仮想の
+/// アドレスに置き換える。grow/grow_zeroedは常に新しい位置に移し、shrinkは同じ位置のまま
+/// 返す。解放した位置は[`DeterministicAlloc::reset`]するまで再利用しない。
+/// 領域が足りなくなるか、4096バイトより大きいアラインメントを求められると失敗する。
+///
+/// [`DebugAlloc`](crate::DebugAlloc)で包んで使うか、
+/// [`replay_history`](crate::replay_history)で記録を実行し直すと、置き換えたアドレスを
+/// 含めて毎回同じ履歴になるので、履歴の出力のスナップショットテストに使える。
+/// 複製すると領域を共有する。
+#[derive(Clone, Debug)]
+pub struct DeterministicAlloc {
+    arena: Arc<Arena>,
+    base: usize,
+}
+
+#[derive(Debug)]
+struct Arena {
+    ptr: NonNull<u8>,
+    capacity: usize,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// 次に切り出す位置
+    next: usize,
+    /// 解放されていないブロックの数
+    live: usize,
+}
+
+// `ptr`の領域は`Arena`が持っていて、切り出しは`state`のロックの中で行う
+unsafe impl Send for Arena {}
+unsafe impl Sync for Arena {}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        let layout = Layout::from_size_align(self.capacity, ARENA_ALIGN).unwrap();
+        unsafe { System.deallocate(self.ptr, layout) };
+    }
+}
+
+impl Default for DeterministicAlloc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DeterministicAlloc {
+    /// 1MiBの領域から切り出す
+    pub fn new() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+
+    /// `capacity`バイトの領域から切り出す
+    ///
+    /// 領域を確保できなければパニックする。
+    pub fn with_capacity(capacity: usize) -> Self {
+        let layout = Layout::from_size_align(capacity.max(1), ARENA_ALIGN)
+            .expect("arena capacity is too large");
+        let ptr = System
+            .allocate(layout)
+            .expect("failed allocating the arena")
+            .cast();
+        Self {
+            arena: Arc::new(Arena {
+                ptr,
+                capacity: layout.size(),
+                state: Mutex::new(State::default()),
+            }),
+            base: DEFAULT_BASE,
+        }
+    }
+
+    /// [`DeterministicAlloc::relocate`]で置き換える先の最初のアドレスを`base`にする
+    ///
+    /// 初期値は`0x1000_0000`。アラインメントを保つように4096の倍数に切り上げる。
+    pub fn base(mut self, base: usize) -> Self {
+        self.base = base.max(1).next_multiple_of(ARENA_ALIGN);
+        self
+    }
+
+    /// 領域の先頭から切り出し直す
+    ///
+    /// 解放されていないブロックがあればパニックする。
+    pub fn reset(&self) {
+        let mut state = self.state();
+        assert_eq!(state.live, 0, "reset with blocks still allocated");
+        state.next = 0;
+    }
+
+    /// `ptr`の領域の先頭からのバイト数(領域の外なら`None`)
+    pub fn offset(&self, ptr: NonNull<()>) -> Option<usize> {
+        let offset = (ptr.as_ptr() as usize).checked_sub(self.arena.ptr.as_ptr() as usize)?;
+        (offset <= self.arena.capacity).then_some(offset)
+    }
+
+    /// `action`のアドレスを、領域の先頭を最初のアドレスに置いた仮想のアドレスに置き換える
+    ///
+    /// この割り当て器の領域の外のアドレスはそのまま残す。
+    pub fn relocate(&self, action: &Action) -> Action {
+        let relocate = |addr: Option<NonNull<()>>| {
+            addr.map(|addr| match self.offset(addr) {
+                Some(offset) => NonNull::new((self.base + offset) as *mut ()).unwrap(),
+                None => addr,
+            })
+        };
+        Action {
+            addr: relocate(action.addr),
+            old_addr: relocate(action.old_addr),
+            ..action.clone()
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.arena.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+unsafe impl Allocator for DeterministicAlloc {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if layout.align() > ARENA_ALIGN {
+            return Err(AllocError);
+        }
+        let mut state = self.state();
+        let offset = state
+            .next
+            .checked_next_multiple_of(layout.align())
+            .ok_or(AllocError)?;
+        let end = offset.checked_add(layout.size()).ok_or(AllocError)?;
+        if end > self.arena.capacity {
+            return Err(AllocError);
+        }
+        state.next = end;
+        state.live += 1;
+        let ptr = unsafe { self.arena.ptr.add(offset) };
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, _ptr: NonNull<u8>, _layout: Layout) {
+        let mut state = self.state();
+        state.live = state.live.saturating_sub(1);
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        _old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        Ok(NonNull::slice_from_raw_parts(ptr, new_layout.size()))
+    }
+}
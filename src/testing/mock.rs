@@ -0,0 +1,226 @@
+use std::{
+    alloc::{AllocError, Allocator, Layout, System},
+    collections::{HashMap, VecDeque},
+    ptr::{self, NonNull},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::alloc::Kind;
+
+/// [`MockAlloc`]の台本の1つの結果
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MockResult {
+    /// `System`で確保して成功する。返すスライスは要求より`extra`バイト長い
+    Succeed { extra: usize },
+    /// `AllocError`を返す
+    Fail,
+    /// 渡したアドレスを、要求されたサイズのブロックとして返す
+    ///
+    /// メモリは呼び出し側が用意する。grow/shrinkでは元の中身をここに移す。
+    /// このブロックを解放しても何もしない。
+    At(NonNull<u8>),
+}
+
+unsafe impl Send for MockResult {}
+unsafe impl Sync for MockResult {}
+
+/// [`MockAlloc`]が受けた呼び出し
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MockCall {
+    pub kind: Kind,
+    /// 要求されたレイアウト(grow/shrinkでは新しいレイアウト)
+    pub layout: Layout,
+    /// 使った台本の位置(0から)
+    ///
+    /// 台本が尽きていた呼び出しと、台本を使わない解放では`None`。
+    pub step: Option<usize>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    script: VecDeque<MockResult>,
+    next_step: usize,
+    calls: Vec<MockCall>,
+    /// 台本が尽きた後の呼び出しの数
+    unexpected: usize,
+    /// `System`で確保したブロック(アドレス → 実際のレイアウト)
+    owned: HashMap<usize, Layout>,
+}
+
+/// 確保の結果を台本のとおりに返す割り当て器
+///
+/// allocate/allocate_zeroed/grow/grow_zeroed/shrinkの呼び出しごとに台本の先頭の
+/// [`MockResult`]を1つ使う。台本が尽きた後の呼び出しは失敗させ、
+/// [`MockAlloc::assert_done`]で報告する。解放は台本を使わない。
+/// 複製すると台本と記録を共有するので、複製をコンテナに渡して元の値で結果を調べられる。
+/// [`DebugAlloc`](crate::DebugAlloc)で包めば履歴も取れる。
+#[derive(Clone, Debug, Default)]
+pub struct MockAlloc {
+    state: Arc<Mutex<State>>,
+}
+
+impl MockAlloc {
+    pub fn new(script: impl IntoIterator<Item = MockResult>) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                script: script.into_iter().collect(),
+                ..Default::default()
+            })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// 台本の最後に結果を加える
+    pub fn push(&self, result: MockResult) {
+        self.lock().script.push_back(result);
+    }
+
+    /// 受けた呼び出し(古い順)
+    pub fn calls(&self) -> Vec<MockCall> {
+        self.lock().calls.clone()
+    }
+
+    /// まだ使われていない台本の結果の数
+    pub fn remaining(&self) -> usize {
+        self.lock().script.len()
+    }
+
+    /// 台本が尽きた後に受けた呼び出しの数
+    pub fn unexpected(&self) -> usize {
+        self.lock().unexpected
+    }
+
+    /// 台本をすべて使い切り、台本が尽きた後の呼び出しもなかったことを確かめる
+    ///
+    /// # Panics
+    ///
+    /// 使われていない結果が残っているか、台本が尽きた後に呼び出しがあればpanicする。
+    #[track_caller]
+    pub fn assert_done(&self) {
+        let state = self.lock();
+        assert!(
+            state.script.is_empty(),
+            "MockAlloc: {} scripted results were not used: {:?}",
+            state.script.len(),
+            state.script
+        );
+        assert!(
+            state.unexpected == 0,
+            "MockAlloc: {} calls were made after the script ran out",
+            state.unexpected
+        );
+    }
+
+    /// 台本の次の結果で`layout`のブロックを用意する
+    fn next(&self, kind: Kind, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let mut state = self.lock();
+        let result = state.script.pop_front();
+        let step = result.map(|_| state.next_step);
+        state.calls.push(MockCall { kind, layout, step });
+        let Some(result) = result else {
+            state.unexpected += 1;
+            return Err(AllocError);
+        };
+        state.next_step += 1;
+        match result {
+            MockResult::Succeed { extra } => {
+                let outer = layout
+                    .size()
+                    .checked_add(extra)
+                    .and_then(|size| Layout::from_size_align(size, layout.align()).ok())
+                    .ok_or(AllocError)?;
+                let ptr = System.allocate(outer)?;
+                state
+                    .owned
+                    .insert(ptr.cast::<u8>().as_ptr() as usize, outer);
+                Ok(ptr)
+            }
+            MockResult::Fail => Err(AllocError),
+            MockResult::At(ptr) => Ok(NonNull::slice_from_raw_parts(ptr, layout.size())),
+        }
+    }
+
+    fn release(&self, ptr: NonNull<u8>) {
+        let owned = self.lock().owned.remove(&(ptr.as_ptr() as usize));
+        if let Some(layout) = owned {
+            unsafe { System.deallocate(ptr, layout) };
+        }
+    }
+
+    unsafe fn resize(
+        &self,
+        kind: Kind,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let new = self.next(kind, new_layout)?;
+        let new_ptr = new.cast::<u8>();
+        if new_ptr != ptr {
+            ptr::copy(
+                ptr.as_ptr(),
+                new_ptr.as_ptr(),
+                old_layout.size().min(new_layout.size()),
+            );
+            self.release(ptr);
+        }
+        if let Kind::GrowZeroed(_) = kind {
+            new_ptr
+                .as_ptr()
+                .add(old_layout.size())
+                .write_bytes(0, new.len() - old_layout.size());
+        }
+        Ok(new)
+    }
+}
+
+unsafe impl Allocator for MockAlloc {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.next(Kind::Allocate, layout)
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = self.next(Kind::AllocateZeroed, layout)?;
+        unsafe { ptr.cast::<u8>().as_ptr().write_bytes(0, ptr.len()) };
+        Ok(ptr)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.lock().calls.push(MockCall {
+            kind: Kind::Deallocate,
+            layout,
+            step: None,
+        });
+        self.release(ptr);
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(Kind::Grow(old_layout), ptr, old_layout, new_layout)
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(Kind::GrowZeroed(old_layout), ptr, old_layout, new_layout)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(Kind::Shrink(old_layout), ptr, old_layout, new_layout)
+    }
+}
@@ -0,0 +1,20 @@
+use std::{
+    alloc::{AllocError, Allocator, Layout},
+    ptr::NonNull,
+};
+
+/// すべての確保に失敗する割り当て器
+///
+/// ライブラリが`AllocError`をどの経路でも正しく返すかを確かめるのに使う。
+/// `DebugAlloc::new(NeverAlloc)`で包めば、どの操作が試みられたかが履歴に残る。
+/// 確保に成功することがないので、解放が呼ばれることもない。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct NeverAlloc;
+
+unsafe impl Allocator for NeverAlloc {
+    fn allocate(&self, _layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        Err(AllocError)
+    }
+
+    unsafe fn deallocate(&self, _ptr: NonNull<u8>, _layout: Layout) {}
+}
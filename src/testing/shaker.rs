@@ -0,0 +1,94 @@
+use std::{
+    alloc::{AllocError, Allocator, Layout},
+    ptr::{self, NonNull},
+};
+
+use crate::alloc::POISON_BYTE;
+
+/// grow/shrinkを必ず別のアドレスへの移動にする割り当て器
+///
+/// grow/grow_zeroed/shrinkのたびに内部の割り当て器で新しいブロックを確保し、中身を移してから
+/// 元のブロックを[`POISON_BYTE`]で埋めて解放する。新しいブロックは元のブロックを解放する前に
+/// 確保するので、アドレスは必ず変わる。再確保をまたいで古いポインタを使い続けるコードは、
+/// 埋めた値を読むか解放済みのメモリに触れるので見つけやすくなる。
+/// [`DebugAlloc`](crate::DebugAlloc)の内側に置けば、移動が履歴に残る。
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ShakerAlloc<A> {
+    alloc: A,
+}
+
+impl<A> ShakerAlloc<A> {
+    pub fn new(alloc: A) -> Self {
+        Self { alloc }
+    }
+
+    /// 内部の割り当て器
+    pub fn inner(&self) -> &A {
+        &self.alloc
+    }
+}
+
+impl<A: Allocator> ShakerAlloc<A> {
+    unsafe fn relocate(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        zeroed: bool,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let new = if zeroed {
+            self.alloc.allocate_zeroed(new_layout)?
+        } else {
+            self.alloc.allocate(new_layout)?
+        };
+        ptr::copy_nonoverlapping(
+            ptr.as_ptr(),
+            new.cast::<u8>().as_ptr(),
+            old_layout.size().min(new_layout.size()),
+        );
+        ptr.as_ptr().write_bytes(POISON_BYTE, old_layout.size());
+        self.alloc.deallocate(ptr, old_layout);
+        Ok(new)
+    }
+}
+
+unsafe impl<A: Allocator> Allocator for ShakerAlloc<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.alloc.allocate(layout)
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.alloc.allocate_zeroed(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.alloc.deallocate(ptr, layout)
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.relocate(ptr, old_layout, new_layout, false)
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.relocate(ptr, old_layout, new_layout, true)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.relocate(ptr, old_layout, new_layout, false)
+    }
+}